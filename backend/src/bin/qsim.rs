@@ -0,0 +1,345 @@
+// Simulates a circuit from a file without starting the server, e.g. to check many submissions from a shell script:
+//
+// qsim bell.qasm --show probabilities --format csv
+//
+// The exit code is 0 when the circuit was simulated, 1 when it is invalid and 2 when the arguments are wrong.
+
+use backend::simulation::circuit_validator::QuantumCircuitError;
+use backend::simulation::custom_gates::{define_custom_gates, CustomGateDefinitions};
+use backend::simulation::ket::format_ket;
+use backend::simulation::qasm_importer::import_qasm;
+use backend::simulation::quirk::import_quirk;
+use backend::simulation::simulator::simulate_state_after_step_with_backend;
+use backend::simulation::subcircuits::{define_subcircuits, SubCircuitDefinitions};
+use backend::{simulate_with_backend, BackendKind, BitOrder, QuantumState, UnparsedCircuit};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::process::ExitCode;
+
+const USAGE: &str = "\
+Usage: qsim <file> [options]
+
+Reads a circuit as grid JSON ({\"circuit_matrix\": [...], \"custom_gates\": {...}, \"subcircuits\": {...}} or just the matrix), OpenQASM 2.0 or Quirk JSON ({\"cols\": [...]}).
+
+Options:
+  --input grid|qasm|quirk                    The format of the file, guessed from its extension and content by default
+  --show final|steps|probabilities|counts    What to print, the final state by default
+  --format text|json|csv                     How to print it, text by default
+  --shots N                                  The number of samples for --show counts, 1024 by default
+  --seed N                                   Seed the sampling for reproducible counts
+  --bit-order little_endian|big_endian       Which end of a basis state qubit 0 is, little_endian by default
+  --backend grouped|state_vector|sparse|auto The simulation engine, grouped by default";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InputFormat {
+    Grid,
+    Qasm,
+    Quirk,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Show {
+    Final,
+    Steps,
+    Probabilities,
+    Counts,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Text,
+    Json,
+    Csv,
+}
+
+#[derive(Debug, PartialEq)]
+struct Options {
+    path: String,
+    input: Option<InputFormat>,
+    show: Show,
+    format: OutputFormat,
+    shots: usize,
+    seed: Option<u64>,
+    bit_order: BitOrder,
+    backend: BackendKind,
+}
+
+fn main() -> ExitCode {
+    let arguments: Vec<String> = std::env::args().skip(1).collect();
+    if arguments.iter().any(|argument| argument == "--help" || argument == "-h") {
+        println!("{}", USAGE);
+        return ExitCode::SUCCESS;
+    }
+    let options = match parse_arguments(&arguments) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}\n\n{}", message, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+    let contents = match std::fs::read_to_string(&options.path) {
+        Ok(contents) => contents,
+        Err(error) => {
+            eprintln!("Could not read {}: {}", options.path, error);
+            return ExitCode::from(2);
+        }
+    };
+
+    match run(&options, &contents) {
+        Ok(output) => {
+            print!("{}", output);
+            ExitCode::SUCCESS
+        }
+        Err(messages) => {
+            for message in messages {
+                eprintln!("{}: {}", options.path, message);
+            }
+            ExitCode::from(1)
+        }
+    }
+}
+
+fn parse_arguments(arguments: &[String]) -> Result<Options, String> {
+    let mut path = None;
+    let mut options = Options {
+        path: String::new(),
+        input: None,
+        show: Show::Final,
+        format: OutputFormat::Text,
+        shots: 1024,
+        seed: None,
+        bit_order: BitOrder::default(),
+        backend: BackendKind::default(),
+    };
+
+    let mut arguments = arguments.iter();
+    while let Some(argument) = arguments.next() {
+        if !argument.starts_with("--") {
+            if path.replace(argument.clone()).is_some() {
+                return Err(format!("Unexpected argument '{}', only one file can be simulated at a time", argument));
+            }
+            continue;
+        }
+        let value = arguments.next().ok_or_else(|| format!("{} needs a value", argument))?;
+        let invalid = || format!("'{}' is not a valid value for {}", value, argument);
+        match argument.as_str() {
+            "--input" => {
+                options.input = Some(match value.as_str() {
+                    "grid" => InputFormat::Grid,
+                    "qasm" => InputFormat::Qasm,
+                    "quirk" => InputFormat::Quirk,
+                    _ => return Err(invalid()),
+                })
+            }
+            "--show" => {
+                options.show = match value.as_str() {
+                    "final" => Show::Final,
+                    "steps" => Show::Steps,
+                    "probabilities" => Show::Probabilities,
+                    "counts" => Show::Counts,
+                    _ => return Err(invalid()),
+                }
+            }
+            "--format" => {
+                options.format = match value.as_str() {
+                    "text" => OutputFormat::Text,
+                    "json" => OutputFormat::Json,
+                    "csv" => OutputFormat::Csv,
+                    _ => return Err(invalid()),
+                }
+            }
+            "--shots" => options.shots = value.parse().map_err(|_| invalid())?,
+            "--seed" => options.seed = Some(value.parse().map_err(|_| invalid())?),
+            "--bit-order" => options.bit_order = from_name(value).ok_or_else(invalid)?,
+            "--backend" => options.backend = from_name(value).ok_or_else(invalid)?,
+            _ => return Err(format!("Unknown option {}", argument)),
+        }
+    }
+
+    options.path = path.ok_or("No circuit file given")?;
+    Ok(options)
+}
+
+// Options that are also request keys of the server are read by the same names
+fn from_name<T: DeserializeOwned>(name: &str) -> Option<T> {
+    serde_json::from_value(Value::String(name.to_string())).ok()
+}
+
+fn run(options: &Options, contents: &str) -> Result<String, Vec<String>> {
+    let grid = read_circuit(options, contents)?;
+    let no_of_qubits = grid.circuit.len();
+    let messages = |errors: Vec<QuantumCircuitError>| errors.iter().map(|error| error.message()).collect::<Vec<String>>();
+    // Only the steps need the states in between, the rest are faster without them
+    let states = match options.show {
+        Show::Steps => simulate_with_backend(grid, options.backend, options.bit_order).map_err(messages)?,
+        _ => {
+            let last_step = grid.circuit.first().map_or(0, |row| row.len().saturating_sub(1));
+            vec![simulate_state_after_step_with_backend(grid, options.backend, options.bit_order, last_step).map_err(messages)?]
+        }
+    };
+    let final_state = states.last().expect("there is always a state");
+
+    let label = |index: usize| format!("{:0width$b}", index, width = no_of_qubits);
+    Ok(match options.show {
+        Show::Final => format_states(&states[states.len() - 1..], None, options.format, &label),
+        Show::Steps => format_states(&states, Some(0), options.format, &label),
+        Show::Probabilities => {
+            let probabilities: Vec<(String, f64)> = final_state.probabilities().into_iter().enumerate().map(|(index, p)| (label(index), p)).collect();
+            match options.format {
+                OutputFormat::Text => probabilities.iter().map(|(basis, p)| format!("{}  {:.6}\n", basis, p)).collect(),
+                OutputFormat::Csv => {
+                    let rows: String = probabilities.iter().map(|(basis, p)| format!("{},{}\n", basis, p)).collect();
+                    format!("basis,probability\n{}", rows)
+                }
+                OutputFormat::Json => {
+                    let probabilities: serde_json::Map<String, Value> = probabilities.into_iter().map(|(basis, p)| (basis, json!(p))).collect();
+                    format!("{}\n", json!({ "bit_order": options.bit_order, "probabilities": probabilities }))
+                }
+            }
+        }
+        Show::Counts => {
+            let mut rng = match options.seed {
+                Some(seed) => StdRng::seed_from_u64(seed),
+                None => StdRng::from_entropy(),
+            };
+            let counts = final_state.sample(options.shots, &mut rng);
+            let counts: BTreeMap<String, usize> = counts.into_iter().map(|(index, count)| (label(index), count)).collect();
+            match options.format {
+                OutputFormat::Text => counts.iter().map(|(basis, count)| format!("{}  {}\n", basis, count)).collect(),
+                OutputFormat::Csv => {
+                    let rows: String = counts.iter().map(|(basis, count)| format!("{},{}\n", basis, count)).collect();
+                    format!("basis,count\n{}", rows)
+                }
+                OutputFormat::Json => format!("{}\n", json!({ "bit_order": options.bit_order, "shots": options.shots, "counts": counts })),
+            }
+        }
+    })
+}
+
+fn read_circuit(options: &Options, contents: &str) -> Result<UnparsedCircuit, Vec<String>> {
+    let input = options.input.unwrap_or_else(|| guess_input_format(&options.path, contents));
+    match input {
+        InputFormat::Qasm => import_qasm(contents)
+            .map(|import| import.grid)
+            .map_err(|errors| errors.iter().map(|error| error.message()).collect()),
+        InputFormat::Quirk => {
+            let value: Value = serde_json::from_str(contents).map_err(|error| vec![format!("Invalid JSON: {}", error)])?;
+            import_quirk(&value).map_err(|errors| errors.iter().map(|error| error.message()).collect())
+        }
+        InputFormat::Grid => {
+            let value: Value = serde_json::from_str(contents).map_err(|error| vec![format!("Invalid JSON: {}", error)])?;
+            let messages = |errors: Vec<QuantumCircuitError>| errors.iter().map(|error| error.message()).collect::<Vec<String>>();
+            let definitions: CustomGateDefinitions = match value.get("custom_gates") {
+                Some(definitions) => serde_json::from_value(definitions.clone())
+                    .map_err(|_| vec!["Expected custom_gates, an object of names and matrices".to_string()])?,
+                None => CustomGateDefinitions::new(),
+            };
+            let custom_gates = define_custom_gates(definitions).map_err(messages)?;
+            let definitions: SubCircuitDefinitions = match value.get("subcircuits") {
+                Some(definitions) => serde_json::from_value(definitions.clone())
+                    .map_err(|_| vec!["Expected subcircuits, an object of names and grids".to_string()])?,
+                None => SubCircuitDefinitions::new(),
+            };
+            let subcircuits = define_subcircuits(definitions, &custom_gates).map_err(messages)?;
+            let matrix = value.get("circuit_matrix").cloned().unwrap_or(value);
+            let circuit: Vec<Vec<String>> = serde_json::from_value(matrix)
+                .map_err(|_| vec!["Expected a circuit_matrix, a list of rows of gate tokens".to_string()])?;
+            Ok(UnparsedCircuit { circuit, custom_gates, subcircuits })
+        }
+    }
+}
+
+fn guess_input_format(path: &str, contents: &str) -> InputFormat {
+    if path.ends_with(".qasm") || contents.trim_start().starts_with("OPENQASM") {
+        InputFormat::Qasm
+    } else if serde_json::from_str::<Value>(contents).is_ok_and(|value| value.get("cols").is_some()) {
+        InputFormat::Quirk
+    } else {
+        InputFormat::Grid
+    }
+}
+
+// States in the order they are given, numbered from first_step when there is more than one
+fn format_states(states: &[QuantumState], first_step: Option<usize>, format: OutputFormat, label: &dyn Fn(usize) -> String) -> String {
+    let step_of = |position: usize| first_step.map(|first| first + position);
+    match format {
+        OutputFormat::Text => states
+            .iter()
+            .enumerate()
+            .map(|(position, state)| match step_of(position) {
+                Some(step) => format!("{}: {}\n", step, format_ket(state)),
+                None => format!("{}\n", format_ket(state)),
+            })
+            .collect(),
+        OutputFormat::Csv => {
+            let header = if first_step.is_some() { "step,basis,re,im\n" } else { "basis,re,im\n" };
+            let mut csv = header.to_string();
+            for (position, state) in states.iter().enumerate() {
+                for (index, amplitude) in state.col.iter().enumerate() {
+                    if let Some(step) = step_of(position) {
+                        csv.push_str(&format!("{},", step));
+                    }
+                    csv.push_str(&format!("{},{},{}\n", label(index), amplitude.re, amplitude.im));
+                }
+            }
+            csv
+        }
+        OutputFormat::Json => {
+            let amplitudes = |state: &QuantumState| -> Vec<[f64; 2]> { state.col.iter().map(|amplitude| [amplitude.re, amplitude.im]).collect() };
+            let value = match first_step {
+                Some(_) => json!({ "state_list": states.iter().map(amplitudes).collect::<Vec<_>>() }),
+                None => json!({ "state": amplitudes(&states[0]) }),
+            };
+            format!("{}\n", value)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(arguments: &[&str]) -> Options {
+        parse_arguments(&arguments.iter().map(|argument| argument.to_string()).collect::<Vec<String>>()).unwrap()
+    }
+
+    const BELL_QASM: &str = "OPENQASM 2.0;\ninclude \"qelib1.inc\";\nqreg q[2];\nh q[0];\ncx q[0], q[1];\n";
+
+    #[test]
+    fn test_parse_arguments() {
+        let parsed = options(&["bell.qasm", "--show", "counts", "--shots", "10", "--bit-order", "big_endian", "--backend", "sparse"]);
+
+        assert_eq!(parsed.path, "bell.qasm");
+        assert_eq!(parsed.show, Show::Counts);
+        assert_eq!(parsed.shots, 10);
+        assert_eq!(parsed.bit_order, BitOrder::BigEndian);
+        assert_eq!(parsed.backend, BackendKind::Sparse);
+        assert!(parse_arguments(&["--show".to_string()]).is_err());
+        assert!(parse_arguments(&["a.json".to_string(), "--show".to_string(), "everything".to_string()]).is_err());
+        assert!(parse_arguments(&[]).is_err());
+    }
+
+    #[test]
+    fn test_every_input_format() {
+        let grid = r#"{"circuit_matrix": [["H", "C_down"], ["I", "X"]]}"#;
+        let quirk = r#"{"cols": [["H"], ["•", "X"]]}"#;
+
+        for (path, contents) in [("bell.json", grid), ("bell.json", "[[\"H\", \"C_down\"], [\"I\", \"X\"]]"), ("bell.qasm", BELL_QASM), ("bell.json", quirk)] {
+            assert_eq!(run(&options(&[path]), contents).unwrap(), "(1/√2)(|00⟩ + |11⟩)\n", "{}", contents);
+        }
+    }
+
+    #[test]
+    fn test_output_formats() {
+        let grid = "[[\"X\"], [\"I\"]]";
+
+        assert_eq!(run(&options(&["x.json", "--show", "steps"]), grid).unwrap(), "0: |00⟩\n1: |01⟩\n");
+        assert_eq!(run(&options(&["x.json", "--show", "probabilities", "--format", "csv", "--bit-order", "big_endian"]), grid).unwrap(), "basis,probability\n00,0\n01,0\n10,1\n11,0\n");
+        assert_eq!(run(&options(&["x.json", "--format", "json"]), "[[\"X\"]]").unwrap(), "{\"state\":[[0.0,0.0],[1.0,0.0]]}\n");
+        assert_eq!(run(&options(&["x.json", "--show", "counts", "--shots", "5", "--format", "json"]), grid).unwrap(), "{\"bit_order\":\"little_endian\",\"counts\":{\"01\":5},\"shots\":5}\n");
+        assert_eq!(run(&options(&["x.json"]), "[[\"A\"]]"), Err(vec!["'A' on qubit 0 at step 0 is not a known gate".to_string()]));
+    }
+}
@@ -0,0 +1,37 @@
+// The quantum circuit simulator behind the Rocket server in main.rs, for embedding in other Rust programs:
+//
+// let grid = UnparsedCircuit::from(vec![vec!["H", "C_down"], vec!["I", "X"]]);
+// let states = simulate(grid)?;
+// let same_states = Circuit::new(2).h(0).cx(0, 1).simulate()?;
+//
+// The modules under simulation stay public for everything else, such as the importers and exporters.
+
+pub mod simulation;
+
+pub use simulation::circuit_builder::Circuit;
+pub use simulation::circuit_parser::{build_circuit_from_data, ParsedCircuit, UnparsedCircuit};
+pub use simulation::circuit_validator::{validate_grid_input, QuantumCircuitError};
+pub use simulation::grid_layout::GridLayout;
+pub use simulation::quantum_gate::QuantumGate;
+pub use simulation::quantum_state::QuantumState;
+pub use simulation::simulation_backend::{BackendKind, SimulationBackend};
+pub use simulation::simulator::{
+    simulate_circuit, simulate_circuit_handler as simulate, simulate_circuit_handler_with_backend as simulate_with_backend,
+};
+pub use simulation::utils::BitOrder;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_simulate_through_the_public_api() {
+        let grid = UnparsedCircuit::from(vec![vec!["H", "C_down"], vec!["I", "X"]]);
+
+        let states = simulate_with_backend(grid.clone(), BackendKind::StateVector, BitOrder::BigEndian).unwrap();
+
+        assert_eq!(states.len(), 3);
+        assert_eq!(simulate(UnparsedCircuit::from(vec![vec!["A"]])).unwrap_err()[0].code(), "unknown_gate");
+        assert!((states[2].col[[3, 0]].re - std::f64::consts::FRAC_1_SQRT_2).abs() < 1e-12);
+    }
+}
@@ -1,4 +1,4 @@
-mod simulation;
+use backend::simulation;
 
 use rocket::http::{ContentType, Method};
 use rocket_cors::{AllowedOrigins, CorsOptions};
@@ -6,7 +6,7 @@ use rocket_cors::{AllowedOrigins, CorsOptions};
 #[macro_use]
 extern crate rocket;
 
-use crate::simulation::circuit_validator::QuantumCircuitError;
+use backend::simulation::circuit_validator::{validate_grid_input, QuantumCircuitError};
 use rocket::serde::json::Json;
 use serde::{Deserialize, Serialize};
 
@@ -15,36 +15,90 @@ use rocket::response::{self, Responder, Response};
 use rocket::Request;
 
 
-use crate::simulation::circuit_parser::{UnparsedCircuit};
-use crate::simulation::quantum_state::{QuantumState};
+use backend::simulation::circuit_parser::{UnparsedCircuit};
+use backend::simulation::qasm_exporter::QasmVersion;
+use backend::simulation::qasm_importer::{QasmError, QasmMeasurement};
+use backend::simulation::quirk::QuirkError;
+use backend::simulation::text_renderer::TextStyle;
+use backend::simulation::utils::BitOrder;
+use backend::simulation::binary_export::{encode_complex128, BinaryFormat};
+use backend::simulation::custom_gates::{define_custom_gates, CustomGateDefinitions};
+use backend::simulation::optimiser::Optimisation;
+use backend::simulation::subcircuits::{define_subcircuits, BlockSteps, SubCircuitDefinitions};
+use backend::simulation::transpiler::{Basis, DEFAULT_TOLERANCE};
+use rocket::Either::{self, Left, Right};
+use backend::simulation::quantum_state::{QuantumState, SPARSE_EPSILON};
+use num::Complex;
+use std::collections::BTreeMap;
+use backend::simulation::simulation_backend::BackendKind;
 
 #[derive(Serialize, Deserialize)]
 struct IncomingData {
     circuit_matrix: Vec<Vec<String>>,
+    // Gates and sub-circuits the grid refers to by name
+    #[serde(flatten)]
+    definitions: GateDefinitions,
+    #[serde(default)]
+    backend: BackendKind,
+    // Add every state in ket notation, e.g. "(1/√2)(|00⟩ + |11⟩)"
+    #[serde(default)]
+    ket: bool,
+    #[serde(default)]
+    bit_order: BitOrder,
+    #[serde(default)]
+    mode: ResponseMode,
+    // Amplitudes with a magnitude up to this are left out in the sparse mode
+    #[serde(default = "default_threshold")]
+    threshold: f64,
+    #[serde(default)]
+    block_steps: BlockSteps,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct Step {
-    states: Vec<QuantumState>,
+fn default_threshold() -> f64 {
+    SPARSE_EPSILON
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct ComplexContainer {
-    re: f64,
-    im: f64,
+// What every step of the /simulate response contains
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ResponseMode {
+    // The whole state vector
+    #[default]
+    Full,
+    // The probability of every basis state, for clients that only draw the probability bars
+    Probabilities,
+    // Only the amplitudes above the threshold, keyed by basis state
+    Sparse,
 }
 
 #[derive(Serialize, Deserialize)]
 struct OutgoingData {
-    state_list: Vec<QuantumState>,
+    state_list: Vec<OutgoingStep>,
+    // The order the states were written in, so clients never have to guess
+    bit_order: BitOrder,
+    // With expanded block steps, the step of the grid every state after the first belongs to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    columns: Option<Vec<usize>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct OutgoingStep {
+    #[serde(flatten, default, skip_serializing_if = "Option::is_none")]
+    state: Option<QuantumState>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    probabilities: Option<Vec<f64>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    amplitudes: Option<BTreeMap<String, Complex<f64>>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ket: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
-struct ApiError {
-    error: QuantumCircuitError,
+struct ApiError<E: Serialize = QuantumCircuitError> {
+    errors: Vec<E>,
 }
 
-impl<'r> Responder<'r, 'static> for ApiError {
+impl<'r, E: Serialize> Responder<'r, 'static> for ApiError<E> {
     fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
         let error_json = serde_json::to_string(&self).unwrap();
         Response::build()
@@ -55,25 +109,385 @@ impl<'r> Responder<'r, 'static> for ApiError {
     }
 }
 
+#[derive(Clone, Default, Serialize, Deserialize)]
+struct GateDefinitions {
+    // Unitary matrices of [re, im] pairs
+    #[serde(default)]
+    custom_gates: CustomGateDefinitions,
+    // Grids that are placed as blocks, which can use the custom gates and each other
+    #[serde(default)]
+    subcircuits: SubCircuitDefinitions,
+}
+
+// The grid of a request together with the gates and sub-circuits it defines
+fn request_grid(circuit_matrix: Vec<Vec<String>>, definitions: GateDefinitions) -> Result<UnparsedCircuit, ApiError> {
+    let custom_gates = define_custom_gates(definitions.custom_gates).map_err(|errors| ApiError { errors })?;
+    let subcircuits = define_subcircuits(definitions.subcircuits, &custom_gates).map_err(|errors| ApiError { errors })?;
+    Ok(UnparsedCircuit { circuit: circuit_matrix, custom_gates, subcircuits })
+}
+
 #[post("/simulate", format = "json", data = "<incoming_data>")]
 fn simulate_circuit_handler(
     incoming_data: Json<IncomingData>,
 ) -> Result<Json<OutgoingData>, ApiError> {
-    let matrix = incoming_data.into_inner().circuit_matrix;
+    let IncomingData { circuit_matrix: matrix, definitions, backend, ket, bit_order, mode, threshold, block_steps } = incoming_data.into_inner();
+    let grid = request_grid(matrix, definitions)?;
+    debug!("Simulating with the {:?} backend\n{}", backend, grid);
 
-    match simulation::simulator::simulate_circuit_handler(UnparsedCircuit { circuit: matrix }) {
-        Ok(state_list) => {
+    let simulated = match block_steps {
+        BlockSteps::Collapsed => simulation::simulator::simulate_circuit_handler_with_backend(grid, backend, bit_order).map(|states| (states, None)),
+        BlockSteps::Expanded => simulation::simulator::simulate_expanded_with_backend(grid, backend, bit_order).map(|(states, columns)| (states, Some(columns))),
+    };
+    match simulated {
+        Ok((state_list, columns)) => {
             let mut step_list = Vec::new();
 
-            for step in state_list {
-                step_list.push(step);
+            for state in state_list {
+                let ket = ket.then(|| simulation::ket::format_ket(&state));
+                step_list.push(match mode {
+                    ResponseMode::Full => OutgoingStep { state: Some(state), probabilities: None, amplitudes: None, ket },
+                    ResponseMode::Probabilities => OutgoingStep { state: None, probabilities: Some(state.probabilities()), amplitudes: None, ket },
+                    ResponseMode::Sparse => OutgoingStep { state: None, probabilities: None, amplitudes: Some(state.amplitudes_above(threshold)), ket },
+                });
             }
 
 
-            let outgoing_data = OutgoingData { state_list: step_list };
+            let outgoing_data = OutgoingData { state_list: step_list, bit_order, columns };
             Ok(Json(outgoing_data))
         }
-        Err(err) => Err(ApiError { error: err }),
+        Err(errors) => Err(ApiError { errors }),
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct StateDownloadRequest {
+    circuit_matrix: Vec<Vec<String>>,
+    #[serde(flatten)]
+    definitions: GateDefinitions,
+    // The state after this step is written, after the last step if left out
+    #[serde(default)]
+    step: Option<usize>,
+    #[serde(default)]
+    format: BinaryFormat,
+    // Write the density matrix instead of the state vector
+    #[serde(default)]
+    density_matrix: bool,
+    #[serde(default)]
+    bit_order: BitOrder,
+    #[serde(default)]
+    backend: BackendKind,
+}
+
+// A file the browser saves instead of showing
+struct BinaryDownload {
+    file_name: String,
+    bytes: Vec<u8>,
+}
+
+impl<'r> Responder<'r, 'static> for BinaryDownload {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        Response::build()
+            .header(ContentType::Binary)
+            .raw_header("Content-Disposition", format!("attachment; filename=\"{}\"", self.file_name))
+            .sized_body(self.bytes.len(), std::io::Cursor::new(self.bytes))
+            .ok()
+    }
+}
+
+#[post("/download/state", format = "json", data = "<download_request>")]
+fn download_state_handler(download_request: Json<StateDownloadRequest>) -> Result<BinaryDownload, ApiError> {
+    let StateDownloadRequest { circuit_matrix, definitions, step, format, density_matrix, bit_order, backend } = download_request.into_inner();
+    let grid = request_grid(circuit_matrix, definitions)?;
+    // The last step by default
+    let step = step.unwrap_or(grid.circuit.first().map_or(0, |row| row.len().saturating_sub(1)));
+    let state = simulation::simulator::simulate_state_after_step_with_backend(grid, backend, bit_order, step).map_err(|errors| ApiError { errors })?;
+
+    let (name, bytes) = match density_matrix {
+        true => ("density_matrix", encode_complex128(&simulation::binary_export::density_matrix(&state), false, format)),
+        false => ("state", encode_complex128(&state.col, true, format)),
+    };
+    Ok(BinaryDownload { file_name: format!("{}_step_{}.{}", name, step, format.extension()), bytes })
+}
+
+#[derive(Serialize, Deserialize)]
+struct QasmData {
+    qasm: String,
+}
+
+#[derive(Serialize)]
+struct ImportedCircuit {
+    circuit_matrix: Vec<Vec<String>>,
+    measurements: Vec<QasmMeasurement>,
+    classical_bits: usize,
+}
+
+#[post("/import/qasm", format = "json", data = "<qasm_data>")]
+fn import_qasm_handler(qasm_data: Json<QasmData>) -> Result<Json<ImportedCircuit>, ApiError<QasmError>> {
+    match simulation::qasm_importer::import_qasm(&qasm_data.into_inner().qasm) {
+        Ok(import) => Ok(Json(ImportedCircuit {
+            circuit_matrix: import.grid.circuit,
+            measurements: import.measurements,
+            classical_bits: import.classical_bits,
+        })),
+        Err(errors) => Err(ApiError { errors }),
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct ExportRequest {
+    circuit_matrix: Vec<Vec<String>>,
+    #[serde(flatten)]
+    definitions: GateDefinitions,
+    #[serde(default)]
+    version: QasmVersion,
+}
+
+#[post("/export/qasm", format = "json", data = "<export_request>")]
+fn export_qasm_handler(export_request: Json<ExportRequest>) -> Result<Json<QasmData>, ApiError> {
+    let ExportRequest { circuit_matrix, definitions, version } = export_request.into_inner();
+    match simulation::qasm_exporter::export_qasm(&request_grid(circuit_matrix, definitions)?, version) {
+        Ok(qasm) => Ok(Json(QasmData { qasm })),
+        Err(errors) => Err(ApiError { errors }),
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct LatexExportRequest {
+    circuit_matrix: Vec<Vec<String>>,
+    #[serde(flatten)]
+    definitions: GateDefinitions,
+    // Wrap the circuit in a document that compiles on its own
+    #[serde(default)]
+    standalone: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct LatexData {
+    latex: String,
+}
+
+#[post("/export/latex", format = "json", data = "<export_request>")]
+fn export_latex_handler(export_request: Json<LatexExportRequest>) -> Result<Json<LatexData>, ApiError> {
+    let LatexExportRequest { circuit_matrix, definitions, standalone } = export_request.into_inner();
+    match simulation::latex_exporter::export_latex(&request_grid(circuit_matrix, definitions)?, standalone) {
+        Ok(latex) => Ok(Json(LatexData { latex })),
+        Err(errors) => Err(ApiError { errors }),
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct RenderRequest {
+    circuit_matrix: Vec<Vec<String>>,
+    #[serde(flatten)]
+    definitions: GateDefinitions,
+    #[serde(default)]
+    style: TextStyle,
+}
+
+#[post("/render/text", format = "json", data = "<render_request>")]
+fn render_text_handler(render_request: Json<RenderRequest>) -> Result<String, ApiError> {
+    let RenderRequest { circuit_matrix, definitions, style } = render_request.into_inner();
+    simulation::text_renderer::render_text(&request_grid(circuit_matrix, definitions)?, style).map_err(|errors| ApiError { errors })
+}
+
+// The same as the POST endpoint, for quick looks from a browser or curl:
+// /render/text?circuit=[["H","C_down"],["I","X"]]&style=ascii
+#[get("/render/text?<circuit>&<style>")]
+fn render_text_query_handler(circuit: &str, style: Option<&str>) -> Result<String, Either<ApiError, Status>> {
+    let circuit_matrix: Vec<Vec<String>> = serde_json::from_str(circuit).map_err(|_| Right(Status::UnprocessableEntity))?;
+    let style: TextStyle = match style {
+        Some(style) => serde_json::from_value(serde_json::Value::String(style.to_string())).map_err(|_| Right(Status::UnprocessableEntity))?,
+        None => TextStyle::default(),
+    };
+    simulation::text_renderer::render_text(&UnparsedCircuit::from(circuit_matrix), style).map_err(|errors| Left(ApiError { errors }))
+}
+
+#[derive(Serialize, Deserialize)]
+struct SvgRenderRequest {
+    circuit_matrix: Vec<Vec<String>>,
+    #[serde(flatten)]
+    definitions: GateDefinitions,
+    // Draw the probabilities of the basis states after this step under the circuit
+    #[serde(default)]
+    probability_step: Option<usize>,
+    #[serde(default)]
+    bit_order: BitOrder,
+}
+
+#[post("/render/svg", format = "json", data = "<render_request>")]
+fn render_svg_handler(render_request: Json<SvgRenderRequest>) -> Result<(ContentType, String), ApiError> {
+    let SvgRenderRequest { circuit_matrix, definitions, probability_step, bit_order } = render_request.into_inner();
+    match simulation::svg_renderer::render_svg(&request_grid(circuit_matrix, definitions)?, probability_step, bit_order) {
+        Ok(svg) => Ok((ContentType::SVG, svg)),
+        Err(errors) => Err(ApiError { errors }),
+    }
+}
+
+// Can be used directly as the src of an img tag:
+// /render/svg?circuit=[["H","C_down"],["I","X"]]&probability_step=1
+#[get("/render/svg?<circuit>&<probability_step>&<bit_order>")]
+fn render_svg_query_handler(circuit: &str, probability_step: Option<usize>, bit_order: Option<&str>) -> Result<(ContentType, String), Either<ApiError, Status>> {
+    let circuit_matrix: Vec<Vec<String>> = serde_json::from_str(circuit).map_err(|_| Right(Status::UnprocessableEntity))?;
+    let bit_order: BitOrder = match bit_order {
+        Some(bit_order) => serde_json::from_value(serde_json::Value::String(bit_order.to_string())).map_err(|_| Right(Status::UnprocessableEntity))?,
+        None => BitOrder::default(),
+    };
+    match simulation::svg_renderer::render_svg(&UnparsedCircuit::from(circuit_matrix), probability_step, bit_order) {
+        Ok(svg) => Ok((ContentType::SVG, svg)),
+        Err(errors) => Err(Left(ApiError { errors })),
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct CircuitMatrix {
+    circuit_matrix: Vec<Vec<String>>,
+}
+
+// Takes the circuit as Quirk writes it, {"cols": [...]}
+#[post("/import/quirk", format = "json", data = "<quirk>")]
+fn import_quirk_handler(quirk: Json<serde_json::Value>) -> Result<Json<CircuitMatrix>, ApiError<QuirkError>> {
+    match simulation::quirk::import_quirk(&quirk.into_inner()) {
+        Ok(grid) => Ok(Json(CircuitMatrix { circuit_matrix: grid.circuit })),
+        Err(errors) => Err(ApiError { errors }),
+    }
+}
+
+#[post("/export/quirk", format = "json", data = "<circuit_matrix>")]
+fn export_quirk_handler(circuit_matrix: Json<CircuitMatrix>) -> Result<Json<serde_json::Value>, ApiError<QuirkError>> {
+    let grid = UnparsedCircuit::from(circuit_matrix.into_inner().circuit_matrix);
+    match simulation::quirk::export_quirk(&grid) {
+        Ok(quirk) => Ok(Json(quirk)),
+        Err(errors) => Err(ApiError { errors }),
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct InvertRequest {
+    circuit_matrix: Vec<Vec<String>>,
+    #[serde(flatten)]
+    definitions: GateDefinitions,
+    // Only the steps from first_step to last_step are inverted, from the start and to the end of the circuit by default
+    #[serde(default)]
+    first_step: Option<usize>,
+    #[serde(default)]
+    last_step: Option<usize>,
+}
+
+#[post("/invert", format = "json", data = "<invert_request>")]
+fn invert_circuit_handler(invert_request: Json<InvertRequest>) -> Result<Json<CircuitMatrix>, ApiError> {
+    let InvertRequest { circuit_matrix, definitions, first_step, last_step } = invert_request.into_inner();
+    match simulation::circuit_inverse::invert_circuit(&request_grid(circuit_matrix, definitions)?, first_step, last_step) {
+        Ok(grid) => Ok(Json(CircuitMatrix { circuit_matrix: grid.circuit })),
+        Err(errors) => Err(ApiError { errors }),
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct ControlRequest {
+    circuit_matrix: Vec<Vec<String>>,
+    #[serde(flatten)]
+    definitions: GateDefinitions,
+    // The name of the sub-circuit the circuit becomes
+    #[serde(default = "default_block_name")]
+    name: String,
+    // How often the controlled circuit is applied, e.g. 2^k for phase estimation
+    #[serde(default = "default_power")]
+    power: usize,
+}
+
+fn default_block_name() -> String {
+    "Circuit".to_string()
+}
+
+fn default_power() -> usize {
+    1
+}
+
+#[derive(Serialize, Deserialize)]
+struct ControlledCircuit {
+    circuit_matrix: Vec<Vec<String>>,
+    // The custom gates of the request, and its sub-circuits together with the new ones
+    #[serde(flatten)]
+    definitions: GateDefinitions,
+}
+
+#[post("/control", format = "json", data = "<control_request>")]
+fn control_circuit_handler(control_request: Json<ControlRequest>) -> Result<Json<ControlledCircuit>, ApiError> {
+    let ControlRequest { circuit_matrix, mut definitions, name, power } = control_request.into_inner();
+    validate_grid_input(&request_grid(circuit_matrix.clone(), definitions.clone())?).map_err(|errors| ApiError { errors })?;
+
+    let (circuit_matrix, blocks) = simulation::subcircuits::controlled_circuit(circuit_matrix, &name, power).map_err(|error| ApiError { errors: vec![error] })?;
+    for (block, grid) in blocks {
+        if definitions.subcircuits.contains_key(&block) {
+            return Err(ApiError { errors: vec![QuantumCircuitError::InvalidSubCircuit { name: block, reason: "is already defined".to_string() }] });
+        }
+        definitions.subcircuits.insert(block, grid);
+    }
+    // The blocks are checked like the sub-circuits of any request, which catches names that can not be used
+    request_grid(circuit_matrix.clone(), definitions.clone())?;
+    Ok(Json(ControlledCircuit { circuit_matrix, definitions }))
+}
+
+#[derive(Serialize, Deserialize)]
+struct OptimiseRequest {
+    circuit_matrix: Vec<Vec<String>>,
+    #[serde(flatten)]
+    definitions: GateDefinitions,
+}
+
+#[derive(Serialize)]
+struct OptimisedCircuit {
+    circuit_matrix: Vec<Vec<String>>,
+    // What was cancelled, merged and removed, with the steps of the grid of the request
+    changes: Vec<Optimisation>,
+}
+
+#[post("/optimise", format = "json", data = "<optimise_request>")]
+fn optimise_circuit_handler(optimise_request: Json<OptimiseRequest>) -> Result<Json<OptimisedCircuit>, ApiError> {
+    let OptimiseRequest { circuit_matrix, definitions } = optimise_request.into_inner();
+    match simulation::optimiser::optimise_circuit(&request_grid(circuit_matrix, definitions)?) {
+        Ok(optimised) => Ok(Json(OptimisedCircuit { circuit_matrix: optimised.grid.circuit, changes: optimised.changes })),
+        Err(errors) => Err(ApiError { errors }),
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct TranspileRequest {
+    circuit_matrix: Vec<Vec<String>>,
+    #[serde(flatten)]
+    definitions: GateDefinitions,
+    #[serde(default)]
+    basis: Basis,
+    // How far each single-qubit gate may be from its approximation
+    #[serde(default = "default_tolerance")]
+    tolerance: f64,
+}
+
+fn default_tolerance() -> f64 {
+    DEFAULT_TOLERANCE
+}
+
+#[derive(Serialize)]
+struct TranspiledCircuit {
+    circuit_matrix: Vec<Vec<String>>,
+    counts_before: BTreeMap<String, usize>,
+    counts_after: BTreeMap<String, usize>,
+    routing_cx: usize,
+    approximation_error: f64,
+}
+
+#[post("/transpile", format = "json", data = "<transpile_request>")]
+fn transpile_circuit_handler(transpile_request: Json<TranspileRequest>) -> Result<Json<TranspiledCircuit>, ApiError> {
+    let TranspileRequest { circuit_matrix, definitions, basis, tolerance } = transpile_request.into_inner();
+    match simulation::transpiler::transpile_circuit(&request_grid(circuit_matrix, definitions)?, basis, tolerance) {
+        Ok(transpiled) => Ok(Json(TranspiledCircuit {
+            circuit_matrix: transpiled.grid.circuit,
+            counts_before: transpiled.counts_before,
+            counts_after: transpiled.counts_after,
+            routing_cx: transpiled.routing_cx,
+            approximation_error: transpiled.approximation_error,
+        })),
+        Err(errors) => Err(ApiError { errors }),
     }
 }
 
@@ -116,7 +530,7 @@ fn rocket() -> _ {
 
     rocket::build()
         .attach(cors.to_cors().unwrap())
-        .mount("/", routes![simulate_circuit_handler, ping_handler])
+        .mount("/", routes![simulate_circuit_handler, import_qasm_handler, export_qasm_handler, import_quirk_handler, export_quirk_handler, export_latex_handler, render_text_handler, render_text_query_handler, render_svg_handler, render_svg_query_handler, download_state_handler, invert_circuit_handler, control_circuit_handler, optimise_circuit_handler, transpile_circuit_handler, ping_handler])
 }
 
 #[cfg(test)]
@@ -170,4 +584,486 @@ mod tests {
         assert_eq!(response.status(), Status::Ok);
         assert_eq!(response.into_string(), Some(expected_response.to_string()));
     }*/
+
+    #[test]
+    fn test_simulate_chooses_backend() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+
+        let dispatch = |backend: &str| {
+            client
+                .post("/simulate")
+                .header(rocket::http::ContentType::JSON)
+                .body(format!(
+                    r#"{{"circuit_matrix": [["H", "C_down"], ["I", "X"]], "backend": "{}"}}"#,
+                    backend
+                ))
+                .dispatch()
+        };
+
+        let grouped = dispatch("grouped");
+        assert_eq!(grouped.status(), Status::Ok);
+        let state_vector = dispatch("state_vector");
+        assert_eq!(state_vector.status(), Status::Ok);
+        assert_eq!(grouped.into_string(), state_vector.into_string());
+
+        assert_eq!(dispatch("unknown").status(), Status::UnprocessableEntity);
+    }
+
+    #[test]
+    fn test_simulate_with_ket_notation() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+
+        let response = client
+            .post("/simulate")
+            .header(rocket::http::ContentType::JSON)
+            .body(r#"{"circuit_matrix": [["H", "C_down"], ["I", "X"]], "ket": true}"#)
+            .dispatch();
+
+        assert_eq!(response.status(), Status::Ok);
+        let body: serde_json::Value = serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        let kets: Vec<&str> = body["state_list"].as_array().unwrap().iter().map(|step| step["ket"].as_str().unwrap()).collect();
+        assert_eq!(kets, vec!["|00⟩", "(1/√2)(|00⟩ + |01⟩)", "(1/√2)(|00⟩ + |11⟩)"]);
+        assert!(body["state_list"][0]["col"].is_object());
+
+        let response = client
+            .post("/simulate")
+            .header(rocket::http::ContentType::JSON)
+            .body(r#"{"circuit_matrix": [["H"]]}"#)
+            .dispatch();
+
+        let body: serde_json::Value = serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert!(body["state_list"][0].get("ket").is_none());
+    }
+
+    #[test]
+    fn test_simulate_echoes_bit_order() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+
+        let dispatch = |body: &str| {
+            let response = client.post("/simulate").header(rocket::http::ContentType::JSON).body(body).dispatch();
+            serde_json::from_str::<serde_json::Value>(&response.into_string().unwrap()).unwrap()
+        };
+
+        let little_endian = dispatch(r#"{"circuit_matrix": [["X"], ["I"]], "ket": true}"#);
+        let big_endian = dispatch(r#"{"circuit_matrix": [["X"], ["I"]], "ket": true, "bit_order": "big_endian"}"#);
+
+        assert_eq!(little_endian["bit_order"], "little_endian");
+        assert_eq!(little_endian["state_list"][1]["ket"], "|01⟩");
+        assert_eq!(big_endian["bit_order"], "big_endian");
+        assert_eq!(big_endian["state_list"][1]["ket"], "|10⟩");
+    }
+
+    #[test]
+    fn test_simulate_response_modes() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+
+        let dispatch = |mode: &str| {
+            let body = format!(r#"{{"circuit_matrix": [["H", "C_down"], ["I", "X"]], "mode": "{}"}}"#, mode);
+            let response = client.post("/simulate").header(rocket::http::ContentType::JSON).body(body).dispatch();
+            serde_json::from_str::<serde_json::Value>(&response.into_string().unwrap()).unwrap()
+        };
+
+        let probabilities = dispatch("probabilities");
+        let last_step = &probabilities["state_list"][2];
+        assert!(last_step.get("col").is_none());
+        let bars: Vec<f64> = last_step["probabilities"].as_array().unwrap().iter().map(|p| p.as_f64().unwrap()).collect();
+        assert_eq!(bars.len(), 4);
+        assert!((bars[0] - 0.5).abs() < 1e-12 && bars[1] == 0.0 && bars[2] == 0.0 && (bars[3] - 0.5).abs() < 1e-12);
+
+        let sparse = dispatch("sparse");
+        let amplitudes = sparse["state_list"][2]["amplitudes"].as_object().unwrap();
+        assert_eq!(amplitudes.keys().collect::<Vec<_>>(), vec!["00", "11"]);
+        assert!((amplitudes["11"][0].as_f64().unwrap() - std::f64::consts::FRAC_1_SQRT_2).abs() < 1e-12);
+
+        let response = client
+            .post("/simulate")
+            .header(rocket::http::ContentType::JSON)
+            .body(r#"{"circuit_matrix": [["H"]], "mode": "everything"}"#)
+            .dispatch();
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+    }
+
+    #[test]
+    fn test_simulate_with_custom_gates() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+
+        let dispatch = |body: &str| {
+            let response = client.post("/simulate").header(rocket::http::ContentType::JSON).body(body).dispatch();
+            (response.status(), serde_json::from_str::<serde_json::Value>(&response.into_string().unwrap()).unwrap())
+        };
+
+        // An oracle that marks |11⟩, after which the qubits are in (|00⟩ + |01⟩ + |10⟩ - |11⟩)/2
+        let (status, body) = dispatch(
+            r#"{"circuit_matrix": [["H", "Oracle"], ["H", "Oracle"]], "ket": true,
+                "custom_gates": {"Oracle": [[[1,0],[0,0],[0,0],[0,0]], [[0,0],[1,0],[0,0],[0,0]], [[0,0],[0,0],[1,0],[0,0]], [[0,0],[0,0],[0,0],[-1,0]]]}}"#,
+        );
+        assert_eq!(status, Status::Ok);
+        assert_eq!(body["state_list"][2]["ket"], "(1/2)(|00⟩ + |01⟩ + |10⟩ - |11⟩)");
+
+        let (status, body) = dispatch(r#"{"circuit_matrix": [["Double"]], "custom_gates": {"Double": [[[2,0],[0,0]], [[0,0],[2,0]]]}}"#);
+        assert_eq!(status, Status::BadRequest);
+        assert_eq!(body["errors"][0]["code"], "invalid_custom_gate");
+        assert_eq!(body["errors"][0]["token"], "Double");
+    }
+
+    #[test]
+    fn test_simulate_with_subcircuits() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+
+        let dispatch = |block_steps: &str| {
+            let response = client
+                .post("/simulate")
+                .header(rocket::http::ContentType::JSON)
+                .body(format!(
+                    r#"{{"circuit_matrix": [["Bell", "Bell†"], ["Bell", "Bell†"]], "ket": true, "block_steps": "{}",
+                        "subcircuits": {{"Bell": [["H", "C_down"], ["I", "X"]]}}}}"#,
+                    block_steps
+                ))
+                .dispatch();
+            assert_eq!(response.status(), Status::Ok);
+            serde_json::from_str::<serde_json::Value>(&response.into_string().unwrap()).unwrap()
+        };
+
+        let collapsed = dispatch("collapsed");
+        assert_eq!(collapsed["state_list"].as_array().unwrap().len(), 3);
+        assert_eq!(collapsed["state_list"][1]["ket"], "(1/√2)(|00⟩ + |11⟩)");
+        assert!(collapsed.get("columns").is_none());
+
+        // Bell† undoes the CNOT before the Hadamard
+        let expanded = dispatch("expanded");
+        assert_eq!(expanded["columns"], serde_json::json!([0, 0, 1, 1]));
+        let kets: Vec<&str> = expanded["state_list"].as_array().unwrap().iter().map(|step| step["ket"].as_str().unwrap()).collect();
+        assert_eq!(kets, vec!["|00⟩", "(1/√2)(|00⟩ + |01⟩)", "(1/√2)(|00⟩ + |11⟩)", "(1/√2)(|00⟩ + |01⟩)", "|00⟩"]);
+    }
+
+    #[test]
+    fn test_invert_circuit() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+
+        let dispatch = |body: &str| {
+            let response = client.post("/invert").header(rocket::http::ContentType::JSON).body(body).dispatch();
+            (response.status(), serde_json::from_str::<serde_json::Value>(&response.into_string().unwrap()).unwrap())
+        };
+
+        let (status, body) = dispatch(r#"{"circuit_matrix": [["H", "C_down", "T"], ["Rz(pi/4)", "X", "QFT"]], "subcircuits": {"QFT": [["H"]]}}"#);
+        assert_eq!(status, Status::Ok);
+        assert_eq!(body["circuit_matrix"], serde_json::json!([["T†", "C_down", "H"], ["QFT†", "X", "Rz(-pi/4)"]]));
+
+        let (status, body) = dispatch(r#"{"circuit_matrix": [["H", "S", "T"]], "first_step": 1}"#);
+        assert_eq!(status, Status::Ok);
+        assert_eq!(body["circuit_matrix"], serde_json::json!([["H", "T†", "S†"]]));
+
+        let (status, body) = dispatch(r#"{"circuit_matrix": [["H", "S", "T"]], "last_step": 3}"#);
+        assert_eq!(status, Status::BadRequest);
+        assert_eq!(body["errors"][0]["code"], "step_out_of_range");
+    }
+
+    #[test]
+    fn test_control_circuit() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+
+        let post = |path: &str, body: String| {
+            let response = client.post(path).header(rocket::http::ContentType::JSON).body(body).dispatch();
+            (response.status(), serde_json::from_str::<serde_json::Value>(&response.into_string().unwrap()).unwrap())
+        };
+
+        // Controlled-S^2 is a controlled Z, which flips the sign of |11⟩
+        let (status, controlled) = post("/control", r#"{"circuit_matrix": [["Rot"]], "subcircuits": {"Rot": [["S"]]}, "name": "Sq", "power": 2}"#.to_string());
+        assert_eq!(status, Status::Ok);
+        assert_eq!(controlled["circuit_matrix"], serde_json::json!([["C_down"], ["Sq_2"]]));
+        assert_eq!(controlled["subcircuits"]["Sq_2"], serde_json::json!([["Sq", "Sq"]]));
+
+        let mut circuit_matrix: Vec<Vec<String>> = serde_json::from_value(controlled["circuit_matrix"].clone()).unwrap();
+        circuit_matrix[0].insert(0, "X".to_string());
+        circuit_matrix[1].insert(0, "X".to_string());
+        let body = serde_json::json!({ "circuit_matrix": circuit_matrix, "subcircuits": controlled["subcircuits"], "ket": true });
+        let (status, simulated) = post("/simulate", body.to_string());
+        assert_eq!(status, Status::Ok);
+        assert_eq!(simulated["state_list"][2]["ket"], "-|11⟩");
+
+        let (status, body) = post("/control", r#"{"circuit_matrix": [["H"]], "subcircuits": {"Circuit": [["X"]]}}"#.to_string());
+        assert_eq!(status, Status::BadRequest);
+        assert_eq!(body["errors"][0]["code"], "invalid_subcircuit");
+
+        // Custom gates are answered too, so the controlled circuit can be simulated as it is
+        let (status, controlled) = post("/control", r#"{"circuit_matrix": [["Flip"]], "custom_gates": {"Flip": [[[0,0],[1,0]], [[1,0],[0,0]]]}}"#.to_string());
+        assert_eq!(status, Status::Ok);
+        assert_eq!(controlled["custom_gates"]["Flip"], serde_json::json!([[[0.0, 0.0], [1.0, 0.0]], [[1.0, 0.0], [0.0, 0.0]]]));
+        let mut circuit_matrix: Vec<Vec<String>> = serde_json::from_value(controlled["circuit_matrix"].clone()).unwrap();
+        circuit_matrix[0].insert(0, "X".to_string());
+        circuit_matrix[1].insert(0, "I".to_string());
+        let body = serde_json::json!({ "circuit_matrix": circuit_matrix, "custom_gates": controlled["custom_gates"], "subcircuits": controlled["subcircuits"], "ket": true });
+        let (status, simulated) = post("/simulate", body.to_string());
+        assert_eq!(status, Status::Ok);
+        assert_eq!(simulated["state_list"][2]["ket"], "|11⟩");
+
+        let (status, body) = post("/control", r#"{"circuit_matrix": [["H"]], "power": 18446744073709551615}"#.to_string());
+        assert_eq!(status, Status::BadRequest);
+        assert_eq!(body["errors"][0]["message"], "The sub-circuit 'Circuit' can not be applied more than 1024 times");
+    }
+
+    #[test]
+    fn test_optimise_circuit() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+
+        let response = client
+            .post("/optimise")
+            .header(rocket::http::ContentType::JSON)
+            .body(r#"{"circuit_matrix": [["H", "H", "Rz(pi/8)", "Rz(pi/8)"], ["X", "I", "I", "C_down"], ["I", "I", "I", "X"]]}"#)
+            .dispatch();
+
+        assert_eq!(response.status(), Status::Ok);
+        let body: serde_json::Value = serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert_eq!(body["circuit_matrix"], serde_json::json!([["I", "Rz(pi/4)", "I"], ["X", "I", "C_down"], ["I", "I", "X"]]));
+        assert_eq!(body["changes"][0], serde_json::json!({"change": "cancelled", "first": "H", "second": "H", "qubits": [0], "steps": [0, 1]}));
+        assert_eq!(body["changes"][1]["change"], "merged");
+        assert_eq!(body["changes"][1]["merged"], "Rz(pi/4)");
+        assert_eq!(body["changes"][2], serde_json::json!({"change": "removed_step", "step": 1}));
+    }
+
+    #[test]
+    fn test_transpile_circuit() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+
+        let response = client
+            .post("/transpile")
+            .header(rocket::http::ContentType::JSON)
+            .body(r#"{"circuit_matrix": [["C_down"], ["C_down"], ["X"]], "basis": "h_t_cnot"}"#)
+            .dispatch();
+
+        assert_eq!(response.status(), Status::Ok);
+        let body: serde_json::Value = serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert_eq!(body["counts_before"], serde_json::json!({"CCX": 1}));
+        assert_eq!(body["counts_after"], serde_json::json!({"CX": 6, "H": 2, "T": 4, "T†": 3}));
+        assert_eq!(body["routing_cx"], 6);
+        assert_eq!(body["approximation_error"], 0.0);
+
+        let response = client
+            .post("/transpile")
+            .header(rocket::http::ContentType::JSON)
+            .body(r#"{"circuit_matrix": [["Rz(0.3)"]], "tolerance": 1e-6}"#)
+            .dispatch();
+
+        assert_eq!(response.status(), Status::BadRequest);
+        let body: serde_json::Value = serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert_eq!(body["errors"][0]["code"], "approximation_failed");
+    }
+
+    #[test]
+    fn test_simulate_reports_every_broken_cell() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+
+        let response = client
+            .post("/simulate")
+            .header(rocket::http::ContentType::JSON)
+            .body(r#"{"circuit_matrix": [["A", "H"], ["X", "B"]]}"#)
+            .dispatch();
+
+        assert_eq!(response.status(), Status::BadRequest);
+        let body: serde_json::Value = serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        let errors = body["errors"].as_array().unwrap();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0]["code"], "unknown_gate");
+        assert_eq!(errors[0]["qubit"], 0);
+        assert_eq!(errors[0]["step"], 0);
+        assert_eq!(errors[1]["token"], "B");
+        assert_eq!(errors[1]["qubit"], 1);
+        assert_eq!(errors[1]["step"], 1);
+    }
+
+    #[test]
+    fn test_import_qasm() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+
+        let response = client
+            .post("/import/qasm")
+            .header(rocket::http::ContentType::JSON)
+            .body(r#"{"qasm": "OPENQASM 2.0;\ninclude \"qelib1.inc\";\nqreg q[2];\ncreg c[1];\nh q[0];\ncx q[0], q[1];\nmeasure q[1] -> c[0];"}"#)
+            .dispatch();
+
+        assert_eq!(response.status(), Status::Ok);
+        let body: serde_json::Value = serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert_eq!(body["circuit_matrix"], serde_json::json!([["H", "C_down"], ["I", "X"]]));
+        assert_eq!(body["measurements"], serde_json::json!([{"qubit": 1, "clbit": 0, "step": 2}]));
+        assert_eq!(body["classical_bits"], 1);
+
+        let response = client
+            .post("/import/qasm")
+            .header(rocket::http::ContentType::JSON)
+            .body(r#"{"qasm": "qreg q[1];\nfoo q[0];"}"#)
+            .dispatch();
+
+        assert_eq!(response.status(), Status::BadRequest);
+        let body: serde_json::Value = serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert_eq!(body["errors"][0]["code"], "unknown_gate");
+        assert_eq!(body["errors"][0]["line"], 2);
+    }
+
+    #[test]
+    fn test_export_qasm() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+
+        let response = client
+            .post("/export/qasm")
+            .header(rocket::http::ContentType::JSON)
+            .body(r#"{"circuit_matrix": [["H", "C_down"], ["I", "X"]], "version": "3.0"}"#)
+            .dispatch();
+
+        assert_eq!(response.status(), Status::Ok);
+        let body: serde_json::Value = serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert_eq!(body["qasm"], "OPENQASM 3.0;\ninclude \"stdgates.inc\";\nqubit[2] q;\nh q[0];\ncx q[0], q[1];\n");
+
+        let response = client
+            .post("/export/qasm")
+            .header(rocket::http::ContentType::JSON)
+            .body(r#"{"circuit_matrix": [["C_down"]]}"#)
+            .dispatch();
+
+        assert_eq!(response.status(), Status::BadRequest);
+    }
+
+    #[test]
+    fn test_quirk_round_trip() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+
+        let exported = client
+            .post("/export/quirk")
+            .header(rocket::http::ContentType::JSON)
+            .body(r#"{"circuit_matrix": [["H", "C_down"], ["I", "X"]]}"#)
+            .dispatch();
+
+        assert_eq!(exported.status(), Status::Ok);
+        let quirk = exported.into_string().unwrap();
+        assert_eq!(quirk, r#"{"cols":[["H",1],["•","X"]]}"#);
+
+        let imported = client
+            .post("/import/quirk")
+            .header(rocket::http::ContentType::JSON)
+            .body(quirk)
+            .dispatch();
+
+        assert_eq!(imported.status(), Status::Ok);
+        assert_eq!(imported.into_string().unwrap(), r#"{"circuit_matrix":[["H","C_down"],["I","X"]]}"#);
+
+        let rejected = client
+            .post("/import/quirk")
+            .header(rocket::http::ContentType::JSON)
+            .body(r#"{"cols": [["QFT3"]]}"#)
+            .dispatch();
+
+        assert_eq!(rejected.status(), Status::BadRequest);
+        let body: serde_json::Value = serde_json::from_str(&rejected.into_string().unwrap()).unwrap();
+        assert_eq!(body["errors"][0]["code"], "unsupported_gate");
+        assert_eq!(body["errors"][0]["column"], 0);
+    }
+
+    #[test]
+    fn test_export_latex() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+
+        let response = client
+            .post("/export/latex")
+            .header(rocket::http::ContentType::JSON)
+            .body(r#"{"circuit_matrix": [["H", "C_down"], ["I", "X"]]}"#)
+            .dispatch();
+
+        assert_eq!(response.status(), Status::Ok);
+        let body: serde_json::Value = serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert!(body["latex"].as_str().unwrap().starts_with("\\begin{quantikz}"));
+    }
+
+    #[test]
+    fn test_render_text() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+
+        let response = client
+            .post("/render/text")
+            .header(rocket::http::ContentType::JSON)
+            .body(r#"{"circuit_matrix": [["H", "C_down"], ["I", "X"]], "style": "ascii"}"#)
+            .dispatch();
+
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.into_string().unwrap(), "q0: -H--*-\n        |\nq1: ----X-\n");
+
+        let response = client
+            .get("/render/text?circuit=%5B%5B%22H%22%2C%22C_down%22%5D%2C%5B%22I%22%2C%22X%22%5D%5D")
+            .dispatch();
+
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.into_string().unwrap(), "q0: ─H──●─\n        │\nq1: ────X─\n");
+
+        assert_eq!(client.get("/render/text?circuit=%5B%5B%22A%22%5D%5D").dispatch().status(), Status::BadRequest);
+        assert_eq!(client.get("/render/text?circuit=nonsense").dispatch().status(), Status::UnprocessableEntity);
+    }
+
+    #[test]
+    fn test_render_svg() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+
+        let response = client
+            .post("/render/svg")
+            .header(rocket::http::ContentType::JSON)
+            .body(r#"{"circuit_matrix": [["H", "C_down"], ["I", "X"]], "probability_step": 1}"#)
+            .dispatch();
+
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(rocket::http::ContentType::SVG));
+        assert!(response.into_string().unwrap().contains("<title>0.5000</title>"));
+
+        let response = client
+            .get("/render/svg?circuit=%5B%5B%22H%22%2C%22C_down%22%5D%2C%5B%22I%22%2C%22X%22%5D%5D")
+            .dispatch();
+
+        assert_eq!(response.status(), Status::Ok);
+        assert!(response.into_string().unwrap().starts_with("<svg"));
+
+        let response = client
+            .post("/render/svg")
+            .header(rocket::http::ContentType::JSON)
+            .body(r#"{"circuit_matrix": [["H"]], "probability_step": 3}"#)
+            .dispatch();
+
+        assert_eq!(response.status(), Status::BadRequest);
+        let body: serde_json::Value = serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert_eq!(body["errors"][0]["code"], "step_out_of_range");
+    }
+
+    #[test]
+    fn test_download_state() {
+        let client = Client::tracked(rocket()).expect("valid rocket instance");
+
+        let response = client
+            .post("/download/state")
+            .header(rocket::http::ContentType::JSON)
+            .body(r#"{"circuit_matrix": [["H", "C_down"], ["I", "X"]]}"#)
+            .dispatch();
+
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(rocket::http::ContentType::Binary));
+        assert_eq!(response.headers().get_one("Content-Disposition"), Some("attachment; filename=\"state_step_1.npy\""));
+        let bytes = response.into_bytes().unwrap();
+        assert!(bytes.starts_with(b"\x93NUMPY"));
+        assert_eq!(bytes.len(), 128 + 4 * 16);
+
+        let response = client
+            .post("/download/state")
+            .header(rocket::http::ContentType::JSON)
+            .body(r#"{"circuit_matrix": [["H", "C_down"], ["I", "X"]], "step": 0, "format": "raw", "density_matrix": true}"#)
+            .dispatch();
+
+        assert_eq!(response.headers().get_one("Content-Disposition"), Some("attachment; filename=\"density_matrix_step_0.bin\""));
+        let bytes = response.into_bytes().unwrap();
+        assert_eq!(bytes.len(), 16 * 16);
+        // After the Hadamard on qubit 0 the density matrix is 1/2 in the corners of the |00⟩ and |01⟩ block
+        let real_part = |row: usize, column: usize| f64::from_le_bytes(bytes[(row * 4 + column) * 16..][..8].try_into().unwrap());
+        assert!((real_part(0, 1) - 0.5).abs() < 1e-12 && (real_part(1, 1) - 0.5).abs() < 1e-12 && real_part(3, 3).abs() < 1e-12);
+
+        let response = client
+            .post("/download/state")
+            .header(rocket::http::ContentType::JSON)
+            .body(r#"{"circuit_matrix": [["H"]], "step": 1}"#)
+            .dispatch();
+
+        assert_eq!(response.status(), Status::BadRequest);
+    }
 }
@@ -0,0 +1,163 @@
+// Angles in parameterised grid tokens such as "Rz(pi/4)".
+// They are written as arithmetic on numbers and pi, with + - * / and parentheses.
+
+use std::f64::consts::PI;
+
+// Denominators tried when writing an angle as a fraction of pi
+const PI_DENOMINATORS: [i64; 8] = [1, 2, 3, 4, 6, 8, 12, 16];
+const FORMAT_EPSILON: f64 = 1e-9;
+
+pub fn parse_angle(text: &str) -> Option<f64> {
+    let chars: Vec<char> = text.chars().filter(|c| !c.is_whitespace()).collect();
+    let mut parser = AngleParser { chars, position: 0 };
+    let value = parser.parse_sum()?;
+    if parser.position != parser.chars.len() || !value.is_finite() {
+        return None;
+    }
+    Some(value)
+}
+
+// Write an angle so that parse_angle reads it back, as a fraction of pi when it is one
+pub fn format_angle(angle: f64) -> String {
+    if angle.abs() < FORMAT_EPSILON {
+        return "0".to_string();
+    }
+    match pi_fraction(angle) {
+        Some((numerator, denominator)) => {
+            let sign = if numerator < 0 { "-" } else { "" };
+            let coefficient = match numerator.abs() {
+                1 => "pi".to_string(),
+                n => format!("{}*pi", n),
+            };
+            match denominator {
+                1 => format!("{}{}", sign, coefficient),
+                d => format!("{}{}/{}", sign, coefficient, d),
+            }
+        }
+        None => format!("{}", angle),
+    }
+}
+
+// A grid token with angles, such as "Rz(pi/4)" or "U(pi/2,0,pi)"
+pub fn format_parameterised_token(name: &str, angles: &[f64]) -> String {
+    let angles: Vec<String> = angles.iter().map(|angle| format_angle(*angle)).collect();
+    format!("{}({})", name, angles.join(","))
+}
+
+// The angle as numerator * pi / denominator with the smallest denominator that fits, if there is one
+pub fn pi_fraction(angle: f64) -> Option<(i64, i64)> {
+    PI_DENOMINATORS.into_iter().find_map(|denominator| {
+        let numerator = angle / PI * denominator as f64;
+        ((numerator - numerator.round()).abs() < FORMAT_EPSILON).then(|| (numerator.round() as i64, denominator))
+    })
+}
+
+struct AngleParser {
+    chars: Vec<char>,
+    position: usize,
+}
+
+impl AngleParser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn parse_sum(&mut self) -> Option<f64> {
+        let mut value = self.parse_product()?;
+        while let Some(operator) = self.peek().filter(|c| *c == '+' || *c == '-') {
+            self.position += 1;
+            let rhs = self.parse_product()?;
+            value = if operator == '+' { value + rhs } else { value - rhs };
+        }
+        Some(value)
+    }
+
+    fn parse_product(&mut self) -> Option<f64> {
+        let mut value = self.parse_unary()?;
+        while let Some(operator) = self.peek().filter(|c| *c == '*' || *c == '/') {
+            self.position += 1;
+            let rhs = self.parse_unary()?;
+            value = if operator == '*' { value * rhs } else { value / rhs };
+        }
+        Some(value)
+    }
+
+    fn parse_unary(&mut self) -> Option<f64> {
+        match self.peek()? {
+            '-' => {
+                self.position += 1;
+                Some(-self.parse_unary()?)
+            }
+            '+' => {
+                self.position += 1;
+                self.parse_unary()
+            }
+            _ => self.parse_atom(),
+        }
+    }
+
+    fn parse_atom(&mut self) -> Option<f64> {
+        let c = self.peek()?;
+        if c == '(' {
+            self.position += 1;
+            let value = self.parse_sum()?;
+            if self.peek()? != ')' {
+                return None;
+            }
+            self.position += 1;
+            return Some(value);
+        }
+        if c == 'π' {
+            self.position += 1;
+            return Some(PI);
+        }
+        if self.chars[self.position..].starts_with(&['p', 'i']) {
+            self.position += 2;
+            return Some(PI);
+        }
+
+        let start = self.position;
+        while self
+            .peek()
+            .is_some_and(|c| c.is_ascii_digit() || c == '.' || c == 'e' || c == 'E')
+        {
+            // Allow a sign directly after an exponent marker
+            if matches!(self.peek(), Some('e') | Some('E'))
+                && matches!(self.chars.get(self.position + 1), Some('-') | Some('+'))
+            {
+                self.position += 1;
+            }
+            self.position += 1;
+        }
+        self.chars[start..self.position].iter().collect::<String>().parse().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_angle() {
+        assert_eq!(parse_angle("pi/2"), Some(PI / 2.0));
+        assert_eq!(parse_angle("-3*pi/4"), Some(-3.0 * PI / 4.0));
+        assert_eq!(parse_angle("0.5"), Some(0.5));
+        assert_eq!(parse_angle("(1 + 1) * π"), Some(2.0 * PI));
+        assert_eq!(parse_angle("1e-3"), Some(0.001));
+        assert_eq!(parse_angle("pi/"), None);
+        assert_eq!(parse_angle("1/0"), None);
+        assert_eq!(parse_angle("theta"), None);
+    }
+
+    #[test]
+    fn test_format_angle_round_trips() {
+        assert_eq!(format_angle(PI / 4.0), "pi/4");
+        assert_eq!(format_angle(-3.0 * PI / 2.0), "-3*pi/2");
+        assert_eq!(format_angle(PI), "pi");
+        assert_eq!(format_angle(0.0), "0");
+        assert_eq!(format_parameterised_token("U", &[PI / 2.0, 0.0, 0.3]), "U(pi/2,0,0.3)");
+        for angle in [0.1, -2.5, PI / 3.0, 7.0 * PI / 8.0] {
+            assert!((parse_angle(&format_angle(angle)).unwrap() - angle).abs() < 1e-12);
+        }
+    }
+}
@@ -0,0 +1,114 @@
+// Writes state vectors and density matrices as binary complex128 data, which NumPy and Julia read without
+// going through JSON, so no precision is lost and large states load quickly.
+// Amplitudes are written in C order as pairs of little endian f64, real part first, the layout of numpy.complex128.
+
+use crate::simulation::quantum_state::QuantumState;
+use ndarray::Array2;
+use num::Complex;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BinaryFormat {
+    // A NumPy .npy file, which numpy.load reads with its shape
+    #[default]
+    Npy,
+    // Only the amplitudes, e.g. for numpy.fromfile or Julia's read!
+    Raw,
+}
+
+impl BinaryFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            BinaryFormat::Npy => "npy",
+            BinaryFormat::Raw => "bin",
+        }
+    }
+}
+
+// The density matrix |ψ⟩⟨ψ| of a pure state
+pub fn density_matrix(state: &QuantumState) -> Array2<Complex<f64>> {
+    let bra = state.col.t().mapv(|amplitude| amplitude.conj());
+    state.col.dot(&bra)
+}
+
+// A state vector is written with the shape (2^n,), a density matrix with the shape (2^n, 2^n)
+pub fn encode_complex128(matrix: &Array2<Complex<f64>>, vector: bool, format: BinaryFormat) -> Vec<u8> {
+    let mut bytes = match format {
+        BinaryFormat::Npy => {
+            let shape = match vector {
+                true => format!("({},)", matrix.len()),
+                false => format!("({}, {})", matrix.nrows(), matrix.ncols()),
+            };
+            npy_header(&shape)
+        }
+        BinaryFormat::Raw => Vec::new(),
+    };
+    // iter() walks the matrix in logical order, which is C order
+    for amplitude in matrix.iter() {
+        bytes.extend_from_slice(&amplitude.re.to_le_bytes());
+        bytes.extend_from_slice(&amplitude.im.to_le_bytes());
+    }
+    bytes
+}
+
+// Version 1.0 of the .npy format: the magic string, the version, the length of the header and the header itself,
+// a Python dict padded with spaces and a newline so that the data starts at a multiple of 64 bytes
+fn npy_header(shape: &str) -> Vec<u8> {
+    const PREAMBLE_LENGTH: usize = 10;
+    let mut header = format!("{{'descr': '<c16', 'fortran_order': False, 'shape': {}, }}", shape);
+    let padding = 64 - (PREAMBLE_LENGTH + header.len() + 1) % 64;
+    header.push_str(&" ".repeat(padding % 64));
+    header.push('\n');
+
+    let mut bytes = b"\x93NUMPY\x01\x00".to_vec();
+    bytes.extend_from_slice(&(header.len() as u16).to_le_bytes());
+    bytes.extend_from_slice(header.as_bytes());
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::arr2;
+    use std::f64::consts::FRAC_1_SQRT_2;
+
+    fn plus_state() -> QuantumState {
+        QuantumState { col: arr2(&[[Complex::new(FRAC_1_SQRT_2, 0.0)], [Complex::new(0.0, FRAC_1_SQRT_2)]]) }
+    }
+
+    fn read_f64(bytes: &[u8], offset: usize) -> f64 {
+        f64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_npy_state_vector() {
+        let bytes = encode_complex128(&plus_state().col, true, BinaryFormat::Npy);
+
+        assert_eq!(&bytes[..8], b"\x93NUMPY\x01\x00");
+        let header_length = u16::from_le_bytes([bytes[8], bytes[9]]) as usize;
+        assert_eq!((10 + header_length) % 64, 0);
+        let header = std::str::from_utf8(&bytes[10..10 + header_length]).unwrap();
+        assert!(header.starts_with("{'descr': '<c16', 'fortran_order': False, 'shape': (2,), }"));
+        assert!(header.ends_with(" \n"));
+
+        let data = &bytes[10 + header_length..];
+        assert_eq!(data.len(), 2 * 16);
+        assert_eq!(read_f64(data, 0), FRAC_1_SQRT_2);
+        assert_eq!(read_f64(data, 8), 0.0);
+        assert_eq!(read_f64(data, 24), FRAC_1_SQRT_2);
+    }
+
+    #[test]
+    fn test_raw_density_matrix() {
+        let matrix = density_matrix(&plus_state());
+        let bytes = encode_complex128(&matrix, false, BinaryFormat::Raw);
+
+        assert_eq!(bytes.len(), 4 * 16);
+        // Row 0, column 1 is ψ0 * conj(ψ1) = -i/2
+        assert!(read_f64(&bytes, 16).abs() < 1e-12);
+        assert!((read_f64(&bytes, 24) + 0.5).abs() < 1e-12);
+        assert!(encode_complex128(&matrix, false, BinaryFormat::Npy).starts_with(b"\x93NUMPY"));
+        assert!(std::str::from_utf8(&encode_complex128(&matrix, false, BinaryFormat::Npy)[10..70]).unwrap().contains("'shape': (2, 2)"));
+    }
+}
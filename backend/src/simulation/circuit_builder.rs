@@ -0,0 +1,332 @@
+// Builds circuits gate by gate instead of as a grid of tokens, for tests and programs that generate circuits:
+//
+// let states = Circuit::new(3).h(0).cx(0, 1).rz(2, PI / 4.0).measure_all().simulate()?;
+//
+// Gates are packed into the leftmost step where their qubits are free, and the result is the same grid the
+// frontend sends, so it goes through the same validator, parser and simulator.
+
+use crate::simulation::angle::format_parameterised_token;
+use crate::simulation::circuit_parser::{build_circuit_from_data, ParsedCircuit, UnparsedCircuit};
+use crate::simulation::circuit_validator::{validate_grid_input, QuantumCircuitError};
+use crate::simulation::custom_gates::validate_custom_gate;
+use crate::simulation::grid_layout::GridLayout;
+use crate::simulation::quantum_gate::QuantumGate;
+use crate::simulation::quantum_state::QuantumState;
+use crate::simulation::simulation_backend::BackendKind;
+use crate::simulation::simulator::{simulate_circuit_handler_with_backend, simulate_state_after_step_with_backend};
+use crate::simulation::utils::BitOrder;
+use rand::RngCore;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone)]
+pub struct Circuit {
+    layout: GridLayout,
+    // The measured qubits in the order they were measured
+    measurements: Vec<usize>,
+    custom_gates: BTreeMap<String, QuantumGate>,
+    // Mistakes such as qubits outside of the register are kept until the circuit is built
+    errors: Vec<QuantumCircuitError>,
+}
+
+impl Circuit {
+    pub fn new(no_of_qubits: usize) -> Circuit {
+        let mut layout = GridLayout::default();
+        layout.add_qubits(no_of_qubits);
+        Circuit { layout, measurements: Vec::new(), custom_gates: BTreeMap::new(), errors: Vec::new() }
+    }
+
+    pub fn no_of_qubits(&self) -> usize {
+        self.layout.no_of_qubits()
+    }
+
+    // Any single qubit gate token of the grid, e.g. "H" or "U(pi/2,0,pi)"
+    pub fn gate(mut self, token: &str, qubit: usize) -> Circuit {
+        if self.check_qubits(&[qubit]) {
+            self.layout.single(token.to_string(), qubit);
+        }
+        self
+    }
+
+    // Any single qubit gate token of the grid, controlled by another qubit
+    pub fn controlled(mut self, token: &str, control: usize, target: usize) -> Circuit {
+        if self.check_qubits(&[control, target]) {
+            self.layout.controlled(token.to_string(), control, target);
+        }
+        self
+    }
+
+    pub fn h(self, qubit: usize) -> Circuit {
+        self.gate("H", qubit)
+    }
+
+    pub fn x(self, qubit: usize) -> Circuit {
+        self.gate("X", qubit)
+    }
+
+    pub fn y(self, qubit: usize) -> Circuit {
+        self.gate("Y", qubit)
+    }
+
+    pub fn z(self, qubit: usize) -> Circuit {
+        self.gate("Z", qubit)
+    }
+
+    pub fn s(self, qubit: usize) -> Circuit {
+        self.gate("S", qubit)
+    }
+
+    pub fn t(self, qubit: usize) -> Circuit {
+        self.gate("T", qubit)
+    }
+
+    pub fn sdg(self, qubit: usize) -> Circuit {
+        self.gate("S†", qubit)
+    }
+
+    pub fn tdg(self, qubit: usize) -> Circuit {
+        self.gate("T†", qubit)
+    }
+
+    pub fn sx(self, qubit: usize) -> Circuit {
+        self.gate("SX", qubit)
+    }
+
+    pub fn sxdg(self, qubit: usize) -> Circuit {
+        self.gate("SX†", qubit)
+    }
+
+    pub fn rx(self, qubit: usize, theta: f64) -> Circuit {
+        self.gate(&format_parameterised_token("Rx", &[theta]), qubit)
+    }
+
+    pub fn ry(self, qubit: usize, theta: f64) -> Circuit {
+        self.gate(&format_parameterised_token("Ry", &[theta]), qubit)
+    }
+
+    pub fn rz(self, qubit: usize, theta: f64) -> Circuit {
+        self.gate(&format_parameterised_token("Rz", &[theta]), qubit)
+    }
+
+    pub fn p(self, qubit: usize, lambda: f64) -> Circuit {
+        self.gate(&format_parameterised_token("P", &[lambda]), qubit)
+    }
+
+    pub fn u(self, qubit: usize, theta: f64, phi: f64, lambda: f64) -> Circuit {
+        self.gate(&format_parameterised_token("U", &[theta, phi, lambda]), qubit)
+    }
+
+    pub fn cx(self, control: usize, target: usize) -> Circuit {
+        self.controlled("X", control, target)
+    }
+
+    pub fn cy(self, control: usize, target: usize) -> Circuit {
+        self.controlled("Y", control, target)
+    }
+
+    pub fn cz(self, control: usize, target: usize) -> Circuit {
+        self.controlled("Z", control, target)
+    }
+
+    pub fn ch(self, control: usize, target: usize) -> Circuit {
+        self.controlled("H", control, target)
+    }
+
+    pub fn cp(self, control: usize, target: usize, lambda: f64) -> Circuit {
+        self.controlled(&format_parameterised_token("P", &[lambda]), control, target)
+    }
+
+    // Define a gate from its unitary matrix, to be added with custom()
+    pub fn define(mut self, name: &str, gate: QuantumGate) -> Circuit {
+        match validate_custom_gate(name, &gate) {
+            Ok(()) => {
+                self.custom_gates.insert(name.to_string(), gate);
+            }
+            Err(error) => self.errors.push(error),
+        }
+        self
+    }
+
+    // A defined gate on the given qubit and, for gates on several qubits, the ones underneath it.
+    // Gates that were never defined are reported as unknown when the circuit is built
+    pub fn custom(mut self, name: &str, first_qubit: usize) -> Circuit {
+        let size = self.custom_gates.get(name).map_or(1, |gate| gate.size);
+        let qubits: Vec<usize> = (first_qubit..first_qubit + size).collect();
+        if self.check_qubits(&qubits) {
+            self.layout.place(first_qubit, vec![name.to_string(); size]);
+        }
+        self
+    }
+
+    pub fn swap(mut self, first: usize, second: usize) -> Circuit {
+        if self.check_qubits(&[first, second]) {
+            self.layout.swap(first, second);
+        }
+        self
+    }
+
+    // Gates added after the barrier start after every gate added before it
+    pub fn barrier(mut self) -> Circuit {
+        let qubits: Vec<usize> = (0..self.no_of_qubits()).collect();
+        self.layout.barrier(&qubits);
+        self
+    }
+
+    // Measurements happen after every gate, so no gate can be added to a measured qubit.
+    // Measuring a qubit again changes nothing.
+    pub fn measure(mut self, qubit: usize) -> Circuit {
+        if !self.measurements.contains(&qubit) && self.check_qubits(&[qubit]) {
+            self.measurements.push(qubit);
+        }
+        self
+    }
+
+    pub fn measure_all(self) -> Circuit {
+        (0..self.no_of_qubits()).fold(self, |circuit, qubit| circuit.measure(qubit))
+    }
+
+    pub fn measurements(&self) -> &[usize] {
+        &self.measurements
+    }
+
+    pub fn grid(&self) -> Result<UnparsedCircuit, Vec<QuantumCircuitError>> {
+        if !self.errors.is_empty() {
+            return Err(self.errors.clone());
+        }
+        let mut grid = self.layout.clone().into_grid();
+        grid.custom_gates = self.custom_gates.clone();
+        validate_grid_input(&grid)?;
+        Ok(grid)
+    }
+
+    pub fn build(&self) -> Result<ParsedCircuit, Vec<QuantumCircuitError>> {
+        build_circuit_from_data(self.grid()?).map_err(|error| vec![error])
+    }
+
+    // The state before the first step and after every step, as /simulate returns them
+    pub fn simulate(&self) -> Result<Vec<QuantumState>, Vec<QuantumCircuitError>> {
+        self.simulate_with_backend(BackendKind::default(), BitOrder::default())
+    }
+
+    pub fn simulate_with_backend(&self, backend: BackendKind, bit_order: BitOrder) -> Result<Vec<QuantumState>, Vec<QuantumCircuitError>> {
+        simulate_circuit_handler_with_backend(self.grid()?, backend, bit_order)
+    }
+
+    // Run the circuit the given number of times and count the outcomes of the measured qubits, every qubit if none are.
+    // Outcomes are written with the first measurement as the rightmost bit, like a classical register.
+    pub fn sample(&self, shots: usize, rng: &mut dyn RngCore) -> Result<BTreeMap<String, usize>, Vec<QuantumCircuitError>> {
+        let grid = self.grid()?;
+        let last_step = grid.circuit[0].len() - 1;
+        let final_state = simulate_state_after_step_with_backend(grid, BackendKind::default(), BitOrder::BigEndian, last_step)?;
+        let no_of_qubits = self.no_of_qubits();
+        let measured: Vec<usize> = match self.measurements.is_empty() {
+            true => (0..no_of_qubits).collect(),
+            false => self.measurements.clone(),
+        };
+
+        let mut counts = BTreeMap::new();
+        for (index, count) in final_state.sample(shots, rng) {
+            let outcome: String = measured
+                .iter()
+                .rev()
+                .map(|qubit| if index >> (no_of_qubits - qubit - 1) & 1 == 1 { '1' } else { '0' })
+                .collect();
+            *counts.entry(outcome).or_insert(0) += count;
+        }
+        Ok(counts)
+    }
+
+    // Qubits must be inside the register, not measured yet and not repeated within one gate
+    fn check_qubits(&mut self, qubits: &[usize]) -> bool {
+        let no_of_qubits = self.no_of_qubits();
+        let error = if let Some(&qubit) = qubits.iter().find(|qubit| **qubit >= no_of_qubits) {
+            Some(QuantumCircuitError::QubitOutOfRange { qubit, no_of_qubits })
+        } else if let Some(&qubit) = qubits.iter().find(|qubit| self.measurements.contains(qubit)) {
+            Some(QuantumCircuitError::GateAfterMeasurement { qubit })
+        } else {
+            qubits
+                .iter()
+                .enumerate()
+                .find(|(position, qubit)| qubits[..*position].contains(qubit))
+                .map(|(_, &qubit)| QuantumCircuitError::RepeatedQubit { qubit })
+        };
+        match error {
+            Some(error) => {
+                self.errors.push(error);
+                false
+            }
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use num::Complex;
+    use rand::SeedableRng;
+    use std::f64::consts::PI;
+
+    #[test]
+    fn test_builds_the_same_circuit_as_the_grid() {
+        let circuit = Circuit::new(3).h(0).cx(0, 1).rz(2, PI / 4.0).t(2);
+        let grid = UnparsedCircuit::from(vec![vec!["H", "C_down"], vec!["I", "X"], vec!["Rz(pi/4)", "T"]]);
+
+        assert_eq!(circuit.grid(), Ok(grid.clone()));
+        assert_eq!(circuit.build(), build_circuit_from_data(grid.clone()).map_err(|error| vec![error]));
+        assert_eq!(circuit.simulate(), simulate_circuit_handler_with_backend(grid, BackendKind::default(), BitOrder::default()));
+    }
+
+    #[test]
+    fn test_packs_gates_into_columns() {
+        let circuit = Circuit::new(3).h(0).x(2).cx(0, 1).z(2).barrier().swap(1, 2).u(0, 0.5, 0.0, PI);
+
+        assert_eq!(
+            circuit.grid().unwrap(),
+            UnparsedCircuit::from(vec![
+                vec!["H", "C_down", "U(0.5,0,pi)"],
+                vec!["I", "X", "Swap"],
+                vec!["X", "Z", "Swap"],
+            ])
+        );
+    }
+
+    #[test]
+    fn test_mistakes_are_reported_when_built() {
+        assert_eq!(Circuit::new(2).h(2).build(), Err(vec![QuantumCircuitError::QubitOutOfRange { qubit: 2, no_of_qubits: 2 }]));
+        assert_eq!(Circuit::new(2).cx(1, 1).build(), Err(vec![QuantumCircuitError::RepeatedQubit { qubit: 1 }]));
+        assert_eq!(Circuit::new(2).measure(0).x(0).build(), Err(vec![QuantumCircuitError::GateAfterMeasurement { qubit: 0 }]));
+        assert_eq!(Circuit::new(7).h(0).build(), Err(vec![QuantumCircuitError::TooManyQubits]));
+    }
+
+    #[test]
+    fn test_custom_gates() {
+        let oracle = QuantumGate::c_down(QuantumGate::z_gate());
+        let circuit = Circuit::new(3).define("Oracle", oracle.clone()).h(0).custom("Oracle", 1).cx(0, 1);
+
+        let grid = circuit.grid().unwrap();
+        assert_eq!(grid.circuit, vec![vec!["H", "C_down"], vec!["Oracle", "X"], vec!["Oracle", "I"]]);
+        assert_eq!(grid.custom_gates["Oracle"], oracle);
+
+        let doubled = QuantumGate { matrix: QuantumGate::x_gate().matrix * Complex::new(2.0, 0.0), size: 1 };
+        assert_eq!(
+            Circuit::new(2).define("Oracle", oracle).custom("Oracle", 1).build(),
+            Err(vec![QuantumCircuitError::QubitOutOfRange { qubit: 2, no_of_qubits: 2 }])
+        );
+        assert_eq!(Circuit::new(1).define("Double", doubled).build().map_err(|errors| errors[0].code()), Err("invalid_custom_gate"));
+        assert_eq!(Circuit::new(1).custom("Oracle", 0).build().map_err(|errors| errors[0].code()), Err("unknown_gate"));
+    }
+
+    #[test]
+    fn test_sample_measured_qubits() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(3);
+        let circuit = Circuit::new(3).x(0).h(1).cx(1, 2);
+
+        let counts = circuit.clone().measure(0).measure(2).measure_all().sample(1000, &mut rng).unwrap();
+        // q1 q2 q0, as qubit 1 was measured last
+        assert_eq!(counts.keys().collect::<Vec<&String>>(), vec!["001", "111"]);
+        assert_eq!(counts.values().sum::<usize>(), 1000);
+
+        let counts = circuit.sample(100, &mut rng).unwrap();
+        assert!(counts.keys().all(|outcome| outcome == "001" || outcome == "111"));
+    }
+}
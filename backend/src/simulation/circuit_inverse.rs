@@ -0,0 +1,114 @@
+// The inverse of a grid circuit, for uncomputation and "undo" exercises.
+// The steps are walked backwards and every token is replaced by the token of its inverse: S and T become S† and T†,
+// SX becomes SX†, rotations and phases turn the other way, and custom gates and sub-circuits get a † or lose it.
+
+use crate::simulation::angle::format_parameterised_token;
+use crate::simulation::circuit_parser::{parse_parameterised_token, UnparsedCircuit};
+use crate::simulation::circuit_validator::{validate_grid_input, QuantumCircuitError};
+use crate::simulation::subcircuits::INVERSE_SUFFIX;
+
+// Invert the steps from the first to the last step, both included and in either order, which default to
+// the start and the end of the circuit. The steps outside of them are left where they are
+pub fn invert_circuit(grid: &UnparsedCircuit, first_step: Option<usize>, last_step: Option<usize>) -> Result<UnparsedCircuit, Vec<QuantumCircuitError>> {
+    validate_grid_input(grid)?;
+
+    let no_of_steps = grid.circuit[0].len();
+    let (first, last) = (first_step.unwrap_or(0), last_step.unwrap_or(no_of_steps - 1));
+    let out_of_range: Vec<QuantumCircuitError> = [first, last]
+        .into_iter()
+        .filter(|step| *step >= no_of_steps)
+        .map(|step| QuantumCircuitError::StepOutOfRange { step, no_of_steps })
+        .collect();
+    if !out_of_range.is_empty() {
+        return Err(out_of_range);
+    }
+    let steps = first.min(last)..=first.max(last);
+
+    let mut circuit = grid.circuit.clone();
+    for row in circuit.iter_mut() {
+        let inverted: Vec<String> = row[steps.clone()].iter().rev().map(|token| inverse_token(token)).collect();
+        row.splice(steps.clone(), inverted);
+    }
+    Ok(UnparsedCircuit { circuit, ..grid.clone() })
+}
+
+// The token of the inverse of a valid token. Controls, swaps and wires stay as they are,
+// and so does every gate that is its own inverse
+pub fn inverse_token(token: &str) -> String {
+    match token {
+        "I" | "H" | "X" | "Y" | "Z" | "Swap" | "C_down" => token.to_string(),
+        "S" => "S†".to_string(),
+        "T" => "T†".to_string(),
+        "S†" => "S".to_string(),
+        "T†" => "T".to_string(),
+        "SX" => "SX†".to_string(),
+        "SX†" => "SX".to_string(),
+        _ => match parse_parameterised_token(token) {
+            Some((name @ ("Rx" | "Ry" | "Rz" | "P"), angles)) => format_parameterised_token(name, &angles.iter().map(|angle| -angle).collect::<Vec<f64>>()),
+            // U(θ,φ,λ) is Rz(φ)·Ry(θ)·Rz(λ), so its inverse swaps φ and λ
+            Some(("U", angles)) => format_parameterised_token("U", &[-angles[0], -angles[2], -angles[1]]),
+            _ => match token.strip_suffix(INVERSE_SUFFIX) {
+                Some(name) => name.to_string(),
+                None => format!("{}{}", token, INVERSE_SUFFIX),
+            },
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::quantum_gate::QuantumGate;
+    use crate::simulation::simulator::simulate_circuit_handler;
+    use crate::simulation::subcircuits::define_subcircuits;
+    use std::collections::BTreeMap;
+
+    // The circuit followed by the given circuit, one step after the other
+    fn followed_by(grid: &UnparsedCircuit, other: &UnparsedCircuit) -> UnparsedCircuit {
+        let circuit = grid.circuit.iter().zip(other.circuit.iter()).map(|(row, other_row)| [row.clone(), other_row.clone()].concat()).collect();
+        UnparsedCircuit { circuit, ..grid.clone() }
+    }
+
+    #[test]
+    fn test_inverse_tokens() {
+        let grid = UnparsedCircuit::from(vec![vec!["H", "S", "C_down", "Rz(pi/4)"], vec!["T†", "U(pi/2,pi/4,0)", "P(1.5)", "Swap"], vec!["I", "Y", "I", "Swap"]]);
+
+        let inverse = invert_circuit(&grid, None, None).unwrap();
+
+        assert_eq!(
+            inverse,
+            UnparsedCircuit::from(vec![vec!["Rz(-pi/4)", "C_down", "S†", "H"], vec!["Swap", "P(-1.5)", "U(-pi/2,0,-pi/4)", "T"], vec!["Swap", "I", "Y", "I"]])
+        );
+        assert_eq!(invert_circuit(&inverse, None, None).unwrap().circuit, grid.circuit);
+    }
+
+    #[test]
+    fn test_circuit_followed_by_its_inverse() {
+        let mut grid = UnparsedCircuit::from(vec![
+            vec!["H", "C_down", "Rx(0.3)", "Bell", "U(1,2,3)"],
+            vec!["T", "X", "I", "Bell", "S"],
+            vec!["Ry(pi/3)", "Y", "C_down", "P(pi/8)", "C_down"],
+            vec!["I", "S", "Oracle", "H", "Rz(2.5)"],
+        ]);
+        grid.custom_gates.insert("Oracle".to_string(), QuantumGate::u_gate(0.4, 1.1, -0.7));
+        grid.subcircuits = define_subcircuits(
+            BTreeMap::from([("Bell".to_string(), vec![vec!["H".to_string(), "C_down".to_string()], vec!["T".to_string(), "X".to_string()]])]),
+            &grid.custom_gates,
+        )
+        .unwrap();
+
+        let states = simulate_circuit_handler(followed_by(&grid, &invert_circuit(&grid, None, None).unwrap())).unwrap();
+        let last = states.last().unwrap();
+
+        assert!((last.col[[0, 0]].norm() - 1.0).abs() < 1e-10);
+    }
+
+    #[test]
+    fn test_invert_selected_steps() {
+        let grid = UnparsedCircuit::from(vec![vec!["H", "S", "T", "X"]]);
+
+        assert_eq!(invert_circuit(&grid, Some(2), Some(1)).unwrap(), UnparsedCircuit::from(vec![vec!["H", "T†", "S†", "X"]]));
+        assert_eq!(invert_circuit(&grid, Some(3), None).unwrap(), grid);
+        assert_eq!(invert_circuit(&grid, Some(1), Some(4)), Err(vec![QuantumCircuitError::StepOutOfRange { step: 4, no_of_steps: 4 }]));
+    }
+}
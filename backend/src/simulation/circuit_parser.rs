@@ -1,12 +1,34 @@
+use crate::simulation::angle::parse_angle;
+use crate::simulation::circuit_validator::QuantumCircuitError;
 use crate::simulation::quantum_gate::{QuantumGate, QuantumGateWrapper, GatesInTimeStep};
-use ndarray::{arr2};
-use num::Complex;
+use crate::simulation::subcircuits::{SubCircuit, INVERSE_SUFFIX};
 use rocket::Either;
 use rocket::Either::{Left, Right};
+use std::collections::BTreeMap;
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct UnparsedCircuit {
     pub circuit: Vec<Vec<String>>,
+    // Gates defined by the request, which the grid refers to by name
+    pub custom_gates: BTreeMap<String, QuantumGate>,
+    // Grids defined by the request, which the grid places as blocks by name
+    pub subcircuits: BTreeMap<String, SubCircuit>,
+}
+
+impl From<Vec<Vec<String>>> for UnparsedCircuit {
+    fn from(circuit: Vec<Vec<String>>) -> Self {
+        UnparsedCircuit { circuit, ..UnparsedCircuit::default() }
+    }
+}
+
+impl UnparsedCircuit {
+    // The gate that a custom gate or a sub-circuit stands for, e.g. "Oracle", "QFT" or the inverse "QFT†"
+    pub fn named_gate(&self, token: &str) -> Option<QuantumGate> {
+        match token.strip_suffix(INVERSE_SUFFIX) {
+            Some(name) => self.named_gate(name).map(|gate| gate.adjoint()),
+            None => self.custom_gates.get(token).or(self.subcircuits.get(token).map(|subcircuit| &subcircuit.gate)).cloned(),
+        }
+    }
 }
 
 impl From<Vec<Vec<&str>>> for UnparsedCircuit {
@@ -19,7 +41,7 @@ impl From<Vec<Vec<&str>>> for UnparsedCircuit {
             }
             new_circuit.push(new_row);
         }
-        UnparsedCircuit { circuit: new_circuit }
+        UnparsedCircuit::from(new_circuit)
     }
 }
 
@@ -28,6 +50,15 @@ pub struct ParsedCircuit {
     pub circuit: Vec<GatesInTimeStep>,
 }
 
+impl ParsedCircuit {
+    // Every qubit is covered by exactly one gate in the first time step, so it tells the register size
+    pub fn no_of_qubits(&self) -> usize {
+        self.circuit
+            .first()
+            .map_or(0, |step| step.gates.iter().map(|gate| gate.qubits.len()).sum())
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct EntangledQubitGroup {
     pub qubits: Vec<usize>,
@@ -39,14 +70,14 @@ pub struct EntangledQubitGroupsInTimeStep {
 }
 
 impl EntangledQubitGroupsInTimeStep {
-    pub fn combine_entangled_groups(self, mut group1: EntangledQubitGroup, group2: EntangledQubitGroup) -> EntangledQubitGroupsInTimeStep {
+    pub fn combine_entangled_groups(self, mut group1: EntangledQubitGroup, group2: EntangledQubitGroup, step: usize) -> Result<EntangledQubitGroupsInTimeStep, QuantumCircuitError> {
         let mut index = 0;
         let mut return_list: EntangledQubitGroupsInTimeStep = EntangledQubitGroupsInTimeStep { groups: vec![] };
         while index < self.groups.len() {
             let group = self.groups[index].clone();
             if group == group1 {
-                if self.groups[index + 1] != group2 {
-                    panic!("invalid circuit");
+                if self.groups.get(index + 1) != Some(&group2) {
+                    return Err(QuantumCircuitError::InconsistentEntanglement { step });
                 }
                 group1.qubits.append(&mut group2.qubits.clone());
                 return_list.groups.push(group1.clone());
@@ -56,30 +87,44 @@ impl EntangledQubitGroupsInTimeStep {
             }
             index += 1;
         }
-        return_list
+        Ok(return_list)
     }
 }
 
 #[derive(Debug, PartialEq, Eq)]
+#[allow(non_camel_case_types)]
 pub enum PartOfMultiGate { control_down, swap }
 
-pub fn build_circuit_from_data(grid: UnparsedCircuit) -> ParsedCircuit {
-    let gates_parsed_individually: ParsedCircuit = parse_gates_individually(grid);
-    let entangled_groups: Vec<EntangledQubitGroupsInTimeStep> = entangle_qubits(gates_parsed_individually.clone());
+pub fn build_circuit_from_data(grid: UnparsedCircuit) -> Result<ParsedCircuit, QuantumCircuitError> {
+    let gates_parsed_individually: ParsedCircuit = parse_gates_individually(grid)?;
+    let entangled_groups: Vec<EntangledQubitGroupsInTimeStep> = entangle_qubits(gates_parsed_individually.clone())?;
     combine_gates_where_necessary(gates_parsed_individually.clone(), entangled_groups)
 }
 
-fn parse_gates_individually(unparsed_circuit: UnparsedCircuit) -> ParsedCircuit {
+// Every gate of the grid as its own wrapper, with the wires as identities
+pub fn parse_gates_individually(unparsed_circuit: UnparsedCircuit) -> Result<ParsedCircuit, QuantumCircuitError> {
+    let no_of_steps = match unparsed_circuit.circuit.first() {
+        Some(row) => row.len(),
+        None => return Err(QuantumCircuitError::TooFewQubits),
+    };
+    if let Some((qubit, row)) = unparsed_circuit.circuit.iter().enumerate().find(|(_, row)| row.len() != no_of_steps) {
+        return Err(QuantumCircuitError::InvalidRowLength { qubit, expected: no_of_steps, found: row.len() });
+    }
+    if no_of_steps == 0 {
+        return Err(QuantumCircuitError::TooFewSteps);
+    }
+
     let mut initial_gates = vec![];
-    for step in 0..unparsed_circuit.circuit[0].len() {
-        let gates_in_time_step = parse_time_step_individual_gates(unparsed_circuit.clone(), step);
+    for step in 0..no_of_steps {
+        let gates_in_time_step = parse_time_step_individual_gates(&unparsed_circuit, step)?;
         initial_gates.push(gates_in_time_step);
     }
-    ParsedCircuit { circuit: initial_gates }
+    Ok(ParsedCircuit { circuit: initial_gates })
 }
 
-fn entangle_qubits(input: ParsedCircuit) -> Vec<EntangledQubitGroupsInTimeStep> {
-    let mut qubit_groups: Vec<EntangledQubitGroupsInTimeStep> = vec![EntangledQubitGroupsInTimeStep { groups: input.circuit[0].gates.iter().map(|gate| EntangledQubitGroup { qubits: gate.qubits.clone() }).collect() }];
+fn entangle_qubits(input: ParsedCircuit) -> Result<Vec<EntangledQubitGroupsInTimeStep>, QuantumCircuitError> {
+    let first_step = input.circuit.first().ok_or(QuantumCircuitError::TooFewSteps)?;
+    let mut qubit_groups: Vec<EntangledQubitGroupsInTimeStep> = vec![EntangledQubitGroupsInTimeStep { groups: first_step.gates.iter().map(|gate| EntangledQubitGroup { qubits: gate.qubits.clone() }).collect() }];
 
     for step in 1..input.circuit.len() {
         let previous_entangled_qubits: EntangledQubitGroupsInTimeStep = qubit_groups[step - 1].clone();
@@ -88,107 +133,168 @@ fn entangle_qubits(input: ParsedCircuit) -> Vec<EntangledQubitGroupsInTimeStep>
         let mut gate_index = 0;
         while gate_index < input.circuit[step].gates.len() {
             let gate: QuantumGateWrapper = input.circuit[step].gates[gate_index].clone();
-            if gate.qubits.len() == 2 {
-                let first_qubit_group = find_qubits_that_are_entangled_to_qubit(gate.qubits[0], previous_entangled_qubits.clone());
-                let second_qubit_group = find_qubits_that_are_entangled_to_qubit(gate.qubits[1], previous_entangled_qubits.clone());
-
-                if first_qubit_group != second_qubit_group {
-                    current_entangled_qubits = current_entangled_qubits.combine_entangled_groups(first_qubit_group.clone(), second_qubit_group.clone());
+            // The qubits of a gate are consecutive rows, so each one is merged into the group of the first,
+            // looking the groups up in the current step as they grow with every merge
+            for qubit in gate.qubits.iter().skip(1) {
+                let first_qubit_group = find_qubits_that_are_entangled_to_qubit(gate.qubits[0], current_entangled_qubits.clone(), step)?;
+                let other_qubit_group = find_qubits_that_are_entangled_to_qubit(*qubit, current_entangled_qubits.clone(), step)?;
+
+                if first_qubit_group != other_qubit_group {
+                    current_entangled_qubits = current_entangled_qubits.combine_entangled_groups(first_qubit_group.clone(), other_qubit_group.clone(), step)?;
                 }
             }
             gate_index += 1;
         }
         qubit_groups.push(current_entangled_qubits);
     }
-    qubit_groups
+    Ok(qubit_groups)
 }
 
-fn combine_gates_where_necessary(preparsed_circuit: ParsedCircuit, entangled_groups: Vec<EntangledQubitGroupsInTimeStep>) -> ParsedCircuit {
-    let mut updated_steps = vec![preparsed_circuit.circuit[0].clone()];
+fn combine_gates_where_necessary(preparsed_circuit: ParsedCircuit, entangled_groups: Vec<EntangledQubitGroupsInTimeStep>) -> Result<ParsedCircuit, QuantumCircuitError> {
+    let mut updated_steps = vec![preparsed_circuit.circuit.first().ok_or(QuantumCircuitError::TooFewSteps)?.clone()];
 
     for (step_no, step) in preparsed_circuit.circuit.iter().enumerate().skip(1) {
-        let entangled_groups = entangled_groups[step_no].clone();
-        updated_steps.push(combine_gates_in_time_step(step.clone(), entangled_groups));
+        let entangled_groups = entangled_groups.get(step_no).ok_or(QuantumCircuitError::InconsistentEntanglement { step: step_no })?.clone();
+        updated_steps.push(combine_gates_in_time_step(step.clone(), entangled_groups, step_no)?);
     }
-    ParsedCircuit { circuit: updated_steps }
+    Ok(ParsedCircuit { circuit: updated_steps })
+}
+
+// One operation in a step of the grid, with the parts of controls and swaps paired up
+#[derive(Debug, Clone, PartialEq)]
+pub enum GridOperation {
+    // A single qubit gate, including wires
+    Single { token: String, gate: QuantumGate, qubit: usize },
+    // Controls on every row from control down to the gate directly underneath them,
+    // which starts at the target and may span several rows
+    Controlled { token: String, gate: QuantumGate, control: usize, target: usize },
+    // Two swaps, on the given qubit and the one underneath it
+    Swap { upper: usize },
+    // A custom gate on more than one qubit, with its name on the given qubit and the rows underneath it
+    Custom { token: String, gate: QuantumGate, upper: usize },
 }
 
-fn parse_time_step_individual_gates(unparsed_circuit: UnparsedCircuit, step: usize) -> GatesInTimeStep {
-    let mut current_gates: GatesInTimeStep = GatesInTimeStep { gates: Vec::new() };
+// Walk a step from the top row down, pairing every control and swap with the cell underneath it.
+// Everything that turns the grid into something else should go through this, so that it agrees with the parser
+pub fn operations_in_step(unparsed_circuit: &UnparsedCircuit, step: usize) -> Result<Vec<GridOperation>, QuantumCircuitError> {
+    let mut operations: Vec<GridOperation> = Vec::new();
     let mut qubit_no = 0;
     while qubit_no < unparsed_circuit.circuit.len() {
-        let unparsed_gate = unparsed_circuit.circuit[qubit_no][step].as_str();
-        let parsed_gate_or_part_of_multigate: Either<QuantumGate, PartOfMultiGate> = parse_gate(unparsed_gate);
+        let parsed_gate_or_part_of_multigate: Either<QuantumGate, PartOfMultiGate> = parse_gate_at(unparsed_circuit, qubit_no, step)?;
+        let token = unparsed_circuit.circuit[qubit_no][step].clone();
 
         if parsed_gate_or_part_of_multigate.is_left() {
             let parsed_gate = parsed_gate_or_part_of_multigate.unwrap_left();
-            current_gates.gates.push(QuantumGateWrapper { qubits: vec![qubit_no], gate: parsed_gate });
+            if parsed_gate.size > 1 {
+                let rows = qubit_no..qubit_no + parsed_gate.size;
+                if rows.end > unparsed_circuit.circuit.len() || rows.clone().any(|row| unparsed_circuit.circuit[row][step] != token) {
+                    return Err(QuantumCircuitError::IncompleteCustomGate { token, qubit: qubit_no, step, size: parsed_gate.size });
+                }
+                qubit_no += parsed_gate.size - 1;
+                operations.push(GridOperation::Custom { token, gate: parsed_gate, upper: rows.start });
+            } else {
+                operations.push(GridOperation::Single { token, gate: parsed_gate, qubit: qubit_no });
+            }
         } 
         else 
         {
             let gate_part = parsed_gate_or_part_of_multigate.unwrap_right();
             if gate_part == PartOfMultiGate::control_down {
-                let gate_underneath = parse_gate(unparsed_circuit.circuit[qubit_no + 1][step].as_str()).unwrap_left();
-                let controlled_gate = QuantumGate::c_down(gate_underneath);
-                current_gates.gates.push(QuantumGateWrapper { qubits: vec![qubit_no, qubit_no + 1], gate: controlled_gate });
-                qubit_no += 1;
-            } else if gate_part == PartOfMultiGate::swap {
-                let object_underneath = parse_gate(unparsed_circuit.circuit[qubit_no + 1][step].as_str());
-                if(object_underneath.is_right()){
-                    current_gates.gates.push(QuantumGateWrapper { qubits: vec![qubit_no, qubit_no + 1], gate: QuantumGate::swap_gate() });
+                // Controls stacked on top of each other all control the gate underneath the last one
+                let control = qubit_no;
+                while qubit_no + 1 < unparsed_circuit.circuit.len() && unparsed_circuit.circuit[qubit_no + 1][step] == "C_down" {
                     qubit_no += 1;
+                }
+                if qubit_no + 1 >= unparsed_circuit.circuit.len() {
+                    return Err(QuantumCircuitError::ControlOnLastQubit { token, qubit: qubit_no, step });
+                }
+                let target = unparsed_circuit.circuit[qubit_no + 1][step].clone();
+                let gate_underneath = match parse_gate_at(unparsed_circuit, qubit_no + 1, step)? {
+                    Left(gate) if target != "I" => gate,
+                    _ => return Err(QuantumCircuitError::InvalidControlTarget { token, qubit: qubit_no, step, target }),
+                };
+                // A controlled gate on several qubits is repeated on all of its rows, like the gate on its own
+                let rows = qubit_no + 1..qubit_no + 1 + gate_underneath.size;
+                if rows.end > unparsed_circuit.circuit.len() || rows.clone().any(|row| unparsed_circuit.circuit[row][step] != target) {
+                    return Err(QuantumCircuitError::IncompleteCustomGate { token: target, qubit: qubit_no + 1, step, size: gate_underneath.size });
+                }
+                qubit_no += gate_underneath.size;
+                operations.push(GridOperation::Controlled { token: target, gate: gate_underneath, control, target: rows.start });
+            } else if gate_part == PartOfMultiGate::swap {
+                let object_underneath = if qubit_no + 1 < unparsed_circuit.circuit.len() {
+                    Some(parse_gate_at(unparsed_circuit, qubit_no + 1, step)?)
                 } else {
-                    // Found incomplete swap gate, replacing with identity gate
-                    current_gates.gates.push(QuantumGateWrapper { qubits: vec![qubit_no], gate: QuantumGate::i_gate() });
+                    None
+                };
+                if !matches!(object_underneath, Some(Right(PartOfMultiGate::swap))) {
+                    return Err(QuantumCircuitError::UnpairedSwap { token, qubit: qubit_no, step });
                 }
+                operations.push(GridOperation::Swap { upper: qubit_no });
+                qubit_no += 1;
             }
         }
         qubit_no += 1;
     }
-    current_gates
+    Ok(operations)
 }
 
-fn combine_gates_in_time_step(step: GatesInTimeStep, entangled_groups: EntangledQubitGroupsInTimeStep) -> GatesInTimeStep {
+fn parse_time_step_individual_gates(unparsed_circuit: &UnparsedCircuit, step: usize) -> Result<GatesInTimeStep, QuantumCircuitError> {
+    let gates = operations_in_step(unparsed_circuit, step)?
+        .into_iter()
+        .map(|operation| match operation {
+            GridOperation::Single { gate, qubit, .. } => QuantumGateWrapper { qubits: vec![qubit], gate },
+            GridOperation::Controlled { gate, control, target, .. } => {
+                QuantumGateWrapper { qubits: (control..target + gate.size).collect(), gate: QuantumGate::multi_controlled(gate, target - control) }
+            }
+            GridOperation::Swap { upper } => QuantumGateWrapper { qubits: vec![upper, upper + 1], gate: QuantumGate::swap_gate() },
+            GridOperation::Custom { gate, upper, .. } => QuantumGateWrapper { qubits: (upper..upper + gate.size).collect(), gate },
+        })
+        .collect();
+    Ok(GatesInTimeStep { gates })
+}
+
+fn combine_gates_in_time_step(step: GatesInTimeStep, entangled_groups: EntangledQubitGroupsInTimeStep, step_no: usize) -> Result<GatesInTimeStep, QuantumCircuitError> {
     let mut gate_index = 1;
     let mut current_step: Vec<QuantumGateWrapper> = vec![];
 
-    let mut previous_entangled_group_of_operand = find_qubits_that_are_entangled_to_qubit(step.gates[0].qubits[0], entangled_groups.clone());
-    let mut previous_gate = step.gates[0].clone();
+    let first_gate = step.gates.first().ok_or(QuantumCircuitError::InconsistentEntanglement { step: step_no })?;
+    let mut previous_entangled_group_of_operand = find_qubits_that_are_entangled_to_qubit(first_gate.qubits[0], entangled_groups.clone(), step_no)?;
+    let mut previous_gate = first_gate.clone();
     current_step.push(previous_gate.clone());
 
     while gate_index < step.gates.len() {
         let gate = step.gates[gate_index].clone();
         let operand_in_gate = gate.qubits[0];
-        let entangled_group_of_operand = find_qubits_that_are_entangled_to_qubit(operand_in_gate, entangled_groups.clone());
+        let entangled_group_of_operand = find_qubits_that_are_entangled_to_qubit(operand_in_gate, entangled_groups.clone(), step_no)?;
 
-        let gate_to_push;
-        if entangled_group_of_operand == previous_entangled_group_of_operand {
+        let gate_to_push = if entangled_group_of_operand == previous_entangled_group_of_operand {
             previous_gate.qubits.append(&mut gate.qubits.clone());
             let large_gate = QuantumGateWrapper { gate: previous_gate.gate.kronecker(gate.gate), qubits: previous_gate.qubits };
             current_step.pop();
-            gate_to_push = large_gate;
+            large_gate
         } else {
-            gate_to_push = gate.clone();
-        }
+            gate.clone()
+        };
         current_step.push(gate_to_push.clone());
         previous_gate = gate_to_push.clone();
-        previous_entangled_group_of_operand = find_qubits_that_are_entangled_to_qubit(gate_to_push.qubits[0], entangled_groups.clone());
+        previous_entangled_group_of_operand = find_qubits_that_are_entangled_to_qubit(gate_to_push.qubits[0], entangled_groups.clone(), step_no)?;
 
         gate_index += 1;
     }
 
-    GatesInTimeStep { gates: current_step }
+    Ok(GatesInTimeStep { gates: current_step })
 }
 
-fn find_qubits_that_are_entangled_to_qubit(qubit: usize, entangled_qubit_groups: EntangledQubitGroupsInTimeStep) -> EntangledQubitGroup {
-    for (_index, entangled_group) in entangled_qubit_groups.groups.iter().enumerate() {
+fn find_qubits_that_are_entangled_to_qubit(qubit: usize, entangled_qubit_groups: EntangledQubitGroupsInTimeStep, step: usize) -> Result<EntangledQubitGroup, QuantumCircuitError> {
+    for entangled_group in entangled_qubit_groups.groups.iter() {
         if entangled_group.qubits.contains(&qubit) {
-            return entangled_group.clone();
+            return Ok(entangled_group.clone());
         }
     }
-    panic!("Qubit not found");
+    Err(QuantumCircuitError::InconsistentEntanglement { step })
 }
 
+#[allow(dead_code, clippy::unused_enumerate_index)]
 fn find_gate_that_acts_upon_qubit(qubit: usize, gates_in_time_step: GatesInTimeStep) -> QuantumGateWrapper {
     for (_gate_no, gate) in gates_in_time_step.gates.iter().enumerate() {
         for (_operand_no, operand) in gate.qubits.iter().enumerate() {
@@ -200,26 +306,56 @@ fn find_gate_that_acts_upon_qubit(qubit: usize, gates_in_time_step: GatesInTimeS
     panic!("Qubit not found in any of the GateWrappers");
 }
 
-fn parse_gate(gate_string: &str) -> Either<QuantumGate, PartOfMultiGate> {
+// Parse the token at the given position in the grid, reporting unknown tokens with their position
+fn parse_gate_at(unparsed_circuit: &UnparsedCircuit, qubit: usize, step: usize) -> Result<Either<QuantumGate, PartOfMultiGate>, QuantumCircuitError> {
+    let token = unparsed_circuit.circuit[qubit][step].as_str();
+    parse_gate(token, unparsed_circuit).ok_or_else(|| QuantumCircuitError::UnknownGate { token: token.to_string(), qubit, step })
+}
+
+fn parse_gate(gate_string: &str, unparsed_circuit: &UnparsedCircuit) -> Option<Either<QuantumGate, PartOfMultiGate>> {
     match gate_string {
-        "I" => Left(QuantumGate::i_gate()),
-        "H" => Left(QuantumGate::h_gate()),
-        "X" => Left(QuantumGate::x_gate()),
-        "Y" => Left(QuantumGate::y_gate()),
-        "Z" => Left(QuantumGate::z_gate()),
-        "T" => Left(QuantumGate::t_gate()),
-        "S" => Left(QuantumGate::s_gate()),
-        "Swap" => Right(PartOfMultiGate::swap),
-        "C_down" => Right(PartOfMultiGate::control_down),
-        _ => panic!("Invalid gate"),
+        "I" => Some(Left(QuantumGate::i_gate())),
+        "H" => Some(Left(QuantumGate::h_gate())),
+        "X" => Some(Left(QuantumGate::x_gate())),
+        "Y" => Some(Left(QuantumGate::y_gate())),
+        "Z" => Some(Left(QuantumGate::z_gate())),
+        "T" => Some(Left(QuantumGate::t_gate())),
+        "S" => Some(Left(QuantumGate::s_gate())),
+        "T†" => Some(Left(QuantumGate::tdg_gate())),
+        "S†" => Some(Left(QuantumGate::sdg_gate())),
+        "SX" => Some(Left(QuantumGate::sx_gate())),
+        "SX†" => Some(Left(QuantumGate::sxdg_gate())),
+        "Swap" => Some(Right(PartOfMultiGate::swap)),
+        "C_down" => Some(Right(PartOfMultiGate::control_down)),
+        _ => parse_parameterised_gate(gate_string).or_else(|| unparsed_circuit.named_gate(gate_string)).map(Left),
+    }
+}
+
+// Parse tokens with angles, such as "Rz(pi/4)" or "U(pi/2,0,pi)"
+pub fn parse_parameterised_gate(gate_string: &str) -> Option<QuantumGate> {
+    let (name, angles) = parse_parameterised_token(gate_string)?;
+    match (name, angles.as_slice()) {
+        ("Rx", [theta]) => Some(QuantumGate::rx_gate(*theta)),
+        ("Ry", [theta]) => Some(QuantumGate::ry_gate(*theta)),
+        ("Rz", [theta]) => Some(QuantumGate::rz_gate(*theta)),
+        ("P", [lambda]) => Some(QuantumGate::phase_gate(*lambda)),
+        ("U", [theta, phi, lambda]) => Some(QuantumGate::u_gate(*theta, *phi, *lambda)),
+        _ => None,
     }
 }
 
+// Split a token with angles into its name and the values of its angles
+pub fn parse_parameterised_token(gate_string: &str) -> Option<(&str, Vec<f64>)> {
+    let (name, rest) = gate_string.split_once('(')?;
+    let arguments = rest.strip_suffix(')')?;
+    let angles: Vec<f64> = arguments.split(',').map(parse_angle).collect::<Option<Vec<f64>>>()?;
+    Some((name, angles))
+}
+
 #[cfg(test)]
+#[allow(non_snake_case, unused_variables)]
 mod tests {
     use super::*;
-    use crate::simulation::quantum_state::QuantumState;
-    use ndarray::Array2;
 
     #[test]
     fn entangle_testing() {
@@ -228,8 +364,8 @@ mod tests {
 
         let grid = vec![q0, q1];
 
-        let circuit = parse_gates_individually(UnparsedCircuit::from(grid));
-        let entangled_groups = entangle_qubits(circuit);
+        let circuit = parse_gates_individually(UnparsedCircuit::from(grid)).unwrap();
+        let entangled_groups = entangle_qubits(circuit).unwrap();
 
         let expected_result = vec![EntangledQubitGroupsInTimeStep {
             groups: vec![
@@ -256,13 +392,13 @@ mod tests {
 
         let grid = vec![q0, q1];
 
-        let circuit = build_circuit_from_data(UnparsedCircuit::from(grid));
+        let circuit = build_circuit_from_data(UnparsedCircuit::from(grid)).unwrap();
     }
 
     #[test]
     fn parse_swap_circuit() {
         let incoming_data = vec![vec!["X", "Swap"], vec!["I", "Swap"]];
-        let result = build_circuit_from_data(UnparsedCircuit::from(incoming_data));
+        let result = build_circuit_from_data(UnparsedCircuit::from(incoming_data)).unwrap();
 
         let expected_result = ParsedCircuit {
             circuit: vec![  GatesInTimeStep { gates: vec![QuantumGateWrapper { gate: QuantumGate::x_gate(), qubits: vec![0] },
@@ -278,7 +414,7 @@ mod tests {
         let q0 = vec!["X"];
         let grid = vec![q0];
 
-        let circuit: ParsedCircuit = build_circuit_from_data(UnparsedCircuit::from(grid));
+        let circuit: ParsedCircuit = build_circuit_from_data(UnparsedCircuit::from(grid)).unwrap();
 
 
         let expected_result: ParsedCircuit = ParsedCircuit {
@@ -294,7 +430,7 @@ mod tests {
         let q0 = vec!["X", "H"];
         let grid = vec![q0];
 
-        let circuit = build_circuit_from_data(UnparsedCircuit::from(grid));
+        let circuit = build_circuit_from_data(UnparsedCircuit::from(grid)).unwrap();
         let expected_result = ParsedCircuit {
             circuit: vec![
                 vec![QuantumGateWrapper { gate: QuantumGate::x_gate(), qubits: vec![0] }],
@@ -312,7 +448,7 @@ mod tests {
 
         let grid = vec![q0, q1];
 
-        let circuit = parse_gates_individually(UnparsedCircuit::from(grid));
+        let circuit = parse_gates_individually(UnparsedCircuit::from(grid)).unwrap();
 
         let expected_result = ParsedCircuit {
             circuit: vec![
@@ -337,8 +473,8 @@ mod tests {
 
         let grid = vec![q0, q1];
 
-        let circuit = parse_gates_individually(UnparsedCircuit::from(grid));
-        let entangled_groups = entangle_qubits(circuit);
+        let circuit = parse_gates_individually(UnparsedCircuit::from(grid)).unwrap();
+        let entangled_groups = entangle_qubits(circuit).unwrap();
         let expected_result = vec![EntangledQubitGroupsInTimeStep {
             groups: vec![
                 EntangledQubitGroup { qubits: vec![0] },
@@ -360,7 +496,7 @@ mod tests {
 
         let grid = vec![q0, q1];
 
-        let circuit = build_circuit_from_data(UnparsedCircuit::from(grid));
+        let circuit = build_circuit_from_data(UnparsedCircuit::from(grid)).unwrap();
 
         let expected_result = ParsedCircuit {
             circuit: vec![
@@ -383,7 +519,7 @@ mod tests {
             vec!["I", "I", "X"],
         ];
 
-        let circuit = parse_gates_individually(UnparsedCircuit::from(grid));
+        let circuit = parse_gates_individually(UnparsedCircuit::from(grid)).unwrap();
 
         let expected_result = ParsedCircuit {
             circuit:
@@ -418,7 +554,7 @@ mod tests {
         ];
 
         //let circuit = build_circuit_from_data(UnparsedCircuit::from(grid));
-        let entangled_qubits = entangle_qubits(parse_gates_individually(UnparsedCircuit::from(grid)));
+        let entangled_qubits = entangle_qubits(parse_gates_individually(UnparsedCircuit::from(grid)).unwrap()).unwrap();
         let expected_result = vec![EntangledQubitGroupsInTimeStep {
             groups: vec![
                 EntangledQubitGroup { qubits: vec![0] },
@@ -449,7 +585,7 @@ mod tests {
             vec!["I", "I", "X"],
         ];
 
-        let circuit = build_circuit_from_data(UnparsedCircuit::from(grid));
+        let circuit = build_circuit_from_data(UnparsedCircuit::from(grid)).unwrap();
 
         let expected_result = ParsedCircuit {
             circuit:
@@ -479,7 +615,7 @@ mod tests {
             vec!["I", "Swap", "Z"],
         ];
 
-        let circuit = build_circuit_from_data(UnparsedCircuit::from(grid));
+        let circuit = build_circuit_from_data(UnparsedCircuit::from(grid)).unwrap();
 
         let expected_result = ParsedCircuit {
             circuit:
@@ -511,24 +647,62 @@ mod tests {
 
         let circuit = build_circuit_from_data(UnparsedCircuit::from(grid));
 
-        let expected_result = ParsedCircuit {
-            circuit:
-            vec![
-                GatesInTimeStep {
-                    gates: vec![QuantumGateWrapper { gate: QuantumGate::x_gate(), qubits: vec![0] },
-                                QuantumGateWrapper { gate: QuantumGate::i_gate(), qubits: vec![1] },
-                                QuantumGateWrapper { gate: QuantumGate::h_gate(), qubits: vec![2] },
-                                QuantumGateWrapper { gate: QuantumGate::i_gate(), qubits: vec![3] },]
-                },
-                GatesInTimeStep {
-                    gates: vec![QuantumGateWrapper { gate: QuantumGate::swap_gate(), qubits: vec![0,1] },
-                                QuantumGateWrapper { gate: QuantumGate::i_gate(), qubits: vec![2] },
-                                QuantumGateWrapper { gate: QuantumGate::h_gate(), qubits: vec![3] },]},
-            ]
-        };
+        assert_eq!(circuit, Err(QuantumCircuitError::UnpairedSwap { token: "Swap".to_string(), qubit: 2, step: 1 }));
+    }
 
-        println!("{:?}", circuit);
+    #[test]
+    fn parse_parameterised_gates() {
+        let grid = vec![vec!["Rz(pi/2)", "C_down"], vec!["U(pi, 0, pi)", "P(-pi/4)"]];
 
-        assert_eq!(circuit, expected_result);
+        let circuit = parse_gates_individually(UnparsedCircuit::from(grid)).unwrap();
+
+        assert_eq!(circuit.circuit[0].gates[0].gate, QuantumGate::rz_gate(std::f64::consts::PI / 2.0));
+        assert_eq!(circuit.circuit[0].gates[1].gate, QuantumGate::u_gate(std::f64::consts::PI, 0.0, std::f64::consts::PI));
+        assert_eq!(circuit.circuit[1].gates[0].gate, QuantumGate::c_down(QuantumGate::phase_gate(-std::f64::consts::PI / 4.0)));
+        assert_eq!(parse_parameterised_gate("Rz(pi, 0)"), None);
+        assert_eq!(parse_parameterised_gate("Rq(pi)"), None);
+    }
+
+    #[test]
+    fn parse_control_above_wire() {
+        let grid = vec![vec!["C_down"], vec!["I"]];
+
+        let circuit = build_circuit_from_data(UnparsedCircuit::from(grid));
+
+        assert_eq!(circuit, Err(QuantumCircuitError::InvalidControlTarget { token: "C_down".to_string(), qubit: 0, step: 0, target: "I".to_string() }));
+    }
+
+    #[test]
+    fn parse_custom_gates() {
+        let grid = UnparsedCircuit {
+            circuit: vec![
+                vec!["H".to_string(), "I".to_string()],
+                vec!["Toffoli".to_string(), "C_down".to_string()],
+                vec!["Toffoli".to_string(), "Flip".to_string()],
+                vec!["Toffoli".to_string(), "I".to_string()],
+            ],
+            custom_gates: BTreeMap::from([("Toffoli".to_string(), QuantumGate::ccnot_gate()), ("Flip".to_string(), QuantumGate::x_gate())]),
+            ..UnparsedCircuit::default()
+        };
+
+        let circuit = build_circuit_from_data(grid.clone()).unwrap();
+
+        assert_eq!(
+            circuit.circuit[0].gates,
+            vec![QuantumGateWrapper { gate: QuantumGate::h_gate(), qubits: vec![0] }, QuantumGateWrapper { gate: QuantumGate::ccnot_gate(), qubits: vec![1, 2, 3] }]
+        );
+        // The qubits of the custom gate stay entangled, so the wire underneath the controlled gate joins it
+        assert_eq!(
+            circuit.circuit[1].gates[1],
+            QuantumGateWrapper { gate: QuantumGate::c_down(QuantumGate::x_gate()).kronecker(QuantumGate::i_gate()), qubits: vec![1, 2, 3] }
+        );
+        assert_eq!(entangle_qubits(circuit).unwrap()[1].groups, vec![EntangledQubitGroup { qubits: vec![0] }, EntangledQubitGroup { qubits: vec![1, 2, 3] }]);
+
+        let mut incomplete = grid;
+        incomplete.circuit[3][0] = "I".to_string();
+        assert_eq!(
+            build_circuit_from_data(incomplete),
+            Err(QuantumCircuitError::IncompleteCustomGate { token: "Toffoli".to_string(), qubit: 1, step: 0, size: 3 })
+        );
     }
 }
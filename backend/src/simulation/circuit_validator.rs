@@ -4,53 +4,251 @@
 // The number of rows (qubits) in the circuit must be between 1 and 6, inclusive
 // Atleast one column must be present
 
-use serde::Serialize;
-use crate::simulation::circuit_parser::{UnparsedCircuit};
+use serde::ser::SerializeStruct;
+use serde::{Serialize, Serializer};
+use crate::simulation::circuit_parser::{parse_parameterised_gate, UnparsedCircuit};
+use crate::simulation::custom_gates::validate_custom_gate;
 
-#[derive(Debug, PartialEq, Serialize)]
+pub const MAX_QUBITS: usize = 6;
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum QuantumCircuitError {
     TooManyQubits,
     TooFewQubits,
-    InvalidGate,
-    InvalidRowLength,
+    TooFewSteps,
+    // A row that does not have as many steps as the first row
+    InvalidRowLength { qubit: usize, expected: usize, found: usize },
+    // A token in the grid that is not a known gate
+    UnknownGate { token: String, qubit: usize, step: usize },
+    // A control on the last qubit, with no gate underneath it to control
+    ControlOnLastQubit { token: String, qubit: usize, step: usize },
+    // A control whose cell underneath is a wire, another control or part of a swap
+    InvalidControlTarget { token: String, qubit: usize, step: usize, target: String },
+    // A swap without a second swap directly underneath or above it
+    UnpairedSwap { token: String, qubit: usize, step: usize },
+    // A custom gate on several qubits whose name is not repeated on all the rows underneath it
+    IncompleteCustomGate { token: String, qubit: usize, step: usize, size: usize },
+    // A custom gate whose name or matrix can not be used
+    InvalidCustomGate { name: String, reason: String },
+    // A sub-circuit whose name or grid can not be used
+    InvalidSubCircuit { name: String, reason: String },
+    // A valid gate that the format being exported to has no equivalent for
+    UnsupportedGate { token: String, qubit: usize, step: usize },
+    // Single-qubit gates that the gates of a basis can not get within the tolerance of
+    ApproximationFailed { qubit: usize, step: usize, tolerance: f64, distance: f64 },
+    // A qubit index that is outside of the register
+    QubitOutOfRange { qubit: usize, no_of_qubits: usize },
+    // A step index that is outside of the circuit
+    StepOutOfRange { step: usize, no_of_steps: usize },
+    // A gate that is given the same qubit more than once, e.g. as control and target
+    RepeatedQubit { qubit: usize },
+    // A gate on a qubit that has already been measured
+    GateAfterMeasurement { qubit: usize },
+    // The entangled groups the parser tracks do not line up with the gates of a step
+    InconsistentEntanglement { step: usize },
+    // A gate applied to a state or positions of a different size
+    GateSizeMismatch { gate_size: usize, state_size: usize },
+    // A basis state with a bit that is not 0 or 1
+    InvalidBasisState,
+}
+
+impl QuantumCircuitError {
+    // A stable identifier for the kind of error, for clients to match on
+    pub fn code(&self) -> &'static str {
+        match self {
+            QuantumCircuitError::TooManyQubits => "too_many_qubits",
+            QuantumCircuitError::TooFewQubits => "too_few_qubits",
+            QuantumCircuitError::TooFewSteps => "too_few_steps",
+            QuantumCircuitError::InvalidRowLength { .. } => "invalid_row_length",
+            QuantumCircuitError::UnknownGate { .. } => "unknown_gate",
+            QuantumCircuitError::ControlOnLastQubit { .. } => "control_on_last_qubit",
+            QuantumCircuitError::InvalidControlTarget { .. } => "invalid_control_target",
+            QuantumCircuitError::UnpairedSwap { .. } => "unpaired_swap",
+            QuantumCircuitError::IncompleteCustomGate { .. } => "incomplete_custom_gate",
+            QuantumCircuitError::InvalidCustomGate { .. } => "invalid_custom_gate",
+            QuantumCircuitError::InvalidSubCircuit { .. } => "invalid_subcircuit",
+            QuantumCircuitError::UnsupportedGate { .. } => "unsupported_gate",
+            QuantumCircuitError::ApproximationFailed { .. } => "approximation_failed",
+            QuantumCircuitError::QubitOutOfRange { .. } => "qubit_out_of_range",
+            QuantumCircuitError::StepOutOfRange { .. } => "step_out_of_range",
+            QuantumCircuitError::RepeatedQubit { .. } => "repeated_qubit",
+            QuantumCircuitError::GateAfterMeasurement { .. } => "gate_after_measurement",
+            QuantumCircuitError::InconsistentEntanglement { .. } => "inconsistent_entanglement",
+            QuantumCircuitError::GateSizeMismatch { .. } => "gate_size_mismatch",
+            QuantumCircuitError::InvalidBasisState => "invalid_basis_state",
+        }
+    }
+
+    // A sentence explaining the error to the person who built the circuit
+    pub fn message(&self) -> String {
+        match self {
+            QuantumCircuitError::TooManyQubits => "The circuit has more than 6 qubits".to_string(),
+            QuantumCircuitError::TooFewQubits => "The circuit has no qubits".to_string(),
+            QuantumCircuitError::TooFewSteps => "The circuit has no steps".to_string(),
+            QuantumCircuitError::InvalidRowLength { qubit, expected, found } => format!(
+                "Qubit {} has {} steps, but the first qubit has {}",
+                qubit, found, expected
+            ),
+            QuantumCircuitError::UnknownGate { token, qubit, step } => format!(
+                "'{}' on qubit {} at step {} is not a known gate",
+                token, qubit, step
+            ),
+            QuantumCircuitError::ControlOnLastQubit { token, qubit, step } => format!(
+                "'{}' on qubit {} at step {} is on the last qubit, so there is no gate underneath it to control",
+                token, qubit, step
+            ),
+            QuantumCircuitError::InvalidControlTarget { token, qubit, step, target } => format!(
+                "'{}' on qubit {} at step {} controls '{}' underneath it, which is not a gate that can be controlled",
+                token, qubit, step, target
+            ),
+            QuantumCircuitError::UnpairedSwap { token, qubit, step } => format!(
+                "'{}' on qubit {} at step {} needs a second swap directly underneath or above it",
+                token, qubit, step
+            ),
+            QuantumCircuitError::IncompleteCustomGate { token, qubit, step, size } => format!(
+                "'{}' on qubit {} at step {} is a gate on {} qubits, so it has to fill the {} rows underneath it as well",
+                token, qubit, step, size, size - 1
+            ),
+            QuantumCircuitError::InvalidCustomGate { name, reason } => format!("The custom gate '{}' {}", name, reason),
+            QuantumCircuitError::InvalidSubCircuit { name, reason } => format!("The sub-circuit '{}' {}", name, reason),
+            QuantumCircuitError::UnsupportedGate { token, qubit, step } => format!(
+                "'{}' on qubit {} at step {} can not be exported to this format",
+                token, qubit, step
+            ),
+            QuantumCircuitError::ApproximationFailed { qubit, step, tolerance, distance } => format!(
+                "The gates on qubit {} up to step {} can only be approximated within {:.1e} in this basis, which is more than the tolerance of {:.1e}",
+                qubit, step, distance, tolerance
+            ),
+            QuantumCircuitError::QubitOutOfRange { qubit, no_of_qubits } => format!(
+                "Qubit {} is outside of the register of {} qubits",
+                qubit, no_of_qubits
+            ),
+            QuantumCircuitError::StepOutOfRange { step, no_of_steps } => format!(
+                "Step {} is outside of the circuit of {} steps",
+                step, no_of_steps
+            ),
+            QuantumCircuitError::RepeatedQubit { qubit } => format!("Qubit {} is used more than once by the same gate", qubit),
+            QuantumCircuitError::GateAfterMeasurement { qubit } => format!("Qubit {} has already been measured", qubit),
+            QuantumCircuitError::InconsistentEntanglement { step } => format!(
+                "The gates at step {} could not be matched with the entangled qubits",
+                step
+            ),
+            QuantumCircuitError::GateSizeMismatch { gate_size, state_size } => format!(
+                "A gate for {} qubits was applied to {} qubits",
+                gate_size, state_size
+            ),
+            QuantumCircuitError::InvalidBasisState => "Basis states can only contain the bits 0 and 1".to_string(),
+        }
+    }
+
+    // The row of the offending cell, if the error can be tied to one
+    pub fn qubit(&self) -> Option<usize> {
+        match self {
+            QuantumCircuitError::InvalidRowLength { qubit, .. }
+            | QuantumCircuitError::UnknownGate { qubit, .. }
+            | QuantumCircuitError::ControlOnLastQubit { qubit, .. }
+            | QuantumCircuitError::InvalidControlTarget { qubit, .. }
+            | QuantumCircuitError::UnpairedSwap { qubit, .. }
+            | QuantumCircuitError::IncompleteCustomGate { qubit, .. }
+            | QuantumCircuitError::UnsupportedGate { qubit, .. }
+            | QuantumCircuitError::ApproximationFailed { qubit, .. }
+            | QuantumCircuitError::QubitOutOfRange { qubit, .. }
+            | QuantumCircuitError::RepeatedQubit { qubit }
+            | QuantumCircuitError::GateAfterMeasurement { qubit } => Some(*qubit),
+            _ => None,
+        }
+    }
+
+    // The column of the offending cell, if the error can be tied to one
+    pub fn step(&self) -> Option<usize> {
+        match self {
+            QuantumCircuitError::UnknownGate { step, .. }
+            | QuantumCircuitError::ControlOnLastQubit { step, .. }
+            | QuantumCircuitError::InvalidControlTarget { step, .. }
+            | QuantumCircuitError::UnpairedSwap { step, .. }
+            | QuantumCircuitError::IncompleteCustomGate { step, .. }
+            | QuantumCircuitError::UnsupportedGate { step, .. }
+            | QuantumCircuitError::ApproximationFailed { step, .. }
+            | QuantumCircuitError::StepOutOfRange { step, .. }
+            | QuantumCircuitError::InconsistentEntanglement { step } => Some(*step),
+            _ => None,
+        }
+    }
+
+    // The token in the offending cell, if the error can be tied to one
+    pub fn token(&self) -> Option<&str> {
+        match self {
+            QuantumCircuitError::UnknownGate { token, .. }
+            | QuantumCircuitError::ControlOnLastQubit { token, .. }
+            | QuantumCircuitError::InvalidControlTarget { token, .. }
+            | QuantumCircuitError::UnpairedSwap { token, .. }
+            | QuantumCircuitError::IncompleteCustomGate { token, .. }
+            | QuantumCircuitError::UnsupportedGate { token, .. } => Some(token),
+            QuantumCircuitError::InvalidCustomGate { name, .. } | QuantumCircuitError::InvalidSubCircuit { name, .. } => Some(name),
+            _ => None,
+        }
+    }
+}
+
+// Errors are sent to the client as flat objects so it can highlight the cell they point to
+impl Serialize for QuantumCircuitError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut error = serializer.serialize_struct("QuantumCircuitError", 5)?;
+        error.serialize_field("code", self.code())?;
+        error.serialize_field("message", &self.message())?;
+        error.serialize_field("qubit", &self.qubit())?;
+        error.serialize_field("step", &self.step())?;
+        error.serialize_field("token", &self.token())?;
+        error.end()
+    }
 }
 
 // Ensures that all rows are the same length and that there is at least one row
-// and that the number of rows is between 1 and 6
-pub fn validate_grid_input(grid: &UnparsedCircuit) -> Result<(), QuantumCircuitError> {
+// and that the number of rows is between 1 and 6.
+// Every problem in the grid is collected instead of stopping at the first one
+pub fn validate_grid_input(grid: &UnparsedCircuit) -> Result<(), Vec<QuantumCircuitError>> {
     if grid.circuit.is_empty() {
-        return Err(QuantumCircuitError::TooFewQubits);
+        return Err(vec![QuantumCircuitError::TooFewQubits]);
     }
 
-    if grid.circuit.len() > 6 {
-        return Err(QuantumCircuitError::TooManyQubits);
+    let mut errors: Vec<QuantumCircuitError> = grid
+        .custom_gates
+        .iter()
+        .filter_map(|(name, gate)| validate_custom_gate(name, gate).err())
+        .collect();
+
+    if grid.circuit.len() > MAX_QUBITS {
+        errors.push(QuantumCircuitError::TooManyQubits);
     }
 
     let row_length = grid.circuit[0].len();
-    for row in grid.circuit.clone().iter() {
+    for (qubit, row) in grid.circuit.iter().enumerate() {
         if row.len() != row_length {
-            return Err(QuantumCircuitError::InvalidRowLength);
+            errors.push(QuantumCircuitError::InvalidRowLength { qubit, expected: row_length, found: row.len() });
         }
     }
 
-    // Validate steps (columns)
-    for i in 0..row_length {
-        let mut col: Vec<String> = Vec::new();
-        for row in grid.circuit.clone().iter() {
-            col.push(row[i].clone());
-        }
+    if row_length == 0 {
+        errors.push(QuantumCircuitError::TooFewSteps);
+    }
 
-        match validate_col(&col) {
-            Ok(_) => (),
-            Err(err) => return Err(err),
-        }
+    // Validate steps (columns), only looking at the cells that exist when rows are ragged
+    let longest_row = grid.circuit.iter().map(|row| row.len()).max().unwrap_or(0);
+    for step in 0..longest_row {
+        let col: Vec<Option<&String>> = grid.circuit.iter().map(|row| row.get(step)).collect();
+        errors.append(&mut validate_col(&col, step, grid));
     }
 
-    Ok(())
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
 }
 
 // Ensure that a gate is valid
-fn validate_gate(gate: &str) -> bool {
+// Custom gates and sub-circuits are looked up in the grid they are used in
+fn validate_gate(gate: &str, grid: &UnparsedCircuit) -> bool {
     matches!(
         gate,
         "I" | "H"
@@ -59,26 +257,102 @@ fn validate_gate(gate: &str) -> bool {
             | "Z"
             | "T"
             | "S"
+            | "T†"
+            | "S†"
+            | "SX"
+            | "SX†"
             | "Swap"
             | "C_down"
-    )
+    ) || parse_parameterised_gate(gate).is_some()
+        || grid.named_gate(gate).is_some()
+}
+
+// Ensure that a gate can be the target of a control, which rules out wires and parts of other multi-qubit gates
+pub fn validate_control_target(gate: &str, grid: &UnparsedCircuit) -> bool {
+    validate_gate(gate, grid) && !matches!(gate, "I" | "Swap" | "C_down")
 }
 
-// Validate a row of gates
+// Validate a column of gates
 // Go through each gate and check if it is valid
-// If a multi-qubit gate is present, check if the other parts of the gate are in the same step
-// by adding them to a list and removing them if they are found
-fn validate_col(row: &Vec<String>) -> Result<(), QuantumCircuitError> {
-    for gate in row {
-        if !validate_gate(gate) {
-            return Err(QuantumCircuitError::InvalidGate);
+// If a multi-qubit gate is present, check if the other parts of the gate are in the same step,
+// walking the column top to bottom the same way the parser pairs them
+fn validate_col(col: &[Option<&String>], step: usize, grid: &UnparsedCircuit) -> Vec<QuantumCircuitError> {
+    let mut errors: Vec<QuantumCircuitError> = Vec::new();
+    for (qubit, gate) in col.iter().enumerate() {
+        if let Some(gate) = gate {
+            if !validate_gate(gate, grid) {
+                errors.push(QuantumCircuitError::UnknownGate { token: gate.to_string(), qubit, step });
+            }
         }
     }
 
-    Ok(())
+    // The structure of ragged columns can not be checked, the row lengths are already reported
+    let Some(col) = col.iter().copied().collect::<Option<Vec<&String>>>() else {
+        return errors;
+    };
+
+    let mut qubit = 0;
+    while qubit < col.len() {
+        let token = col[qubit].as_str();
+        match token {
+            "C_down" => {
+                // Stacked controls all control the gate underneath the last one
+                while col.get(qubit + 1).is_some_and(|gate| gate.as_str() == "C_down") {
+                    qubit += 1;
+                }
+                match col.get(qubit + 1).map(|gate| gate.as_str()) {
+                    None => errors.push(QuantumCircuitError::ControlOnLastQubit { token: token.to_string(), qubit, step }),
+                    Some(target) if validate_control_target(target, grid) => {
+                        let size = named_gate_size(target, grid);
+                        errors.extend(validate_named_gate_rows(&col, target, qubit + 1, size, step));
+                        qubit += size;
+                    }
+                    // Unknown tokens underneath are already reported
+                    Some(target) if !validate_gate(target, grid) => qubit += 1,
+                    Some(target) => errors.push(QuantumCircuitError::InvalidControlTarget {
+                        token: token.to_string(),
+                        qubit,
+                        step,
+                        target: target.to_string(),
+                    }),
+                }
+            }
+            "Swap" => match col.get(qubit + 1).map(|gate| gate.as_str()) {
+                Some("Swap") => qubit += 1,
+                _ => errors.push(QuantumCircuitError::UnpairedSwap { token: token.to_string(), qubit, step }),
+            },
+            // A custom gate or sub-circuit on several qubits takes its first row and the ones underneath it
+            _ => {
+                let size = named_gate_size(token, grid);
+                match validate_named_gate_rows(&col, token, qubit, size, step) {
+                    Some(error) => errors.push(error),
+                    None => qubit += size - 1,
+                }
+            }
+        }
+        qubit += 1;
+    }
+
+    errors
+}
+
+// The number of rows a gate takes, which is more than one only for custom gates and sub-circuits
+fn named_gate_size(token: &str, grid: &UnparsedCircuit) -> usize {
+    grid.named_gate(token).map_or(1, |gate| gate.size)
+}
+
+// Ensure that a gate on several qubits has its token on every one of its rows
+fn validate_named_gate_rows(col: &[&String], token: &str, qubit: usize, size: usize, step: usize) -> Option<QuantumCircuitError> {
+    let rows = qubit..qubit + size;
+    if rows.end > col.len() || rows.clone().any(|row| col[row] != token) {
+        Some(QuantumCircuitError::IncompleteCustomGate { token: token.to_string(), qubit, step, size })
+    } else {
+        None
+    }
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use super::*;
 
@@ -90,7 +364,7 @@ mod tests {
         assert_eq!(validate_grid_input(&UnparsedCircuit::from(valid_grid)), Ok(()));
         assert_eq!(
             validate_grid_input(&UnparsedCircuit::from(invalid_grid)),
-            Err(QuantumCircuitError::InvalidRowLength)
+            Err(vec![QuantumCircuitError::InvalidRowLength { qubit: 1, expected: 2, found: 3 }])
         );
     }
 
@@ -99,8 +373,8 @@ mod tests {
         let valid_gate = "I";
         let invalid_gate = "A";
 
-        assert_eq!(validate_gate(valid_gate), true);
-        assert_eq!(validate_gate(invalid_gate), false);
+        assert_eq!(validate_gate(valid_gate, &UnparsedCircuit::default()), true);
+        assert_eq!(validate_gate(invalid_gate, &UnparsedCircuit::default()), false);
     }
 
     #[test]
@@ -116,7 +390,7 @@ mod tests {
         ];
         assert_eq!(
             validate_grid_input(&UnparsedCircuit::from(grid)),
-            Err(QuantumCircuitError::TooManyQubits)
+            Err(vec![QuantumCircuitError::TooManyQubits])
         );
     }
 
@@ -125,7 +399,95 @@ mod tests {
         let grid = vec![vec!["I", "H", "X"], vec!["X", "Y"]];
         assert_eq!(
             validate_grid_input(&UnparsedCircuit::from(grid)),
-            Err(QuantumCircuitError::InvalidRowLength)
+            Err(vec![QuantumCircuitError::InvalidRowLength { qubit: 1, expected: 3, found: 2 }])
+        );
+    }
+
+    #[test]
+    fn test_circuit_without_steps() {
+        let grid: Vec<Vec<&str>> = vec![vec![], vec![]];
+        assert_eq!(
+            validate_grid_input(&UnparsedCircuit::from(grid)),
+            Err(vec![QuantumCircuitError::TooFewSteps])
+        );
+    }
+
+    #[test]
+    fn test_all_errors_are_collected() {
+        let grid = vec![vec!["A", "H", "B"], vec!["X", "CNOT", "Y"]];
+        assert_eq!(
+            validate_grid_input(&UnparsedCircuit::from(grid)),
+            Err(vec![
+                QuantumCircuitError::UnknownGate { token: "A".to_string(), qubit: 0, step: 0 },
+                QuantumCircuitError::UnknownGate { token: "CNOT".to_string(), qubit: 1, step: 1 },
+                QuantumCircuitError::UnknownGate { token: "B".to_string(), qubit: 0, step: 2 },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_error_serializes_with_position() {
+        let error = QuantumCircuitError::UnknownGate { token: "A".to_string(), qubit: 1, step: 2 };
+        assert_eq!(
+            serde_json::to_string(&error).unwrap(),
+            r#"{"code":"unknown_gate","message":"'A' on qubit 1 at step 2 is not a known gate","qubit":1,"step":2,"token":"A"}"#
+        );
+
+        assert_eq!(
+            serde_json::to_string(&QuantumCircuitError::TooManyQubits).unwrap(),
+            r#"{"code":"too_many_qubits","message":"The circuit has more than 6 qubits","qubit":null,"step":null,"token":null}"#
+        );
+    }
+
+    #[test]
+    fn test_validate_parameterised_gates() {
+        assert!(validate_gate("Rx(pi/2)", &UnparsedCircuit::default()));
+        assert!(validate_gate("U(0.1, 0.2, 0.3)", &UnparsedCircuit::default()));
+        assert!(!validate_gate("Rx()", &UnparsedCircuit::default()));
+        assert!(!validate_gate("Rx(pi", &UnparsedCircuit::default()));
+        assert!(validate_control_target("Rz(pi)", &UnparsedCircuit::default()));
+    }
+
+    #[test]
+    fn test_control_on_last_qubit() {
+        let grid = vec![vec!["H", "I"], vec!["X", "C_down"]];
+        assert_eq!(
+            validate_grid_input(&UnparsedCircuit::from(grid)),
+            Err(vec![QuantumCircuitError::ControlOnLastQubit { token: "C_down".to_string(), qubit: 1, step: 1 }])
+        );
+    }
+
+    #[test]
+    fn test_control_above_wire() {
+        let grid = vec![vec!["C_down", "C_down"], vec!["I", "C_down"], vec!["I", "I"]];
+        assert_eq!(
+            validate_grid_input(&UnparsedCircuit::from(grid)),
+            Err(vec![
+                QuantumCircuitError::InvalidControlTarget { token: "C_down".to_string(), qubit: 0, step: 0, target: "I".to_string() },
+                QuantumCircuitError::InvalidControlTarget { token: "C_down".to_string(), qubit: 1, step: 1, target: "I".to_string() },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_stacked_controls() {
+        let grid = vec![vec!["C_down", "C_down", "H"], vec!["C_down", "C_down", "C_down"], vec!["X", "C_down", "C_down"], vec!["I", "Z", "C_down"]];
+        assert_eq!(
+            validate_grid_input(&UnparsedCircuit::from(grid)),
+            Err(vec![QuantumCircuitError::ControlOnLastQubit { token: "C_down".to_string(), qubit: 3, step: 2 }])
+        );
+    }
+
+    #[test]
+    fn test_unpaired_swaps() {
+        let grid = vec![vec!["Swap", "Swap"], vec!["I", "Swap"], vec!["Swap", "Swap"]];
+        assert_eq!(
+            validate_grid_input(&UnparsedCircuit::from(grid)),
+            Err(vec![
+                QuantumCircuitError::UnpairedSwap { token: "Swap".to_string(), qubit: 0, step: 0 },
+                QuantumCircuitError::UnpairedSwap { token: "Swap".to_string(), qubit: 2, step: 0 },
+                QuantumCircuitError::UnpairedSwap { token: "Swap".to_string(), qubit: 2, step: 1 },
+            ])
         );
     }
 
@@ -146,4 +508,23 @@ mod tests {
         let grid = vec![vec!["X", "Swap"], vec!["I", "Swap"]];
         assert_eq!(validate_grid_input(&UnparsedCircuit::from(grid)), Ok(()));
     }
+
+    #[test]
+    fn test_custom_gates() {
+        use crate::simulation::quantum_gate::QuantumGate;
+        use std::collections::BTreeMap;
+
+        let custom_gates = BTreeMap::from([("Oracle".to_string(), QuantumGate::cz_gate()), ("Flip".to_string(), QuantumGate::x_gate())]);
+        let grid = |circuit: Vec<Vec<&str>>| UnparsedCircuit { custom_gates: custom_gates.clone(), ..UnparsedCircuit::from(circuit) };
+
+        assert_eq!(validate_grid_input(&grid(vec![vec!["Oracle", "C_down"], vec!["Oracle", "Flip"], vec!["Oracle", "C_down"], vec!["Oracle", "Oracle"], vec!["I", "Oracle"]])), Ok(()));
+        assert_eq!(
+            validate_grid_input(&grid(vec![vec!["Oracle", "C_down"], vec!["H", "Oracle"], vec!["Oracle", "I"]])),
+            Err(vec![
+                QuantumCircuitError::IncompleteCustomGate { token: "Oracle".to_string(), qubit: 0, step: 0, size: 2 },
+                QuantumCircuitError::IncompleteCustomGate { token: "Oracle".to_string(), qubit: 2, step: 0, size: 2 },
+                QuantumCircuitError::IncompleteCustomGate { token: "Oracle".to_string(), qubit: 1, step: 1, size: 2 },
+            ])
+        );
+    }
 }
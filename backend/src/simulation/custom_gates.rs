@@ -0,0 +1,141 @@
+// Gates that requests define themselves as a unitary matrix, e.g. the oracles of Grover's and Deutsch-Jozsa's algorithms.
+// A custom gate on one qubit is used like H, and can be controlled. A gate on k qubits is written with its name
+// on k consecutive rows of the same step, with the first of those rows as the most significant qubit of the matrix.
+
+use crate::simulation::circuit_validator::QuantumCircuitError;
+use crate::simulation::quantum_gate::QuantumGate;
+use ndarray::Array2;
+use num::Complex;
+use std::collections::BTreeMap;
+
+// The matrices of the custom gates by name, as requests send them, one row of [re, im] pairs after the other
+pub type CustomGateDefinitions = BTreeMap<String, Vec<Vec<Complex<f64>>>>;
+
+pub const MAX_CUSTOM_GATE_QUBITS: usize = 3;
+// How far U†U may be from the identity, which lets matrices written with 7 or more decimals through
+pub const UNITARY_TOLERANCE: f64 = 1e-6;
+
+// The tokens of the grid and the names of the parameterised gates, which custom gates can not be called
+const RESERVED_NAMES: [&str; 15] = ["I", "H", "X", "Y", "Z", "T", "S", "SX", "Swap", "C_down", "Rx", "Ry", "Rz", "P", "U"];
+
+// Turn the matrices of a request into gates, reporting every definition that can not be used
+pub fn define_custom_gates(definitions: CustomGateDefinitions) -> Result<BTreeMap<String, QuantumGate>, Vec<QuantumCircuitError>> {
+    let mut gates = BTreeMap::new();
+    let mut errors = Vec::new();
+    for (name, rows) in definitions {
+        match custom_gate(&name, rows).and_then(|gate| validate_custom_gate(&name, &gate).map(|_| gate)) {
+            Ok(gate) => {
+                gates.insert(name, gate);
+            }
+            Err(error) => errors.push(error),
+        }
+    }
+    if errors.is_empty() {
+        Ok(gates)
+    } else {
+        Err(errors)
+    }
+}
+
+// Ensure that the name can be written in a cell of the grid and the gate is a unitary on 1 to 3 qubits
+pub fn validate_custom_gate(name: &str, gate: &QuantumGate) -> Result<(), QuantumCircuitError> {
+    let invalid = |reason: String| Err(QuantumCircuitError::InvalidCustomGate { name: name.to_string(), reason });
+
+    if let Some(reason) = invalid_name_reason(name) {
+        return invalid(reason.to_string());
+    }
+    if gate.size == 0 || gate.size > MAX_CUSTOM_GATE_QUBITS || gate.matrix.dim() != (1 << gate.size, 1 << gate.size) {
+        return invalid(format!("has to be a 2x2, 4x4 or 8x8 matrix, for 1 to {} qubits", MAX_CUSTOM_GATE_QUBITS));
+    }
+
+    let product = gate.matrix.t().mapv(|entry| entry.conj()).dot(&gate.matrix);
+    let deviation = product
+        .indexed_iter()
+        .map(|((row, col), entry)| (entry - if row == col { 1.0 } else { 0.0 }).norm())
+        .fold(0.0, f64::max);
+    if deviation > UNITARY_TOLERANCE {
+        return invalid(format!("is not unitary, U†U differs from the identity by up to {:.2e}", deviation));
+    }
+    Ok(())
+}
+
+// Why a name can not be written in a cell of the grid, if it can not
+pub fn invalid_name_reason(name: &str) -> Option<&'static str> {
+    let mut characters = name.chars();
+    if !characters.next().is_some_and(|first| first.is_ascii_alphabetic()) || !characters.all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        Some("has to start with a letter and only contain letters, digits and underscores")
+    } else if RESERVED_NAMES.contains(&name) {
+        Some("has the name of a built-in gate")
+    } else {
+        None
+    }
+}
+
+fn custom_gate(name: &str, rows: Vec<Vec<Complex<f64>>>) -> Result<QuantumGate, QuantumCircuitError> {
+    let dimension = rows.len();
+    let size = dimension.trailing_zeros() as usize;
+    if !dimension.is_power_of_two() || size == 0 || rows.iter().any(|row| row.len() != dimension) {
+        return Err(QuantumCircuitError::InvalidCustomGate {
+            name: name.to_string(),
+            reason: format!("has to be a 2x2, 4x4 or 8x8 matrix, for 1 to {} qubits", MAX_CUSTOM_GATE_QUBITS),
+        });
+    }
+    let matrix = Array2::from_shape_vec((dimension, dimension), rows.into_iter().flatten().collect())
+        .expect("the rows were checked to be square");
+    Ok(QuantumGate { matrix, size })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::FRAC_1_SQRT_2;
+
+    fn real(rows: &[&[f64]]) -> Vec<Vec<Complex<f64>>> {
+        rows.iter().map(|row| row.iter().map(|value| Complex::new(*value, 0.0)).collect()).collect()
+    }
+
+    #[test]
+    fn test_define_unitary_gates() {
+        let mut definitions = CustomGateDefinitions::new();
+        definitions.insert("Hadamard".to_string(), real(&[&[FRAC_1_SQRT_2, FRAC_1_SQRT_2], &[FRAC_1_SQRT_2, -FRAC_1_SQRT_2]]));
+        definitions.insert("Oracle_2".to_string(), real(&[&[1.0, 0.0, 0.0, 0.0], &[0.0, 1.0, 0.0, 0.0], &[0.0, 0.0, 1.0, 0.0], &[0.0, 0.0, 0.0, -1.0]]));
+
+        let gates = define_custom_gates(definitions).unwrap();
+
+        assert_eq!(gates["Hadamard"].size, 1);
+        assert!((&gates["Hadamard"].matrix - &QuantumGate::h_gate().matrix).iter().all(|difference| difference.norm() < 1e-12));
+        assert_eq!(gates["Oracle_2"], QuantumGate::cz_gate());
+    }
+
+    #[test]
+    fn test_invalid_definitions_are_all_reported() {
+        let mut definitions = CustomGateDefinitions::new();
+        definitions.insert("Double".to_string(), real(&[&[2.0, 0.0], &[0.0, 2.0]]));
+        definitions.insert("Three".to_string(), real(&[&[1.0, 0.0, 0.0], &[0.0, 1.0, 0.0], &[0.0, 0.0, 1.0]]));
+        definitions.insert("H".to_string(), real(&[&[0.0, 1.0], &[1.0, 0.0]]));
+        definitions.insert("2x".to_string(), real(&[&[0.0, 1.0], &[1.0, 0.0]]));
+
+        let names: Vec<Option<String>> = define_custom_gates(definitions)
+            .unwrap_err()
+            .iter()
+            .map(|error| error.token().map(str::to_string))
+            .collect();
+
+        assert_eq!(names, vec![Some("2x".to_string()), Some("Double".to_string()), Some("H".to_string()), Some("Three".to_string())]);
+    }
+
+    #[test]
+    fn test_unitarity_tolerance() {
+        // A Hadamard gate with 1/√2 rounded to the given number of decimals
+        let rounded = |decimals: i32| {
+            let value = (FRAC_1_SQRT_2 * 10_f64.powi(decimals)).round() / 10_f64.powi(decimals);
+            real(&[&[value, value], &[value, -value]])
+        };
+
+        assert!(custom_gate("A", rounded(7)).and_then(|gate| validate_custom_gate("A", &gate)).is_ok());
+        assert!(matches!(
+            custom_gate("A", rounded(4)).and_then(|gate| validate_custom_gate("A", &gate)),
+            Err(QuantumCircuitError::InvalidCustomGate { reason, .. }) if reason.starts_with("is not unitary")
+        ));
+    }
+}
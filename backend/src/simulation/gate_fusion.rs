@@ -0,0 +1,88 @@
+// Fusion of single-qubit gates before simulating, so that a wire with H, T and H on it costs one 2x2 gate instead of three.
+// The gates of a run are multiplied together and applied where the run ends: in the step of the next gate on more than one
+// qubit that uses the wire, or in the first step whose state is reported. Gates of different wires commute, so moving
+// a run to its end only changes the states in between, which nobody sees. When every step is reported, as on /simulate,
+// nothing could be moved, so the fusion is skipped there and the only gain is that the gates are applied one by one
+// instead of as one large matrix per entangled group.
+
+use crate::simulation::circuit_parser::ParsedCircuit;
+use crate::simulation::quantum_gate::{GatesInTimeStep, QuantumGate, QuantumGateWrapper};
+
+// Fuse the gates of a circuit parsed gate by gate, where reported tells which steps need to end with their exact state.
+// Wires are left out, except in the first step, which has to cover every qubit to tell the register size
+pub fn fuse_single_qubit_gates(circuit: ParsedCircuit, reported: impl Fn(usize) -> bool) -> ParsedCircuit {
+    let no_of_qubits = circuit.no_of_qubits();
+    let mut pending: Vec<Option<QuantumGate>> = vec![None; no_of_qubits];
+    let identity = QuantumGate::i_gate();
+
+    let mut steps = vec![];
+    for (step, gates_in_step) in circuit.circuit.into_iter().enumerate() {
+        let mut gates = vec![];
+        for wrapper in gates_in_step.gates {
+            match wrapper.qubits[..] {
+                [_] if wrapper.gate == identity => {}
+                [qubit] => {
+                    pending[qubit] = Some(match pending[qubit].take() {
+                        Some(earlier) => earlier.followed_by(&wrapper.gate),
+                        None => wrapper.gate,
+                    })
+                }
+                _ => {
+                    gates.extend(wrapper.qubits.iter().filter_map(|qubit| flush(&mut pending, *qubit)));
+                    gates.push(wrapper);
+                }
+            }
+        }
+        if reported(step) {
+            gates.extend((0..no_of_qubits).filter_map(|qubit| flush(&mut pending, qubit)));
+        }
+        if step == 0 {
+            let covered: Vec<usize> = gates.iter().flat_map(|wrapper| wrapper.qubits.clone()).collect();
+            gates.extend((0..no_of_qubits).filter(|qubit| !covered.contains(qubit)).map(|qubit| QuantumGateWrapper { gate: identity.clone(), qubits: vec![qubit] }));
+            gates.sort_by_key(|wrapper| wrapper.qubits[0]);
+        }
+        steps.push(GatesInTimeStep { gates });
+    }
+    ParsedCircuit { circuit: steps }
+}
+
+// The fused gate of a wire, taken out so that the next run starts afresh
+fn flush(pending: &mut [Option<QuantumGate>], qubit: usize) -> Option<QuantumGateWrapper> {
+    pending[qubit].take().map(|gate| QuantumGateWrapper { gate, qubits: vec![qubit] })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::circuit_parser::{parse_gates_individually, UnparsedCircuit};
+    use crate::simulation::test_support::assert_gates_close;
+
+    #[test]
+    fn test_fuse_run_on_a_wire() {
+        let grid = UnparsedCircuit::from(vec![vec!["H", "T", "H", "C_down"], vec!["I", "X", "I", "X"]]);
+        let circuit = parse_gates_individually(grid).unwrap();
+
+        let fused = fuse_single_qubit_gates(circuit, |step| step == 3);
+
+        let gates_per_step: Vec<usize> = fused.circuit.iter().map(|step| step.gates.len()).collect();
+        assert_eq!(gates_per_step, vec![2, 0, 0, 3]);
+        assert_eq!(fused.no_of_qubits(), 2);
+        let last_step = &fused.circuit[3].gates;
+        assert_eq!(last_step[0].qubits, vec![0]);
+        assert_gates_close(&last_step[0].gate, &QuantumGate::h_gate().followed_by(&QuantumGate::t_gate()).followed_by(&QuantumGate::h_gate()));
+        assert_eq!(last_step[1].qubits, vec![1]);
+        assert_gates_close(&last_step[1].gate, &QuantumGate::x_gate());
+        assert_eq!(last_step[2].qubits, vec![0, 1]);
+    }
+
+    #[test]
+    fn test_reported_steps_end_runs() {
+        let grid = UnparsedCircuit::from(vec![vec!["H", "T", "H"]]);
+        let circuit = parse_gates_individually(grid).unwrap();
+
+        let fused = fuse_single_qubit_gates(circuit, |_| true);
+
+        let gates: Vec<&QuantumGate> = fused.circuit.iter().flat_map(|step| step.gates.iter().map(|wrapper| &wrapper.gate)).collect();
+        assert_eq!(gates, vec![&QuantumGate::h_gate(), &QuantumGate::t_gate(), &QuantumGate::h_gate()]);
+    }
+}
@@ -0,0 +1,98 @@
+// Lays gates out on the grid the frontend edits, for importers that describe circuits gate by gate
+// instead of step by step.
+// The grid only knows controls directly above their target, so other controls are routed
+// with adjacent swaps that are undone right after the controlled gate.
+
+use crate::simulation::circuit_parser::UnparsedCircuit;
+use std::collections::HashMap;
+
+// Places tokens into the grid, each block of rows in the leftmost step where all of its rows are free
+#[derive(Debug, Clone, Default)]
+pub struct GridLayout {
+    next_free_step: Vec<usize>,
+    cells: HashMap<(usize, usize), String>,
+}
+
+impl GridLayout {
+    pub fn add_qubits(&mut self, no_of_qubits: usize) {
+        self.next_free_step.extend(std::iter::repeat_n(0, no_of_qubits));
+    }
+
+    pub fn no_of_qubits(&self) -> usize {
+        self.next_free_step.len()
+    }
+
+    // The first step in which nothing has been placed on the qubit yet
+    pub fn next_free_step(&self, qubit: usize) -> usize {
+        self.next_free_step[qubit]
+    }
+
+    // Place tokens on consecutive rows starting at the first qubit, all in the same step
+    pub fn place(&mut self, first_qubit: usize, tokens: Vec<String>) {
+        let rows = first_qubit..first_qubit + tokens.len();
+        let step = self.next_free_step[rows.clone()].iter().copied().max().unwrap_or(0);
+        for (qubit, token) in rows.clone().zip(tokens) {
+            self.cells.insert((qubit, step), token);
+        }
+        for qubit in rows {
+            self.next_free_step[qubit] = step + 1;
+        }
+    }
+
+    fn swap_adjacent(&mut self, upper: usize) {
+        self.place(upper, vec!["Swap".to_string(), "Swap".to_string()]);
+    }
+
+    // Later gates on these qubits are placed after every gate placed on them so far
+    pub fn barrier(&mut self, qubits: &[usize]) {
+        let step = qubits.iter().map(|qubit| self.next_free_step[*qubit]).max().unwrap_or(0);
+        for qubit in qubits {
+            self.next_free_step[*qubit] = step;
+        }
+    }
+
+    pub fn single(&mut self, token: String, qubit: usize) {
+        self.place(qubit, vec![token]);
+    }
+
+    pub fn controlled(&mut self, token: String, control: usize, target: usize) {
+        if target > control {
+            // Move the target up until it is directly underneath the control
+            let swaps: Vec<usize> = (control + 1..target).rev().collect();
+            swaps.iter().for_each(|upper| self.swap_adjacent(*upper));
+            self.place(control, vec!["C_down".to_string(), token]);
+            swaps.iter().rev().for_each(|upper| self.swap_adjacent(*upper));
+        } else {
+            // Move the control up until it is directly above the target, which pushes the target down by one
+            let swaps: Vec<usize> = (target..control).rev().collect();
+            swaps.iter().for_each(|upper| self.swap_adjacent(*upper));
+            self.place(target, vec!["C_down".to_string(), token]);
+            swaps.iter().rev().for_each(|upper| self.swap_adjacent(*upper));
+        }
+    }
+
+    pub fn swap(&mut self, first: usize, second: usize) {
+        let (upper, lower) = (first.min(second), first.max(second));
+        // Carry the upper qubit down to the lower one, then the lower one back up
+        for qubit in upper..lower {
+            self.swap_adjacent(qubit);
+        }
+        for qubit in (upper..lower - 1).rev() {
+            self.swap_adjacent(qubit);
+        }
+    }
+
+    // Steps that nothing was placed in are filled with wires, and a program without gates gets a single step
+    pub fn into_grid(self) -> UnparsedCircuit {
+        let no_of_steps = self.next_free_step.iter().copied().max().unwrap_or(0).max(1);
+        let circuit: Vec<Vec<String>> = (0..self.next_free_step.len())
+            .map(|qubit| {
+                (0..no_of_steps)
+                    .map(|step| self.cells.get(&(qubit, step)).cloned().unwrap_or_else(|| "I".to_string()))
+                    .collect()
+            })
+            .collect();
+        UnparsedCircuit::from(circuit)
+    }
+}
+
@@ -0,0 +1,128 @@
+use crate::simulation::circuit_validator::QuantumCircuitError;
+use crate::simulation::quantum_gate::QuantumGateWrapper;
+use crate::simulation::quantum_state::{QuantumState, QuantumStateWrapper, QuantumStep};
+use crate::simulation::simulation_backend::{sample_outcome, SimulationBackend, MAX_BACKEND_QUBITS};
+use ndarray::arr2;
+use num::Complex;
+use rand::RngCore;
+
+// The original engine: every group of entangled qubits has its own state vector,
+// and groups are only combined with the Kronecker product when a gate acts on several of them
+#[derive(Debug, Clone, Default)]
+pub struct GroupedBackend {
+    step: QuantumStep,
+}
+
+impl GroupedBackend {
+    fn no_of_qubits(&self) -> usize {
+        self.step.states.iter().map(|state| state.qubits.len()).sum()
+    }
+}
+
+impl SimulationBackend for GroupedBackend {
+    fn initialize(&mut self, no_of_qubits: usize) -> Result<(), QuantumCircuitError> {
+        if no_of_qubits > MAX_BACKEND_QUBITS {
+            return Err(QuantumCircuitError::TooManyQubits);
+        }
+        self.step = QuantumStep {
+            states: (0..no_of_qubits)
+                .map(|qubit| {
+                    Ok(QuantumStateWrapper {
+                        state: QuantumState::new(&[0])?,
+                        qubits: vec![qubit],
+                    })
+                })
+                .collect::<Result<Vec<QuantumStateWrapper>, QuantumCircuitError>>()?,
+        };
+        Ok(())
+    }
+
+    fn apply_gate(&mut self, gate_wrapper: &QuantumGateWrapper) -> Result<(), QuantumCircuitError> {
+        let qubits_in_gate = &gate_wrapper.qubits;
+        let no_of_qubits = self.no_of_qubits();
+        if let Some(&qubit) = qubits_in_gate.iter().find(|qubit| **qubit >= no_of_qubits) {
+            return Err(QuantumCircuitError::QubitOutOfRange { qubit, no_of_qubits });
+        }
+        let first_affected = self
+            .step
+            .states
+            .iter()
+            .position(|state| state.qubits.iter().any(|qubit| qubits_in_gate.contains(qubit)))
+            .ok_or(QuantumCircuitError::GateSizeMismatch { gate_size: gate_wrapper.gate.size, state_size: 0 })?;
+
+        let (affected, unaffected): (Vec<QuantumStateWrapper>, Vec<QuantumStateWrapper>) = self
+            .step
+            .states
+            .clone()
+            .into_iter()
+            .partition(|state| state.qubits.iter().any(|qubit| qubits_in_gate.contains(qubit)));
+
+        let state_into_gate = combine_states(affected.clone());
+        let qubits_in_group: Vec<usize> = affected.into_iter().flat_map(|state| state.qubits).collect();
+
+        let state_after_gate = if &qubits_in_group == qubits_in_gate {
+            state_into_gate.apply_gate(gate_wrapper.gate.clone())?
+        } else {
+            let positions: Vec<usize> = qubits_in_gate
+                .iter()
+                .filter_map(|qubit| qubits_in_group.iter().position(|q| q == qubit))
+                .collect();
+            state_into_gate.apply_gate_on_positions(&gate_wrapper.gate, &positions)?
+        };
+
+        self.step.states = unaffected;
+        self.step.states.insert(
+            first_affected,
+            QuantumStateWrapper {
+                state: state_after_gate,
+                qubits: qubits_in_group,
+            },
+        );
+        Ok(())
+    }
+
+    fn measure(&mut self, qubit: usize, rng: &mut dyn RngCore) -> Result<u8, QuantumCircuitError> {
+        let no_of_qubits = self.no_of_qubits();
+        let group = self
+            .step
+            .states
+            .iter_mut()
+            .find(|state| state.qubits.contains(&qubit))
+            .ok_or(QuantumCircuitError::QubitOutOfRange { qubit, no_of_qubits })?;
+        let position = group.qubits.iter().position(|q| *q == qubit).unwrap_or_default();
+
+        let outcome = sample_outcome(group.state.probability_of_one(position)?, rng);
+        group.state = group.state.collapse(position, outcome)?;
+        Ok(outcome)
+    }
+
+    fn snapshot(&self) -> QuantumState {
+        let combined_state = combine_states(self.step.states.clone());
+        let qubit_order: Vec<usize> = self.step.states.iter().flat_map(|state| state.qubits.clone()).collect();
+        if qubit_order.windows(2).all(|pair| pair[0] < pair[1]) {
+            return combined_state;
+        }
+
+        // Groups that are not contiguous leave the qubits out of order after combining them
+        let no_of_qubits = qubit_order.len();
+        let mut col = combined_state.col.clone();
+        for (index, amplitude) in combined_state.col.iter().enumerate() {
+            let register_index = qubit_order
+                .iter()
+                .enumerate()
+                .filter(|(position, _)| index & (1 << (no_of_qubits - position - 1)) != 0)
+                .fold(0, |acc, (_, qubit)| acc | (1 << (no_of_qubits - qubit - 1)));
+            col[[register_index, 0]] = *amplitude;
+        }
+        QuantumState { col }
+    }
+}
+
+pub fn combine_states(states: Vec<QuantumStateWrapper>) -> QuantumState {
+    // Start from the state of an empty register, which is the identity of the Kronecker product
+    let mut current_state: QuantumState = QuantumState { col: arr2(&[[Complex::new(1.0, 0.0)]]) };
+    for entagled_group in states.iter() {
+        current_state = current_state.kronecker(entagled_group.state.clone());
+    }
+    current_state
+}
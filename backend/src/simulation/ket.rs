@@ -0,0 +1,204 @@
+// Writes states in ket notation, e.g. "(1/√2)(|00⟩ + |11⟩)", for people reading the output of /simulate.
+// Only the non-zero terms are written, and the basis states are labelled with their index in binary,
+// so qubit 0 is the rightmost bit of little endian states and the leftmost bit of big endian states.
+// Amplitudes are recognised as exact values where possible: magnitudes that are square roots of small fractions
+// and phases that are fractions of pi. Anything else is written as a decimal.
+
+use crate::simulation::angle::pi_fraction;
+use crate::simulation::quantum_state::QuantumState;
+use num::Complex;
+use std::fmt;
+
+const EPSILON: f64 = 1e-9;
+// The largest denominator of the squared magnitudes that are recognised, 64 covers an even superposition of 6 qubits
+const MAX_DENOMINATOR: u64 = 64;
+
+pub fn format_ket(state: &QuantumState) -> String {
+    let no_of_qubits = state.size();
+    let terms: Vec<(String, Coefficient)> = state
+        .col
+        .iter()
+        .enumerate()
+        .filter(|(_, amplitude)| amplitude.norm() > EPSILON)
+        .map(|(index, amplitude)| (format!("|{:0width$b}⟩", index, width = no_of_qubits), Coefficient::from(*amplitude)))
+        .collect();
+
+    if terms.is_empty() {
+        return "0".to_string();
+    }
+
+    // A magnitude shared by all terms is written once in front of them
+    let common_magnitude = match &terms[0].1 {
+        Coefficient::Exact { magnitude, .. } if terms.len() > 1 && magnitude != "1" => Some(magnitude.clone()),
+        _ => None,
+    }
+    .filter(|common| {
+        terms.iter().all(|(_, coefficient)| matches!(coefficient, Coefficient::Exact { magnitude, .. } if magnitude == common))
+    });
+
+    let mut text = String::new();
+    for (position, (basis, coefficient)) in terms.iter().enumerate() {
+        let (negative, factor) = coefficient.signed_factor(common_magnitude.is_none());
+        match (position, negative) {
+            (0, true) => text.push('-'),
+            (0, false) => (),
+            (_, true) => text.push_str(" - "),
+            (_, false) => text.push_str(" + "),
+        }
+        text.push_str(&factor);
+        text.push_str(basis);
+    }
+
+    match common_magnitude {
+        Some(magnitude) => format!("({})({})", magnitude, text),
+        None => text,
+    }
+}
+
+// States are shown in ket notation, e.g. in test failures
+impl fmt::Display for QuantumState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", format_ket(self))
+    }
+}
+
+enum Coefficient {
+    // The magnitude, e.g. "1/√2", and the phase as numerator * pi / denominator
+    Exact { magnitude: String, phase: (i64, i64) },
+    Decimal(Complex<f64>),
+}
+
+impl From<Complex<f64>> for Coefficient {
+    fn from(amplitude: Complex<f64>) -> Self {
+        match (exact_magnitude(amplitude.norm()), pi_fraction(amplitude.arg())) {
+            (Some(magnitude), Some(phase)) => Coefficient::Exact { magnitude, phase },
+            _ => Coefficient::Decimal(amplitude),
+        }
+    }
+}
+
+impl Coefficient {
+    // Whether the term is subtracted, and what is written in front of its basis state
+    fn signed_factor(&self, with_magnitude: bool) -> (bool, String) {
+        match self {
+            Coefficient::Exact { magnitude, phase } => {
+                let (negative, phase) = phase_factor(*phase);
+                let magnitude = match magnitude.as_str() {
+                    "1" => String::new(),
+                    _ if !with_magnitude => String::new(),
+                    magnitude if magnitude.contains('/') => format!("({})", magnitude),
+                    magnitude => magnitude.to_string(),
+                };
+                (negative, format!("{}{}", magnitude, phase))
+            }
+            Coefficient::Decimal(amplitude) if amplitude.im.abs() < EPSILON => (amplitude.re < 0.0, decimal(amplitude.re.abs())),
+            Coefficient::Decimal(amplitude) if amplitude.re.abs() < EPSILON => (amplitude.im < 0.0, format!("{}i", decimal(amplitude.im.abs()))),
+            Coefficient::Decimal(amplitude) => {
+                let sign = if amplitude.im < 0.0 { "-" } else { "+" };
+                (false, format!("({}{}{}i)", decimal(amplitude.re), sign, decimal(amplitude.im.abs())))
+            }
+        }
+    }
+}
+
+// The magnitude as the square root of a fraction, e.g. "1/√2", "√3/2" or "1/(2√2)"
+fn exact_magnitude(magnitude: f64) -> Option<String> {
+    let square = magnitude * magnitude;
+    (1..=MAX_DENOMINATOR).find_map(|denominator| {
+        let numerator = (square * denominator as f64).round();
+        if numerator < 1.0 || (square - numerator / denominator as f64).abs() > EPSILON {
+            return None;
+        }
+        let numerator = square_root(numerator as u64);
+        Some(match denominator {
+            1 => numerator,
+            _ => {
+                let denominator = square_root(denominator);
+                if denominator.contains('√') && !denominator.starts_with('√') {
+                    format!("{}/({})", numerator, denominator)
+                } else {
+                    format!("{}/{}", numerator, denominator)
+                }
+            }
+        })
+    })
+}
+
+// The square root of n with square factors taken out, e.g. 8 -> "2√2"
+fn square_root(n: u64) -> String {
+    let outside = (1..=n).rev().find(|factor| n.is_multiple_of(factor * factor)).unwrap_or(1);
+    match (outside, n / (outside * outside)) {
+        (outside, 1) => outside.to_string(),
+        (1, inside) => format!("√{}", inside),
+        (outside, inside) => format!("{}√{}", outside, inside),
+    }
+}
+
+// Whether the phase turns the term negative, and the rest of it, e.g. "i" or "e^{iπ/4}"
+fn phase_factor((numerator, denominator): (i64, i64)) -> (bool, String) {
+    // Bring the phase into (-pi, pi]
+    let numerator = (numerator + denominator).rem_euclid(2 * denominator) - denominator;
+    let numerator = if numerator == -denominator { denominator } else { numerator };
+    match (numerator, denominator) {
+        (0, _) => (false, String::new()),
+        (1, 1) => (true, String::new()),
+        (1, 2) => (false, "i".to_string()),
+        (-1, 2) => (true, "i".to_string()),
+        (numerator, denominator) => {
+            let sign = if numerator < 0 { "-" } else { "" };
+            let coefficient = match numerator.abs() {
+                1 => String::new(),
+                n => n.to_string(),
+            };
+            let fraction = match denominator {
+                1 => String::new(),
+                d => format!("/{}", d),
+            };
+            (false, format!("e^{{{}{}iπ{}}}", sign, coefficient, fraction))
+        }
+    }
+}
+
+// Four decimals without trailing zeros, e.g. 0.3 instead of 0.3000
+fn decimal(value: f64) -> String {
+    let text = format!("{:.4}", value);
+    text.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::Array2;
+    use std::f64::consts::{FRAC_1_SQRT_2, PI};
+
+    fn state(amplitudes: &[Complex<f64>]) -> QuantumState {
+        QuantumState { col: Array2::from_shape_vec((amplitudes.len(), 1), amplitudes.to_vec()).unwrap() }
+    }
+
+    fn real(value: f64) -> Complex<f64> {
+        Complex::new(value, 0.0)
+    }
+
+    #[test]
+    fn test_common_magnitudes_are_factored_out() {
+        let zero = real(0.0);
+
+        assert_eq!(format_ket(&state(&[real(FRAC_1_SQRT_2), zero, zero, real(FRAC_1_SQRT_2)])), "(1/√2)(|00⟩ + |11⟩)");
+        assert_eq!(format_ket(&state(&[real(FRAC_1_SQRT_2), real(-FRAC_1_SQRT_2)])), "(1/√2)(|0⟩ - |1⟩)");
+        assert_eq!(
+            format_ket(&state(&[real(0.5), Complex::new(0.0, 0.5), real(-0.5), Complex::from_polar(0.5, PI / 4.0)])),
+            "(1/2)(|00⟩ + i|01⟩ - |10⟩ + e^{iπ/4}|11⟩)"
+        );
+        assert_eq!(format_ket(&state(&[zero, real(-1.0)])), "-|1⟩");
+    }
+
+    #[test]
+    fn test_mixed_magnitudes_and_decimals() {
+        assert_eq!(format_ket(&state(&[real(0.5), real(3.0_f64.sqrt() / 2.0)])), "(1/2)|0⟩ + (√3/2)|1⟩");
+        assert_eq!(
+            format_ket(&state(&[real(1.0 / 8.0_f64.sqrt()), Complex::from_polar((7.0_f64 / 8.0).sqrt(), -3.0 * PI / 4.0)])),
+            "(1/(2√2))|0⟩ + (√7/(2√2))e^{-3iπ/4}|1⟩"
+        );
+        assert_eq!(format_ket(&state(&[real(0.3), Complex::new(0.1, -0.9486)])), "0.3|0⟩ + (0.1-0.9486i)|1⟩");
+    }
+}
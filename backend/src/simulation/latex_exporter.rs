@@ -0,0 +1,155 @@
+// Exports grid circuits as quantikz LaTeX, for lecture notes and exams.
+// Every step of the grid becomes a column of the quantikz environment and row i is labelled q_i.
+
+use crate::simulation::angle::format_angle;
+use crate::simulation::circuit_parser::{operations_in_step, parse_parameterised_token, GridOperation, UnparsedCircuit};
+use crate::simulation::circuit_validator::{validate_grid_input, QuantumCircuitError};
+use crate::simulation::subcircuits::INVERSE_SUFFIX;
+
+pub fn export_latex(grid: &UnparsedCircuit, standalone: bool) -> Result<String, Vec<QuantumCircuitError>> {
+    validate_grid_input(grid)?;
+
+    let mut rows: Vec<Vec<String>> = (0..grid.circuit.len()).map(|qubit| vec![format!("\\lstick{{$q_{{{}}}$}}", qubit)]).collect();
+    for step in 0..grid.circuit[0].len() {
+        for operation in operations_in_step(grid, step).map_err(|error| vec![error])? {
+            match operation {
+                GridOperation::Single { token, qubit, .. } => rows[qubit].push(gate_command(&token)),
+                GridOperation::Controlled { token, gate, control, target } => {
+                    for (row, cells) in rows.iter_mut().enumerate().take(target).skip(control) {
+                        cells.push(format!("\\ctrl{{{}}}", target - row));
+                    }
+                    // Controlled X is drawn as the usual target symbol, a controlled block as its box
+                    rows[target].push(match token.as_str() {
+                        "X" => "\\targ{}".to_string(),
+                        _ if gate.size > 1 => block_command(&token, gate.size),
+                        _ => gate_command(&token),
+                    });
+                    for row in rows.iter_mut().skip(target + 1).take(gate.size - 1) {
+                        row.push("\\qw".to_string());
+                    }
+                }
+                GridOperation::Swap { upper } => {
+                    rows[upper].push("\\swap{1}".to_string());
+                    rows[upper + 1].push("\\targX{}".to_string());
+                }
+                // One box over all of its wires, the rows underneath it only continue their wire
+                GridOperation::Custom { token, gate, upper } => {
+                    rows[upper].push(block_command(&token, gate.size));
+                    for row in rows.iter_mut().skip(upper + 1).take(gate.size - 1) {
+                        row.push("\\qw".to_string());
+                    }
+                }
+            }
+        }
+    }
+
+    let body: Vec<String> = rows.iter().map(|row| format!("{} & \\qw", row.join(" & "))).collect();
+    let environment = format!("\\begin{{quantikz}}\n{}\n\\end{{quantikz}}\n", body.join(" \\\\\n"));
+    if standalone {
+        Ok(format!(
+            "\\documentclass{{standalone}}\n\\usepackage{{quantikz}}\n\\begin{{document}}\n{}\\end{{document}}\n",
+            environment
+        ))
+    } else {
+        Ok(environment)
+    }
+}
+
+// The quantikz command for a single qubit gate, e.g. \gate{R_z(\pi/4)}
+fn gate_command(token: &str) -> String {
+    if token == "I" {
+        return "\\qw".to_string();
+    }
+    match parse_parameterised_token(token) {
+        Some((name, angles)) => {
+            let name = match name {
+                "Rx" => "R_x",
+                "Ry" => "R_y",
+                "Rz" => "R_z",
+                _ => name,
+            };
+            let angles: Vec<String> = angles.iter().map(|angle| latex_angle(*angle)).collect();
+            format!("\\gate{{{}({})}}", name, angles.join(", "))
+        }
+        None => format!("\\gate{{{}}}", dagger(token)),
+    }
+}
+
+// A box over the given number of wires, e.g. \gate[3]{QFT}
+fn block_command(token: &str, size: usize) -> String {
+    format!("\\gate[{}]{{{}}}", size, dagger(token))
+}
+
+// The inverse of a sub-circuit is written QFT^\dagger
+fn dagger(token: &str) -> String {
+    token.replace(INVERSE_SUFFIX, "^\\dagger")
+}
+
+// Angles as fractions of pi where possible, e.g. -3\pi/4
+fn latex_angle(angle: f64) -> String {
+    format_angle(angle).replace("*pi", "pi").replace("pi", "\\pi")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_export_bell_state() {
+        let grid = UnparsedCircuit::from(vec![vec!["H", "C_down"], vec!["I", "X"]]);
+
+        assert_eq!(
+            export_latex(&grid, false).unwrap(),
+            "\\begin{quantikz}\n\
+             \\lstick{$q_{0}$} & \\gate{H} & \\ctrl{1} & \\qw \\\\\n\
+             \\lstick{$q_{1}$} & \\qw & \\targ{} & \\qw\n\
+             \\end{quantikz}\n"
+        );
+    }
+
+    #[test]
+    fn test_export_swaps_and_parameterised_gates() {
+        let grid = UnparsedCircuit::from(vec![
+            vec!["Swap", "Rz(-3*pi/4)"],
+            vec!["Swap", "C_down"],
+            vec!["T", "U(pi/2,0,0.5)"],
+        ]);
+
+        let latex = export_latex(&grid, false).unwrap();
+
+        assert!(latex.contains("\\lstick{$q_{0}$} & \\swap{1} & \\gate{R_z(-3\\pi/4)} & \\qw"));
+        assert!(latex.contains("\\lstick{$q_{1}$} & \\targX{} & \\ctrl{1} & \\qw"));
+        assert!(latex.contains("\\lstick{$q_{2}$} & \\gate{T} & \\gate{U(\\pi/2, 0, 0.5)} & \\qw"));
+    }
+
+    #[test]
+    fn test_standalone_document() {
+        let grid = UnparsedCircuit::from(vec![vec!["X"]]);
+
+        let latex = export_latex(&grid, true).unwrap();
+
+        assert!(latex.starts_with("\\documentclass{standalone}\n\\usepackage{quantikz}\n\\begin{document}\n\\begin{quantikz}"));
+        assert!(latex.ends_with("\\end{quantikz}\n\\end{document}\n"));
+        assert!(export_latex(&UnparsedCircuit::from(vec![vec!["C_down"]]), false).is_err());
+    }
+
+    #[test]
+    fn test_export_custom_gate_on_several_qubits() {
+        let mut grid = UnparsedCircuit::from(vec![vec!["H"], vec!["Oracle"], vec!["Oracle"]]);
+        grid.custom_gates.insert("Oracle".to_string(), crate::simulation::quantum_gate::QuantumGate::cz_gate());
+
+        assert!(export_latex(&grid, false).unwrap().contains("\\gate{H} & \\qw \\\\\n\\lstick{$q_{1}$} & \\gate[2]{Oracle} & \\qw \\\\\n\\lstick{$q_{2}$} & \\qw & \\qw\n"));
+    }
+
+    #[test]
+    fn test_export_controlled_inverse_subcircuit() {
+        let subcircuits = crate::simulation::subcircuits::define_subcircuits(
+            std::collections::BTreeMap::from([("Flip".to_string(), vec![vec!["X".to_string()], vec!["S".to_string()]])]),
+            &std::collections::BTreeMap::new(),
+        )
+        .unwrap();
+        let grid = UnparsedCircuit { subcircuits, ..UnparsedCircuit::from(vec![vec!["C_down"], vec!["Flip†"], vec!["Flip†"]]) };
+
+        assert!(export_latex(&grid, false).unwrap().contains("\\ctrl{1} & \\qw \\\\\n\\lstick{$q_{1}$} & \\gate[2]{Flip^\\dagger} & \\qw \\\\\n\\lstick{$q_{2}$} & \\qw & \\qw\n"));
+    }
+}
@@ -1,6 +1,30 @@
+pub mod angle;
+pub mod circuit_builder;
+pub mod circuit_inverse;
+pub mod binary_export;
 pub mod circuit_parser;
 pub mod circuit_validator;
+pub mod custom_gates;
+pub mod gate_fusion;
+pub mod grid_layout;
+pub mod grouped_backend;
+pub mod ket;
+pub mod latex_exporter;
+pub mod optimiser;
+pub mod qasm_exporter;
+pub mod qasm_importer;
 pub mod quantum_gate;
 pub mod quantum_state;
+pub mod quirk;
+pub mod simulation_backend;
 pub mod simulator;
+pub mod solovay_kitaev;
+pub mod sparse_backend;
+pub mod state_vector_backend;
+pub mod subcircuits;
+pub mod svg_renderer;
+#[cfg(test)]
+pub mod test_support;
+pub mod text_renderer;
+pub mod transpiler;
 pub mod utils;
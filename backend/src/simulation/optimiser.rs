@@ -0,0 +1,233 @@
+// A peephole optimiser for grid circuits, so students can see how their circuit could be simplified.
+// It works on the tokens of the grid instead of the parsed circuit, whose matrices no longer say which gates they were made of.
+// Two gates are adjacent when nothing else happens on any of their rows between them. Adjacent gates on the same rows that
+// undo each other are removed, adjacent rotations about the same axis are merged, and at the end steps with only wires are dropped.
+// Gates are only replaced by wires until then, so the steps in the report are the steps of the original grid.
+
+use crate::simulation::angle::format_angle;
+use crate::simulation::circuit_inverse::inverse_token;
+use crate::simulation::circuit_parser::{operations_in_step, parse_parameterised_token, GridOperation, UnparsedCircuit};
+use crate::simulation::circuit_validator::{validate_grid_input, QuantumCircuitError};
+use serde::Serialize;
+use std::f64::consts::PI;
+use std::ops::Range;
+
+const ANGLE_EPSILON: f64 = 1e-9;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum Optimisation {
+    // Two gates that undo each other, e.g. H·H, CNOT·CNOT or S·S†
+    Cancelled { first: String, second: String, qubits: Vec<usize>, steps: [usize; 2] },
+    // Two rotations about the same axis, which became one in the first step
+    Merged { first: String, second: String, merged: String, qubits: Vec<usize>, steps: [usize; 2] },
+    // A step in which nothing happens any more
+    RemovedStep { step: usize },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OptimisedCircuit {
+    pub grid: UnparsedCircuit,
+    pub changes: Vec<Optimisation>,
+}
+
+// A gate of the grid with the rows it takes, which are its controls followed by the rows of the gate itself
+#[derive(Debug, Clone)]
+struct PlacedGate {
+    token: String,
+    step: usize,
+    rows: Range<usize>,
+    target: usize,
+}
+
+pub fn optimise_circuit(grid: &UnparsedCircuit) -> Result<OptimisedCircuit, Vec<QuantumCircuitError>> {
+    validate_grid_input(grid)?;
+
+    let mut grid = grid.clone();
+    let mut changes = Vec::new();
+    // Every change can make two other gates adjacent, so look again until nothing changes
+    while let Some(change) = next_change(&mut grid).map_err(|error| vec![error])? {
+        changes.push(change);
+    }
+
+    let no_of_steps = grid.circuit[0].len();
+    let empty: Vec<usize> = (0..no_of_steps).filter(|step| grid.circuit.iter().all(|row| row[*step] == "I")).collect();
+    // A grid needs at least one step, so a circuit that does nothing keeps its first one
+    let removed = if empty.len() == no_of_steps { &empty[1..] } else { &empty[..] };
+    for row in grid.circuit.iter_mut() {
+        *row = row.iter().enumerate().filter(|(step, _)| !removed.contains(step)).map(|(_, token)| token.clone()).collect();
+    }
+    changes.extend(removed.iter().map(|step| Optimisation::RemovedStep { step: *step }));
+
+    Ok(OptimisedCircuit { grid, changes })
+}
+
+// Find the first pair of adjacent gates that can be cancelled or merged and change the grid accordingly
+fn next_change(grid: &mut UnparsedCircuit) -> Result<Option<Optimisation>, QuantumCircuitError> {
+    let gates = placed_gates(grid)?;
+    for (index, first) in gates.iter().enumerate() {
+        let Some(second) = next_gate_on_rows(&gates[index + 1..], first) else {
+            continue;
+        };
+        if second.rows != first.rows || second.target != first.target {
+            continue;
+        }
+
+        let qubits: Vec<usize> = first.rows.clone().collect();
+        let steps = [first.step, second.step];
+        if let Some(merged) = merged_rotation(&first.token, &second.token) {
+            place(grid, second, None);
+            place(grid, first, merged.as_deref());
+            return Ok(Some(match merged {
+                Some(merged) => Optimisation::Merged { first: first.token.clone(), second: second.token.clone(), merged, qubits, steps },
+                None => Optimisation::Cancelled { first: first.token.clone(), second: second.token.clone(), qubits, steps },
+            }));
+        }
+        if second.token == inverse_token(&first.token) {
+            place(grid, first, None);
+            place(grid, second, None);
+            return Ok(Some(Optimisation::Cancelled { first: first.token.clone(), second: second.token.clone(), qubits, steps }));
+        }
+    }
+    Ok(None)
+}
+
+// Every gate of the grid except the wires, step by step
+fn placed_gates(grid: &UnparsedCircuit) -> Result<Vec<PlacedGate>, QuantumCircuitError> {
+    let mut gates = Vec::new();
+    for step in 0..grid.circuit[0].len() {
+        for operation in operations_in_step(grid, step)? {
+            let (token, rows, target) = match operation {
+                GridOperation::Single { token, .. } if token == "I" => continue,
+                GridOperation::Single { token, qubit, .. } => (token, qubit..qubit + 1, qubit),
+                GridOperation::Controlled { token, gate, control, target } => (token, control..target + gate.size, target),
+                GridOperation::Swap { upper } => ("Swap".to_string(), upper..upper + 2, upper),
+                GridOperation::Custom { token, gate, upper } => (token, upper..upper + gate.size, upper),
+            };
+            gates.push(PlacedGate { token, step, rows, target });
+        }
+    }
+    Ok(gates)
+}
+
+// The first gate after the given one that shares a row with it, which is adjacent to it when it takes the same rows
+fn next_gate_on_rows<'a>(later_gates: &'a [PlacedGate], gate: &PlacedGate) -> Option<&'a PlacedGate> {
+    later_gates
+        .iter()
+        .find(|other| other.step > gate.step && other.rows.start < gate.rows.end && gate.rows.start < other.rows.end)
+}
+
+// The rotation that two rotations about the same axis make together, None when they add up to the identity
+fn merged_rotation(first: &str, second: &str) -> Option<Option<String>> {
+    let (name, first_angles) = parse_parameterised_token(first)?;
+    let (second_name, second_angles) = parse_parameterised_token(second)?;
+    // Rotations repeat after 4π, phases after 2π
+    let period = match name {
+        "Rx" | "Ry" | "Rz" => 4.0 * PI,
+        "P" => 2.0 * PI,
+        _ => return None,
+    };
+    if name != second_name {
+        return None;
+    }
+    let angle = (first_angles[0] + second_angles[0]).rem_euclid(period);
+    if angle < ANGLE_EPSILON || period - angle < ANGLE_EPSILON {
+        return Some(None);
+    }
+    // The angle is written between -period/2 and period/2, so that Rz(-pi/4) stays negative
+    let angle = if angle > period / 2.0 { angle - period } else { angle };
+    Some(Some(format!("{}({})", name, format_angle(angle))))
+}
+
+// Write a gate back into the grid with a new token, or replace it with wires
+fn place(grid: &mut UnparsedCircuit, gate: &PlacedGate, token: Option<&str>) {
+    for row in gate.rows.clone() {
+        grid.circuit[row][gate.step] = match token {
+            Some(_) if row < gate.target => "C_down".to_string(),
+            Some(token) => token.to_string(),
+            None => "I".to_string(),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::test_support::assert_same_final_state;
+
+    #[test]
+    fn test_cancel_inverse_pairs() {
+        let grid = UnparsedCircuit::from(vec![
+            vec!["H", "X", "X", "C_down", "C_down", "S", "S†"],
+            vec!["I", "I", "Y", "X", "X", "I", "I"],
+        ]);
+
+        let optimised = optimise_circuit(&grid).unwrap();
+
+        assert_eq!(optimised.grid, UnparsedCircuit::from(vec![vec!["H", "I"], vec!["I", "Y"]]));
+        let cancelled = |first: &str, second: &str, qubits: Vec<usize>, steps: [usize; 2]| Optimisation::Cancelled {
+            first: first.to_string(),
+            second: second.to_string(),
+            qubits,
+            steps,
+        };
+        assert_eq!(
+            optimised.changes,
+            vec![
+                cancelled("X", "X", vec![0], [1, 2]),
+                cancelled("X", "X", vec![0, 1], [3, 4]),
+                cancelled("S", "S†", vec![0], [5, 6]),
+                Optimisation::RemovedStep { step: 1 },
+                Optimisation::RemovedStep { step: 3 },
+                Optimisation::RemovedStep { step: 4 },
+                Optimisation::RemovedStep { step: 5 },
+                Optimisation::RemovedStep { step: 6 },
+            ]
+        );
+        assert_same_final_state(&grid, &optimised.grid, 1e-10);
+    }
+
+    #[test]
+    fn test_merge_rotations() {
+        let grid = UnparsedCircuit::from(vec![
+            vec!["Rz(pi/4)", "Rz(pi/4)", "P(3*pi/2)", "P(pi/2)", "Rx(0.5)", "C_down", "Rx(0.25)"],
+            vec!["Ry(-pi/8)", "H", "Ry(-pi/8)", "I", "I", "X", "I"],
+        ]);
+
+        let optimised = optimise_circuit(&grid).unwrap();
+
+        // The H between the rotations of qubit 1 and the CNOT between those of qubit 0 keep them apart
+        assert_eq!(
+            optimised.grid,
+            UnparsedCircuit::from(vec![
+                vec!["Rz(pi/2)", "I", "I", "Rx(0.5)", "C_down", "Rx(0.25)"],
+                vec!["Ry(-pi/8)", "H", "Ry(-pi/8)", "I", "X", "I"],
+            ])
+        );
+        assert_eq!(
+            optimised.changes,
+            vec![
+                Optimisation::Merged {
+                    first: "Rz(pi/4)".to_string(),
+                    second: "Rz(pi/4)".to_string(),
+                    merged: "Rz(pi/2)".to_string(),
+                    qubits: vec![0],
+                    steps: [0, 1],
+                },
+                Optimisation::Cancelled { first: "P(3*pi/2)".to_string(), second: "P(pi/2)".to_string(), qubits: vec![0], steps: [2, 3] },
+                Optimisation::RemovedStep { step: 3 },
+            ]
+        );
+        assert_same_final_state(&grid, &optimised.grid, 1e-10);
+    }
+
+    #[test]
+    fn test_gates_that_stay() {
+        let grid = UnparsedCircuit::from(vec![vec!["H", "C_down", "H", "T"], vec!["I", "X", "I", "T"]]);
+
+        let optimised = optimise_circuit(&grid).unwrap();
+
+        assert_eq!(optimised.grid, grid);
+        assert!(optimised.changes.is_empty());
+    }
+}
@@ -0,0 +1,217 @@
+// Exports grid circuits as OpenQASM programs, so circuits built in the frontend can be taken to other tools.
+// Row i of the grid becomes qubit q[i], and the gates of each step are written from the top row down.
+
+use crate::simulation::angle::format_angle;
+use crate::simulation::circuit_parser::{operations_in_step, parse_parameterised_token, GridOperation, UnparsedCircuit};
+use crate::simulation::circuit_validator::{validate_grid_input, QuantumCircuitError};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum QasmVersion {
+    #[default]
+    #[serde(rename = "2.0")]
+    V2,
+    #[serde(rename = "3.0")]
+    V3,
+}
+
+pub fn export_qasm(grid: &UnparsedCircuit, version: QasmVersion) -> Result<String, Vec<QuantumCircuitError>> {
+    validate_grid_input(grid)?;
+
+    let no_of_qubits = grid.circuit.len();
+    let mut lines: Vec<String> = match version {
+        QasmVersion::V2 => vec![
+            "OPENQASM 2.0;".to_string(),
+            "include \"qelib1.inc\";".to_string(),
+            format!("qreg q[{}];", no_of_qubits),
+        ],
+        QasmVersion::V3 => vec![
+            "OPENQASM 3.0;".to_string(),
+            "include \"stdgates.inc\";".to_string(),
+            format!("qubit[{}] q;", no_of_qubits),
+        ],
+    };
+
+    for step in 0..grid.circuit[0].len() {
+        for operation in operations_in_step(grid, step).map_err(|error| vec![error])? {
+            let unsupported = |token: &str, qubit: usize| vec![QuantumCircuitError::UnsupportedGate { token: token.to_string(), qubit, step }];
+            match operation {
+                GridOperation::Single { token, .. } if token == "I" => (),
+                GridOperation::Single { token, qubit, .. } => {
+                    let name = gate_name(&token, version).ok_or_else(|| unsupported(&token, qubit))?;
+                    lines.push(format!("{} q[{}];", name, qubit));
+                }
+                GridOperation::Controlled { token, control, target, .. } => {
+                    let name = match (target - control, version) {
+                        (1, _) => controlled_gate_name(&token, version),
+                        // Both standard libraries have the Toffoli gate, only OpenQASM 3.0 can control anything else more than once
+                        (2, _) if token == "X" => Some("ccx".to_string()),
+                        (no_of_controls, QasmVersion::V3) => gate_name(&token, version).map(|name| format!("ctrl({}) @ {}", no_of_controls, name)),
+                        (_, QasmVersion::V2) => None,
+                    };
+                    let name = name.ok_or_else(|| unsupported(&token, target))?;
+                    let qubits: Vec<String> = (control..=target).map(|qubit| format!("q[{}]", qubit)).collect();
+                    lines.push(format!("{} {};", name, qubits.join(", ")));
+                }
+                GridOperation::Swap { upper } => lines.push(format!("swap q[{}], q[{}];", upper, upper + 1)),
+                // Custom gates would have to be decomposed into the gates of the standard library first
+                GridOperation::Custom { token, upper, .. } => return Err(unsupported(&token, upper)),
+            }
+        }
+    }
+
+    lines.push(String::new());
+    Ok(lines.join("\n"))
+}
+
+// The name and parameters of a single qubit gate, e.g. "rz(pi/4)"
+fn gate_name(token: &str, version: QasmVersion) -> Option<String> {
+    match token {
+        "H" | "X" | "Y" | "Z" | "S" | "T" => Some(token.to_lowercase()),
+        "S†" => Some("sdg".to_string()),
+        "T†" => Some("tdg".to_string()),
+        "SX" => Some("sx".to_string()),
+        "SX†" => Some("sxdg".to_string()),
+        _ => {
+            let (name, angles) = parse_parameterised_token(token)?;
+            let name = match (name, version) {
+                ("Rx", _) => "rx",
+                ("Ry", _) => "ry",
+                ("Rz", _) => "rz",
+                ("P", QasmVersion::V2) => "u1",
+                ("P", QasmVersion::V3) => "p",
+                ("U", QasmVersion::V2) => "u3",
+                ("U", QasmVersion::V3) => "U",
+                _ => return None,
+            };
+            Some(with_angles(name, &angles))
+        }
+    }
+}
+
+// The name and parameters of the gate that controls the given single qubit gate
+fn controlled_gate_name(target: &str, version: QasmVersion) -> Option<String> {
+    match (target, version) {
+        ("X" | "Y" | "Z" | "H", _) => Some(format!("c{}", target.to_lowercase())),
+        // qelib1 has no controlled S or T, but they are controlled phases
+        ("S", QasmVersion::V2) => Some(with_angles("cu1", &[std::f64::consts::PI / 2.0])),
+        ("T", QasmVersion::V2) => Some(with_angles("cu1", &[std::f64::consts::PI / 4.0])),
+        ("S†", QasmVersion::V2) => Some(with_angles("cu1", &[-std::f64::consts::PI / 2.0])),
+        ("T†", QasmVersion::V2) => Some(with_angles("cu1", &[-std::f64::consts::PI / 4.0])),
+        (_, QasmVersion::V2) => {
+            let (name, angles) = parse_parameterised_token(target)?;
+            let name = match name {
+                "Rx" => "crx",
+                "Ry" => "cry",
+                "Rz" => "crz",
+                "P" => "cu1",
+                "U" => "cu3",
+                _ => return None,
+            };
+            Some(with_angles(name, &angles))
+        }
+        (_, QasmVersion::V3) => match parse_parameterised_token(target) {
+            Some(("Rx" | "Ry" | "Rz" | "P", _)) => gate_name(target, version).map(|name| format!("c{}", name)),
+            _ => gate_name(target, version).map(|name| format!("ctrl @ {}", name)),
+        },
+    }
+}
+
+fn with_angles(name: &str, angles: &[f64]) -> String {
+    let angles: Vec<String> = angles.iter().map(|angle| format_angle(*angle)).collect();
+    format!("{}({})", name, angles.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::qasm_importer::import_qasm;
+    use crate::simulation::simulator::simulate_circuit_handler;
+
+    #[test]
+    fn test_export_bell_state() {
+        let grid = UnparsedCircuit::from(vec![vec!["H", "C_down"], vec!["I", "X"]]);
+
+        assert_eq!(
+            export_qasm(&grid, QasmVersion::V2).unwrap(),
+            "OPENQASM 2.0;\ninclude \"qelib1.inc\";\nqreg q[2];\nh q[0];\ncx q[0], q[1];\n"
+        );
+        assert_eq!(
+            export_qasm(&grid, QasmVersion::V3).unwrap(),
+            "OPENQASM 3.0;\ninclude \"stdgates.inc\";\nqubit[2] q;\nh q[0];\ncx q[0], q[1];\n"
+        );
+    }
+
+    #[test]
+    fn test_export_parameterised_and_controlled_gates() {
+        let grid = UnparsedCircuit::from(vec![
+            vec!["C_down", "Swap", "C_down", "P(pi/8)"],
+            vec!["S", "Swap", "U(pi,0,pi)", "C_down"],
+            vec!["Rz(pi/4)", "I", "I", "Rx(0.5)"],
+        ]);
+
+        let qasm2 = export_qasm(&grid, QasmVersion::V2).unwrap();
+        let qasm3 = export_qasm(&grid, QasmVersion::V3).unwrap();
+
+        assert!(qasm2.contains("cu1(pi/2) q[0], q[1];\nrz(pi/4) q[2];\nswap q[0], q[1];\ncu3(pi, 0, pi) q[0], q[1];\nu1(pi/8) q[0];\ncrx(0.5) q[1], q[2];"));
+        assert!(qasm3.contains("ctrl @ s q[0], q[1];\nrz(pi/4) q[2];\nswap q[0], q[1];\nctrl @ U(pi, 0, pi) q[0], q[1];\np(pi/8) q[0];\ncrx(0.5) q[1], q[2];"));
+    }
+
+    #[test]
+    fn test_export_stacked_controls() {
+        let grid = UnparsedCircuit::from(vec![vec!["C_down", "C_down"], vec!["C_down", "C_down"], vec!["X", "H"]]);
+
+        assert!(export_qasm(&grid, QasmVersion::V3).unwrap().contains("ccx q[0], q[1], q[2];\nctrl(2) @ h q[0], q[1], q[2];"));
+        assert_eq!(
+            export_qasm(&grid, QasmVersion::V2),
+            Err(vec![QuantumCircuitError::UnsupportedGate { token: "H".to_string(), qubit: 2, step: 1 }])
+        );
+    }
+
+    #[test]
+    fn test_round_trip_through_the_importer() {
+        let grids = vec![
+            vec![vec!["H", "C_down", "T"], vec!["Ry(pi/3)", "Z", "Swap"], vec!["X", "C_down", "Swap"], vec!["I", "H", "S"]],
+            vec![vec!["C_down", "U(pi/2,pi/4,0)"], vec!["T", "C_down"], vec!["Rx(1.25)", "P(-pi/2)"]],
+            vec![vec!["H", "Swap", "C_down"], vec!["S", "Swap", "Rz(pi/8)"]],
+            vec![vec!["H", "C_down", "T†"], vec!["H", "C_down", "S†"], vec!["I", "X", "H"]],
+            vec![vec!["SX", "T"], vec!["H", "SX†"]],
+        ];
+
+        for grid in grids {
+            let grid = UnparsedCircuit::from(grid);
+            let qasm = export_qasm(&grid, QasmVersion::V2).unwrap();
+            let import = import_qasm(&qasm).unwrap();
+
+            let expected = simulate_circuit_handler(grid).unwrap().pop().unwrap();
+            let found = simulate_circuit_handler(import.grid).unwrap().pop().unwrap();
+            for (a, b) in expected.col.iter().zip(found.col.iter()) {
+                assert!((a - b).norm() < 1e-10, "{:?} != {:?}\n{}", expected, found, qasm);
+            }
+        }
+    }
+
+    #[test]
+    fn test_invalid_grids_are_not_exported() {
+        let grid = UnparsedCircuit::from(vec![vec!["A", "Swap"], vec!["H", "I"]]);
+
+        assert_eq!(
+            export_qasm(&grid, QasmVersion::V2),
+            Err(vec![
+                QuantumCircuitError::UnknownGate { token: "A".to_string(), qubit: 0, step: 0 },
+                QuantumCircuitError::UnpairedSwap { token: "Swap".to_string(), qubit: 0, step: 1 },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_custom_gates_are_not_exported() {
+        let mut grid = UnparsedCircuit::from(vec![vec!["H", "Oracle"], vec!["I", "Oracle"]]);
+        grid.custom_gates.insert("Oracle".to_string(), crate::simulation::quantum_gate::QuantumGate::cz_gate());
+
+        assert_eq!(
+            export_qasm(&grid, QasmVersion::V3),
+            Err(vec![QuantumCircuitError::UnsupportedGate { token: "Oracle".to_string(), qubit: 0, step: 1 }])
+        );
+    }
+}
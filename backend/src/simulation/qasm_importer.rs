@@ -0,0 +1,1149 @@
+// Imports OpenQASM 2.0 programs into the grid the frontend edits.
+// Quantum registers become rows in the order they are declared, and the gates are laid out by GridLayout.
+
+use crate::simulation::angle::format_parameterised_token;
+use crate::simulation::circuit_parser::{build_circuit_from_data, ParsedCircuit, UnparsedCircuit};
+use crate::simulation::circuit_validator::{validate_grid_input, QuantumCircuitError, MAX_QUBITS};
+use crate::simulation::grid_layout::GridLayout;
+use serde::ser::SerializeStruct;
+use serde::{Serialize, Serializer};
+use std::collections::HashMap;
+use std::f64::consts::PI;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct QasmImport {
+    pub grid: UnparsedCircuit,
+    pub circuit: ParsedCircuit,
+    pub measurements: Vec<QasmMeasurement>,
+    pub classical_bits: usize,
+}
+
+// A measurement of a qubit into a classical bit, taken before the given step of the grid
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct QasmMeasurement {
+    pub qubit: usize,
+    pub clbit: usize,
+    pub step: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum QasmError {
+    // A token the grammar does not allow at this point
+    UnexpectedToken { line: usize, expected: String, found: String },
+    // The program ended in the middle of a statement
+    UnexpectedEnd { expected: String },
+    UnsupportedVersion { line: usize, version: String },
+    // Only the standard gate library can be included
+    UnsupportedInclude { line: usize, file: String },
+    // A statement that has no equivalent in the grid, such as reset or opaque
+    UnsupportedStatement { line: usize, statement: String },
+    UnknownRegister { line: usize, name: String },
+    // A register declared with no bits, which no statement could use
+    EmptyRegister { line: usize, name: String },
+    DuplicateDefinition { line: usize, name: String },
+    IndexOutOfRange { line: usize, register: String, index: usize, size: usize },
+    UnknownGate { line: usize, name: String },
+    WrongParameterCount { line: usize, name: String, expected: usize, found: usize },
+    WrongQubitCount { line: usize, name: String, expected: usize, found: usize },
+    // Whole registers of different sizes used as arguments of one statement
+    RegisterSizeMismatch { line: usize },
+    // The same qubit given twice to one gate
+    RepeatedQubit { line: usize, qubit: usize },
+    UnknownParameter { line: usize, name: String },
+    // An expression that is not a finite number, such as 1/0
+    InvalidExpression { line: usize },
+    // The grid can not apply gates to a qubit after it has been measured
+    GateAfterMeasurement { line: usize, qubit: usize },
+    // An if statement on a register that was measured, which depends on the outcome
+    ConditionOnMeasuredBits { line: usize, register: String },
+    // Gate definitions that expand to more gates than the grid can be made of
+    TooManyOperations { line: usize, limit: usize },
+    // The imported grid was rejected by the circuit validator or parser
+    Circuit(QuantumCircuitError),
+}
+
+impl QasmError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            QasmError::UnexpectedToken { .. } => "unexpected_token",
+            QasmError::UnexpectedEnd { .. } => "unexpected_end",
+            QasmError::UnsupportedVersion { .. } => "unsupported_version",
+            QasmError::UnsupportedInclude { .. } => "unsupported_include",
+            QasmError::UnsupportedStatement { .. } => "unsupported_statement",
+            QasmError::UnknownRegister { .. } => "unknown_register",
+            QasmError::EmptyRegister { .. } => "empty_register",
+            QasmError::DuplicateDefinition { .. } => "duplicate_definition",
+            QasmError::IndexOutOfRange { .. } => "index_out_of_range",
+            QasmError::UnknownGate { .. } => "unknown_gate",
+            QasmError::WrongParameterCount { .. } => "wrong_parameter_count",
+            QasmError::WrongQubitCount { .. } => "wrong_qubit_count",
+            QasmError::RegisterSizeMismatch { .. } => "register_size_mismatch",
+            QasmError::RepeatedQubit { .. } => "repeated_qubit",
+            QasmError::UnknownParameter { .. } => "unknown_parameter",
+            QasmError::InvalidExpression { .. } => "invalid_expression",
+            QasmError::GateAfterMeasurement { .. } => "gate_after_measurement",
+            QasmError::ConditionOnMeasuredBits { .. } => "condition_on_measured_bits",
+            QasmError::TooManyOperations { .. } => "too_many_operations",
+            QasmError::Circuit(error) => error.code(),
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            QasmError::UnexpectedToken { line, expected, found } => {
+                format!("Expected {} on line {}, found {}", expected, line, found)
+            }
+            QasmError::UnexpectedEnd { expected } => format!("The program ended where {} was expected", expected),
+            QasmError::UnsupportedVersion { line, version } => {
+                format!("OpenQASM {} on line {} is not supported, only version 2.0 is", version, line)
+            }
+            QasmError::UnsupportedInclude { line, file } => {
+                format!("'{}' on line {} can not be included, only \"qelib1.inc\" can", file, line)
+            }
+            QasmError::UnsupportedStatement { line, statement } => {
+                format!("'{}' on line {} can not be shown in the grid", statement, line)
+            }
+            QasmError::UnknownRegister { line, name } => format!("Register '{}' on line {} is not declared", name, line),
+            QasmError::EmptyRegister { line, name } => format!("Register '{}' on line {} has no bits", name, line),
+            QasmError::DuplicateDefinition { line, name } => format!("'{}' on line {} is already declared", name, line),
+            QasmError::IndexOutOfRange { line, register, index, size } => format!(
+                "Index {} on line {} is outside of register '{}' of size {}",
+                index, line, register, size
+            ),
+            QasmError::UnknownGate { line, name } => format!("Gate '{}' on line {} is not defined", name, line),
+            QasmError::WrongParameterCount { line, name, expected, found } => format!(
+                "Gate '{}' on line {} takes {} parameters, but {} were given",
+                name, line, expected, found
+            ),
+            QasmError::WrongQubitCount { line, name, expected, found } => format!(
+                "Gate '{}' on line {} acts on {} qubits, but {} were given",
+                name, line, expected, found
+            ),
+            QasmError::RegisterSizeMismatch { line } => format!("The registers on line {} have different sizes", line),
+            QasmError::RepeatedQubit { line, qubit } => format!("Qubit {} is used twice by the gate on line {}", qubit, line),
+            QasmError::UnknownParameter { line, name } => format!("Parameter '{}' on line {} is not defined", name, line),
+            QasmError::InvalidExpression { line } => format!("The expression on line {} is not a finite number", line),
+            QasmError::GateAfterMeasurement { line, qubit } => format!(
+                "The gate on line {} acts on qubit {} after it was measured, which the grid can not show",
+                line, qubit
+            ),
+            QasmError::ConditionOnMeasuredBits { line, register } => format!(
+                "The condition on line {} depends on the measured register '{}', which the grid can not show",
+                line, register
+            ),
+            QasmError::TooManyOperations { line, limit } => {
+                format!("The program expands to more than {} gates on line {}", limit, line)
+            }
+            QasmError::Circuit(error) => error.message(),
+        }
+    }
+
+    // The line of the program the error points to, if it can be tied to one
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            QasmError::UnexpectedToken { line, .. }
+            | QasmError::UnsupportedVersion { line, .. }
+            | QasmError::UnsupportedInclude { line, .. }
+            | QasmError::UnsupportedStatement { line, .. }
+            | QasmError::UnknownRegister { line, .. }
+            | QasmError::EmptyRegister { line, .. }
+            | QasmError::DuplicateDefinition { line, .. }
+            | QasmError::IndexOutOfRange { line, .. }
+            | QasmError::UnknownGate { line, .. }
+            | QasmError::WrongParameterCount { line, .. }
+            | QasmError::WrongQubitCount { line, .. }
+            | QasmError::RegisterSizeMismatch { line }
+            | QasmError::RepeatedQubit { line, .. }
+            | QasmError::UnknownParameter { line, .. }
+            | QasmError::InvalidExpression { line }
+            | QasmError::GateAfterMeasurement { line, .. }
+            | QasmError::ConditionOnMeasuredBits { line, .. }
+            | QasmError::TooManyOperations { line, .. } => Some(*line),
+            QasmError::UnexpectedEnd { .. } | QasmError::Circuit(_) => None,
+        }
+    }
+}
+
+// Errors in the grid keep the shape of circuit errors, errors in the program point to a line instead
+impl Serialize for QasmError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if let QasmError::Circuit(error) = self {
+            return error.serialize(serializer);
+        }
+        let mut error = serializer.serialize_struct("QasmError", 3)?;
+        error.serialize_field("code", self.code())?;
+        error.serialize_field("message", &self.message())?;
+        error.serialize_field("line", &self.line())?;
+        error.end()
+    }
+}
+
+pub fn import_qasm(source: &str) -> Result<QasmImport, Vec<QasmError>> {
+    let tokens = tokenize(source).map_err(|error| vec![error])?;
+    let mut importer = Importer { tokens, position: 0, ..Importer::default() };
+    importer.run().map_err(|error| vec![error])?;
+
+    let classical_bits = importer.classical_bits();
+    let Importer { layout, measurements, .. } = importer;
+    let grid = layout.into_grid();
+    validate_grid_input(&grid).map_err(|errors| errors.into_iter().map(QasmError::Circuit).collect::<Vec<QasmError>>())?;
+    let circuit = build_circuit_from_data(grid.clone()).map_err(|error| vec![QasmError::Circuit(error)])?;
+
+    Ok(QasmImport { grid, circuit, measurements, classical_bits })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Identifier(String),
+    Number(String),
+    Str(String),
+    Symbol(&'static str),
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        match self {
+            Token::Identifier(name) | Token::Number(name) => format!("'{}'", name),
+            Token::Str(text) => format!("\"{}\"", text),
+            Token::Symbol(symbol) => format!("'{}'", symbol),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct LexedToken {
+    token: Token,
+    line: usize,
+}
+
+const SYMBOLS: [&str; 15] = ["->", "==", ";", ",", "(", ")", "[", "]", "{", "}", "+", "-", "*", "/", "^"];
+
+fn tokenize(source: &str) -> Result<Vec<LexedToken>, QasmError> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut position = 0;
+    let mut line = 1;
+
+    while position < chars.len() {
+        let c = chars[position];
+        let rest: String = chars[position..chars.len().min(position + 2)].iter().collect();
+        if c == '\n' {
+            line += 1;
+            position += 1;
+        } else if c.is_whitespace() {
+            position += 1;
+        } else if rest == "//" {
+            while position < chars.len() && chars[position] != '\n' {
+                position += 1;
+            }
+        } else if rest == "/*" {
+            position += 2;
+            while position < chars.len() && !(chars[position] == '*' && chars.get(position + 1) == Some(&'/')) {
+                if chars[position] == '\n' {
+                    line += 1;
+                }
+                position += 1;
+            }
+            position += 2;
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let start = position;
+            while position < chars.len() && (chars[position].is_ascii_alphanumeric() || chars[position] == '_') {
+                position += 1;
+            }
+            tokens.push(LexedToken { token: Token::Identifier(chars[start..position].iter().collect()), line });
+        } else if c.is_ascii_digit() || (c == '.' && chars.get(position + 1).is_some_and(|c| c.is_ascii_digit())) {
+            let start = position;
+            while position < chars.len() && (chars[position].is_ascii_digit() || chars[position] == '.') {
+                position += 1;
+            }
+            if position < chars.len() && (chars[position] == 'e' || chars[position] == 'E') {
+                position += 1;
+                if position < chars.len() && (chars[position] == '+' || chars[position] == '-') {
+                    position += 1;
+                }
+                while position < chars.len() && chars[position].is_ascii_digit() {
+                    position += 1;
+                }
+            }
+            tokens.push(LexedToken { token: Token::Number(chars[start..position].iter().collect()), line });
+        } else if c == '"' {
+            let start = position + 1;
+            position = start;
+            while position < chars.len() && chars[position] != '"' && chars[position] != '\n' {
+                position += 1;
+            }
+            if chars.get(position) != Some(&'"') {
+                return Err(QasmError::UnexpectedToken { line, expected: "'\"'".to_string(), found: "the end of the line".to_string() });
+            }
+            tokens.push(LexedToken { token: Token::Str(chars[start..position].iter().collect()), line });
+            position += 1;
+        } else if let Some(symbol) = SYMBOLS.iter().find(|symbol| rest.starts_with(**symbol)) {
+            tokens.push(LexedToken { token: Token::Symbol(symbol), line });
+            position += symbol.len();
+        } else {
+            return Err(QasmError::UnexpectedToken { line, expected: "a statement".to_string(), found: format!("'{}'", c) });
+        }
+    }
+    Ok(tokens)
+}
+
+// Parameter expressions, kept unevaluated inside gate definitions until the gate is applied
+#[derive(Debug, Clone, PartialEq)]
+enum Expression {
+    Number(f64),
+    Parameter(String),
+    Negate(Box<Expression>),
+    Binary(&'static str, Box<Expression>, Box<Expression>),
+    Function(String, Box<Expression>),
+}
+
+impl Expression {
+    fn evaluate(&self, parameters: &HashMap<String, f64>, line: usize) -> Result<f64, QasmError> {
+        let value = match self {
+            Expression::Number(value) => *value,
+            Expression::Parameter(name) => *parameters
+                .get(name)
+                .ok_or_else(|| QasmError::UnknownParameter { line, name: name.clone() })?,
+            Expression::Negate(inner) => -inner.evaluate(parameters, line)?,
+            Expression::Binary(operator, lhs, rhs) => {
+                let (lhs, rhs) = (lhs.evaluate(parameters, line)?, rhs.evaluate(parameters, line)?);
+                match *operator {
+                    "+" => lhs + rhs,
+                    "-" => lhs - rhs,
+                    "*" => lhs * rhs,
+                    "/" => lhs / rhs,
+                    _ => lhs.powf(rhs),
+                }
+            }
+            Expression::Function(name, inner) => {
+                let inner = inner.evaluate(parameters, line)?;
+                match name.as_str() {
+                    "sin" => inner.sin(),
+                    "cos" => inner.cos(),
+                    "tan" => inner.tan(),
+                    "exp" => inner.exp(),
+                    "ln" => inner.ln(),
+                    _ => inner.sqrt(),
+                }
+            }
+        };
+        if value.is_finite() {
+            Ok(value)
+        } else {
+            Err(QasmError::InvalidExpression { line })
+        }
+    }
+}
+
+// Gates and barriers a program may expand to, as definitions calling each other twice grow exponentially
+const MAX_OPERATIONS: usize = 10_000;
+
+const FUNCTIONS: [&str; 6] = ["sin", "cos", "tan", "exp", "ln", "sqrt"];
+
+// A register, or a single qubit or bit of one
+#[derive(Debug, Clone)]
+struct Argument {
+    register: String,
+    index: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+struct Register {
+    name: String,
+    offset: usize,
+    size: usize,
+}
+
+// A gate applied inside the body of a gate definition
+#[derive(Debug, Clone)]
+struct GateCall {
+    name: String,
+    parameters: Vec<Expression>,
+    qubits: Vec<String>,
+    line: usize,
+}
+
+#[derive(Debug, Clone)]
+enum GateBodyStatement {
+    Call(GateCall),
+    Barrier(Vec<String>),
+}
+
+#[derive(Debug, Clone)]
+struct GateDefinition {
+    parameters: Vec<String>,
+    qubits: Vec<String>,
+    body: Vec<GateBodyStatement>,
+    // The gates and barriers the body expands to, counted once when the gate is defined
+    operations: usize,
+}
+
+// The number of parameters and qubits of the gates that are translated directly instead of through a definition
+fn builtin_signature(name: &str) -> Option<(usize, usize)> {
+    match name {
+        "id" | "u0" | "x" | "y" | "z" | "h" | "s" | "sdg" | "t" | "tdg" | "sx" | "sxdg" => Some((0, 1)),
+        "u1" | "p" | "rx" | "ry" | "rz" => Some((1, 1)),
+        "u2" => Some((2, 1)),
+        "U" | "u3" | "u" => Some((3, 1)),
+        "CX" | "cx" | "cy" | "cz" | "ch" | "swap" => Some((0, 2)),
+        "crx" | "cry" | "crz" | "cu1" | "cp" => Some((1, 2)),
+        "cu3" => Some((3, 2)),
+        "ccx" | "cswap" => Some((0, 3)),
+        _ => None,
+    }
+}
+
+// The decomposition of ccx in qelib1 with the controls a and b and the target c, in gates of qelib1:
+// 6 CNOTs and 7 T and T† gates between two H gates on the target
+pub fn ccx_decomposition(a: usize, b: usize, c: usize) -> Vec<(&'static str, Vec<usize>)> {
+    vec![
+        ("h", vec![c]),
+        ("cx", vec![b, c]),
+        ("tdg", vec![c]),
+        ("cx", vec![a, c]),
+        ("t", vec![c]),
+        ("cx", vec![b, c]),
+        ("tdg", vec![c]),
+        ("cx", vec![a, c]),
+        ("t", vec![b]),
+        ("t", vec![c]),
+        ("h", vec![c]),
+        ("cx", vec![a, b]),
+        ("t", vec![a]),
+        ("tdg", vec![b]),
+        ("cx", vec![a, b]),
+    ]
+}
+
+#[derive(Debug, Default)]
+struct Importer {
+    tokens: Vec<LexedToken>,
+    position: usize,
+    quantum_registers: Vec<Register>,
+    classical_registers: Vec<Register>,
+    definitions: HashMap<String, GateDefinition>,
+    layout: GridLayout,
+    measured_qubits: Vec<bool>,
+    measurements: Vec<QasmMeasurement>,
+    operations: usize,
+}
+
+impl Importer {
+    fn classical_bits(&self) -> usize {
+        self.classical_registers.iter().map(|register| register.size).sum()
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|lexed| &lexed.token)
+    }
+
+    fn line(&self) -> usize {
+        self.tokens
+            .get(self.position)
+            .or(self.tokens.last())
+            .map_or(1, |lexed| lexed.line)
+    }
+
+    fn unexpected(&self, expected: &str) -> QasmError {
+        match self.peek() {
+            Some(token) => QasmError::UnexpectedToken { line: self.line(), expected: expected.to_string(), found: token.describe() },
+            None => QasmError::UnexpectedEnd { expected: expected.to_string() },
+        }
+    }
+
+    fn next_is(&self, symbol: &str) -> bool {
+        match self.peek() {
+            Some(Token::Symbol(found)) => *found == symbol,
+            Some(Token::Identifier(found)) => found == symbol,
+            _ => false,
+        }
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), QasmError> {
+        if !self.next_is(symbol) {
+            return Err(self.unexpected(&format!("'{}'", symbol)));
+        }
+        self.position += 1;
+        Ok(())
+    }
+
+    fn expect_identifier(&mut self) -> Result<String, QasmError> {
+        match self.peek() {
+            Some(Token::Identifier(name)) => {
+                let name = name.clone();
+                self.position += 1;
+                Ok(name)
+            }
+            _ => Err(self.unexpected("a name")),
+        }
+    }
+
+    fn expect_integer(&mut self) -> Result<usize, QasmError> {
+        match self.peek().cloned() {
+            Some(Token::Number(text)) => {
+                let value = text.parse().map_err(|_| self.unexpected("a whole number"))?;
+                self.position += 1;
+                Ok(value)
+            }
+            _ => Err(self.unexpected("a whole number")),
+        }
+    }
+
+    fn run(&mut self) -> Result<(), QasmError> {
+        if self.next_is("OPENQASM") {
+            self.position += 1;
+            let line = self.line();
+            let version = match self.peek() {
+                Some(Token::Number(version)) => version.clone(),
+                _ => return Err(self.unexpected("a version number")),
+            };
+            if version != "2.0" && version != "2" {
+                return Err(QasmError::UnsupportedVersion { line, version });
+            }
+            self.position += 1;
+            self.expect(";")?;
+        }
+        while self.peek().is_some() {
+            self.statement()?;
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self) -> Result<(), QasmError> {
+        let line = self.line();
+        let keyword = match self.peek() {
+            Some(Token::Identifier(keyword)) => keyword.clone(),
+            _ => return Err(self.unexpected("a statement")),
+        };
+        match keyword.as_str() {
+            "include" => {
+                self.position += 1;
+                let file = match self.peek() {
+                    Some(Token::Str(file)) => file.clone(),
+                    _ => return Err(self.unexpected("a file name")),
+                };
+                if file != "qelib1.inc" {
+                    return Err(QasmError::UnsupportedInclude { line, file });
+                }
+                self.position += 1;
+                self.expect(";")
+            }
+            "qreg" | "creg" => {
+                self.position += 1;
+                let name = self.expect_identifier()?;
+                self.expect("[")?;
+                let size = self.expect_integer()?;
+                self.expect("]")?;
+                self.expect(";")?;
+                self.declare_register(keyword == "qreg", name, size, line)
+            }
+            "gate" => self.gate_definition(),
+            "opaque" => Err(QasmError::UnsupportedStatement { line, statement: keyword }),
+            "if" => {
+                self.position += 1;
+                self.expect("(")?;
+                let register = self.expect_identifier()?;
+                self.expect("==")?;
+                let value = self.expect_integer()?;
+                self.expect(")")?;
+                let register = self.find_register(false, &register, line)?;
+                let measured = self
+                    .measurements
+                    .iter()
+                    .any(|measurement| (register.offset..register.offset + register.size).contains(&measurement.clbit));
+                if measured {
+                    return Err(QasmError::ConditionOnMeasuredBits { line, register: register.name });
+                }
+                // Nothing has been written to the register yet, so it still holds zero and the
+                // condition can be decided here. An operation that never runs is parsed but not placed
+                self.operation(value == 0)
+            }
+            _ => self.operation(true),
+        }
+    }
+
+    fn declare_register(&mut self, quantum: bool, name: String, size: usize, line: usize) -> Result<(), QasmError> {
+        let taken = self.quantum_registers.iter().chain(self.classical_registers.iter()).any(|register| register.name == name);
+        if taken {
+            return Err(QasmError::DuplicateDefinition { line, name });
+        }
+        if size == 0 {
+            return Err(QasmError::EmptyRegister { line, name });
+        }
+        if quantum {
+            let offset = self.layout.no_of_qubits();
+            // Checked before the rows are made, as the size can be anything up to usize::MAX
+            if offset.saturating_add(size) > MAX_QUBITS {
+                return Err(QasmError::Circuit(QuantumCircuitError::TooManyQubits));
+            }
+            self.layout.add_qubits(size);
+            self.measured_qubits.extend(std::iter::repeat_n(false, size));
+            self.quantum_registers.push(Register { name, offset, size });
+        } else {
+            let offset = self.classical_bits();
+            self.classical_registers.push(Register { name, offset, size });
+        }
+        Ok(())
+    }
+
+    fn find_register(&self, quantum: bool, name: &str, line: usize) -> Result<Register, QasmError> {
+        let registers = if quantum { &self.quantum_registers } else { &self.classical_registers };
+        registers
+            .iter()
+            .find(|register| register.name == name)
+            .cloned()
+            .ok_or_else(|| QasmError::UnknownRegister { line, name: name.to_string() })
+    }
+
+    fn argument(&mut self) -> Result<Argument, QasmError> {
+        let register = self.expect_identifier()?;
+        let mut index = None;
+        if self.next_is("[") {
+            self.position += 1;
+            index = Some(self.expect_integer()?);
+            self.expect("]")?;
+        }
+        Ok(Argument { register, index })
+    }
+
+    fn arguments(&mut self) -> Result<Vec<Argument>, QasmError> {
+        let mut arguments = vec![self.argument()?];
+        while self.next_is(",") {
+            self.position += 1;
+            arguments.push(self.argument()?);
+        }
+        Ok(arguments)
+    }
+
+    // Expand whole registers so that the statement is repeated for each of their bits
+    fn broadcast(&self, quantum: &[bool], arguments: &[Argument], line: usize) -> Result<Vec<Vec<usize>>, QasmError> {
+        let mut resolved: Vec<Vec<usize>> = Vec::new();
+        for (argument, quantum) in arguments.iter().zip(quantum) {
+            let register = self.find_register(*quantum, &argument.register, line)?;
+            match argument.index {
+                Some(index) if index >= register.size => {
+                    return Err(QasmError::IndexOutOfRange { line, register: register.name, index, size: register.size })
+                }
+                Some(index) => resolved.push(vec![register.offset + index]),
+                None => resolved.push((register.offset..register.offset + register.size).collect()),
+            }
+        }
+
+        let whole_registers: Vec<usize> = arguments
+            .iter()
+            .zip(resolved.iter())
+            .filter(|(argument, _)| argument.index.is_none())
+            .map(|(_, bits)| bits.len())
+            .collect();
+        if whole_registers.windows(2).any(|sizes| sizes[0] != sizes[1]) {
+            return Err(QasmError::RegisterSizeMismatch { line });
+        }
+        let repetitions = whole_registers.first().copied().unwrap_or(1);
+
+        Ok((0..repetitions)
+            .map(|repetition| resolved.iter().map(|bits| if bits.len() == 1 { bits[0] } else { bits[repetition] }).collect())
+            .collect())
+    }
+
+    fn expressions(&mut self) -> Result<Vec<Expression>, QasmError> {
+        let mut expressions = Vec::new();
+        if self.next_is("(") {
+            self.position += 1;
+            if !self.next_is(")") {
+                expressions.push(self.sum()?);
+                while self.next_is(",") {
+                    self.position += 1;
+                    expressions.push(self.sum()?);
+                }
+            }
+            self.expect(")")?;
+        }
+        Ok(expressions)
+    }
+
+    fn sum(&mut self) -> Result<Expression, QasmError> {
+        let mut expression = self.product()?;
+        while let Some(operator) = ["+", "-"].into_iter().find(|operator| self.next_is(operator)) {
+            self.position += 1;
+            expression = Expression::Binary(operator, Box::new(expression), Box::new(self.product()?));
+        }
+        Ok(expression)
+    }
+
+    fn product(&mut self) -> Result<Expression, QasmError> {
+        let mut expression = self.unary()?;
+        while let Some(operator) = ["*", "/"].into_iter().find(|operator| self.next_is(operator)) {
+            self.position += 1;
+            expression = Expression::Binary(operator, Box::new(expression), Box::new(self.unary()?));
+        }
+        Ok(expression)
+    }
+
+    fn unary(&mut self) -> Result<Expression, QasmError> {
+        if self.next_is("-") {
+            self.position += 1;
+            return Ok(Expression::Negate(Box::new(self.unary()?)));
+        }
+        if self.next_is("+") {
+            self.position += 1;
+            return self.unary();
+        }
+        let base = self.atom()?;
+        if self.next_is("^") {
+            self.position += 1;
+            return Ok(Expression::Binary("^", Box::new(base), Box::new(self.unary()?)));
+        }
+        Ok(base)
+    }
+
+    fn atom(&mut self) -> Result<Expression, QasmError> {
+        match self.peek().cloned() {
+            Some(Token::Number(text)) => {
+                let value = text.parse().map_err(|_| self.unexpected("a number"))?;
+                self.position += 1;
+                Ok(Expression::Number(value))
+            }
+            Some(Token::Identifier(name)) if name == "pi" => {
+                self.position += 1;
+                Ok(Expression::Number(PI))
+            }
+            Some(Token::Identifier(name)) if FUNCTIONS.contains(&name.as_str()) => {
+                self.position += 1;
+                self.expect("(")?;
+                let inner = self.sum()?;
+                self.expect(")")?;
+                Ok(Expression::Function(name, Box::new(inner)))
+            }
+            Some(Token::Identifier(name)) => {
+                self.position += 1;
+                Ok(Expression::Parameter(name))
+            }
+            Some(Token::Symbol("(")) => {
+                self.position += 1;
+                let inner = self.sum()?;
+                self.expect(")")?;
+                Ok(inner)
+            }
+            _ => Err(self.unexpected("an expression")),
+        }
+    }
+
+    fn identifiers(&mut self) -> Result<Vec<String>, QasmError> {
+        let mut names = vec![self.expect_identifier()?];
+        while self.next_is(",") {
+            self.position += 1;
+            names.push(self.expect_identifier()?);
+        }
+        Ok(names)
+    }
+
+    fn gate_definition(&mut self) -> Result<(), QasmError> {
+        self.position += 1;
+        let line = self.line();
+        let name = self.expect_identifier()?;
+        if self.definitions.contains_key(&name) || builtin_signature(&name).is_some() {
+            return Err(QasmError::DuplicateDefinition { line, name });
+        }
+        let mut parameters = Vec::new();
+        if self.next_is("(") {
+            self.position += 1;
+            if !self.next_is(")") {
+                parameters = self.identifiers()?;
+            }
+            self.expect(")")?;
+        }
+        let qubits = self.identifiers()?;
+        self.expect("{")?;
+
+        let mut body = Vec::new();
+        while !self.next_is("}") {
+            let line = self.line();
+            let gate_name = self.expect_identifier()?;
+            if gate_name == "barrier" {
+                let names = self.identifiers()?;
+                self.expect(";")?;
+                self.check_names(&names, &qubits, line)?;
+                body.push(GateBodyStatement::Barrier(names));
+                continue;
+            }
+            // Only gates defined before this one can be used, which also rules out recursion
+            if !self.definitions.contains_key(&gate_name) && builtin_signature(&gate_name).is_none() {
+                return Err(QasmError::UnknownGate { line, name: gate_name });
+            }
+            let call_parameters = self.expressions()?;
+            let call_qubits = self.identifiers()?;
+            self.expect(";")?;
+            self.check_names(&call_qubits, &qubits, line)?;
+            body.push(GateBodyStatement::Call(GateCall { name: gate_name, parameters: call_parameters, qubits: call_qubits, line }));
+        }
+        self.expect("}")?;
+
+        let operations = body.iter().fold(0, |operations: usize, statement| {
+            operations.saturating_add(match statement {
+                GateBodyStatement::Call(call) => self.operations_of(&call.name),
+                GateBodyStatement::Barrier(_) => 1,
+            })
+        });
+        self.definitions.insert(name, GateDefinition { parameters, qubits, body, operations });
+        Ok(())
+    }
+
+    // The gates and barriers a gate expands to, where gates of qelib1 count as one
+    fn operations_of(&self, name: &str) -> usize {
+        self.definitions.get(name).map_or(1, |definition| definition.operations)
+    }
+
+    // Count the operations a statement places before placing them, so that nothing is expanded past the limit
+    fn count_operations(&mut self, operations: usize, line: usize) -> Result<(), QasmError> {
+        self.operations = self.operations.saturating_add(operations);
+        if self.operations > MAX_OPERATIONS {
+            return Err(QasmError::TooManyOperations { line, limit: MAX_OPERATIONS });
+        }
+        Ok(())
+    }
+
+    fn check_names(&self, names: &[String], declared: &[String], line: usize) -> Result<(), QasmError> {
+        match names.iter().find(|name| !declared.contains(name)) {
+            Some(name) => Err(QasmError::UnknownRegister { line, name: name.clone() }),
+            None => Ok(()),
+        }
+    }
+
+    // A gate, measure, reset or barrier statement, which is only checked and not placed when it does not run
+    fn operation(&mut self, runs: bool) -> Result<(), QasmError> {
+        let line = self.line();
+        let name = self.expect_identifier()?;
+        match name.as_str() {
+            "measure" => {
+                let qubit = self.argument()?;
+                self.expect("->")?;
+                let clbit = self.argument()?;
+                self.expect(";")?;
+                let bits = self.broadcast(&[true, false], &[qubit, clbit], line)?;
+                for bits in bits.into_iter().filter(|_| runs) {
+                    let step = self.layout.next_free_step(bits[0]);
+                    self.measured_qubits[bits[0]] = true;
+                    self.measurements.push(QasmMeasurement { qubit: bits[0], clbit: bits[1], step });
+                }
+                Ok(())
+            }
+            "reset" => {
+                let argument = self.argument()?;
+                self.expect(";")?;
+                // Qubits start in |0>, so a reset before anything touched the qubit does nothing
+                for bits in self.broadcast(&[true], &[argument], line)? {
+                    if runs && (self.layout.next_free_step(bits[0]) > 0 || self.measured_qubits[bits[0]]) {
+                        return Err(QasmError::UnsupportedStatement { line, statement: name });
+                    }
+                }
+                Ok(())
+            }
+            "barrier" => {
+                let arguments = self.arguments()?;
+                self.expect(";")?;
+                let quantum = vec![true; arguments.len()];
+                let qubits: Vec<usize> = self.broadcast(&quantum, &arguments, line)?.concat();
+                if runs {
+                    self.count_operations(1, line)?;
+                    self.layout.barrier(&qubits);
+                }
+                Ok(())
+            }
+            _ => {
+                let expressions = self.expressions()?;
+                let arguments = self.arguments()?;
+                self.expect(";")?;
+                let parameters = expressions
+                    .iter()
+                    .map(|expression| expression.evaluate(&HashMap::new(), line))
+                    .collect::<Result<Vec<f64>, QasmError>>()?;
+                let quantum = vec![true; arguments.len()];
+                let broadcast = self.broadcast(&quantum, &arguments, line)?;
+                if !runs {
+                    return self.check_gate(&name, &parameters, &broadcast[0], line);
+                }
+                self.count_operations(self.operations_of(&name).saturating_mul(broadcast.len()), line)?;
+                for qubits in broadcast {
+                    self.apply_gate(&name, &parameters, &qubits, line)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    // Make sure the gate exists and gets the right number of parameters and distinct qubits
+    fn check_gate(&self, name: &str, parameters: &[f64], qubits: &[usize], line: usize) -> Result<(), QasmError> {
+        if let Some(index) = (1..qubits.len()).find(|index| qubits[..*index].contains(&qubits[*index])) {
+            return Err(QasmError::RepeatedQubit { line, qubit: qubits[index] });
+        }
+
+        let (no_of_parameters, no_of_qubits) = match self.definitions.get(name) {
+            Some(definition) => (definition.parameters.len(), definition.qubits.len()),
+            None => builtin_signature(name).ok_or_else(|| QasmError::UnknownGate { line, name: name.to_string() })?,
+        };
+        if parameters.len() != no_of_parameters {
+            return Err(QasmError::WrongParameterCount { line, name: name.to_string(), expected: no_of_parameters, found: parameters.len() });
+        }
+        if qubits.len() != no_of_qubits {
+            return Err(QasmError::WrongQubitCount { line, name: name.to_string(), expected: no_of_qubits, found: qubits.len() });
+        }
+        Ok(())
+    }
+
+    fn apply_gate(&mut self, name: &str, parameters: &[f64], qubits: &[usize], line: usize) -> Result<(), QasmError> {
+        self.check_gate(name, parameters, qubits, line)?;
+        if let Some(qubit) = qubits.iter().find(|qubit| self.measured_qubits[**qubit]) {
+            return Err(QasmError::GateAfterMeasurement { line, qubit: *qubit });
+        }
+
+        match self.definitions.get(name).cloned() {
+            Some(definition) => self.apply_definition(&definition, parameters, qubits),
+            None => self.apply_builtin(name, parameters, qubits, line),
+        }
+    }
+
+    fn apply_definition(&mut self, definition: &GateDefinition, parameters: &[f64], qubits: &[usize]) -> Result<(), QasmError> {
+        let values: HashMap<String, f64> = definition.parameters.iter().cloned().zip(parameters.iter().copied()).collect();
+        let positions: HashMap<&String, usize> = definition.qubits.iter().zip(qubits.iter().copied()).collect();
+        for call in definition.body.iter() {
+            match call {
+                GateBodyStatement::Barrier(names) => {
+                    let barrier_qubits: Vec<usize> = names.iter().map(|name| positions[name]).collect();
+                    self.layout.barrier(&barrier_qubits);
+                }
+                GateBodyStatement::Call(call) => {
+                    let call_parameters = call
+                        .parameters
+                        .iter()
+                        .map(|expression| expression.evaluate(&values, call.line))
+                        .collect::<Result<Vec<f64>, QasmError>>()?;
+                    let call_qubits: Vec<usize> = call.qubits.iter().map(|name| positions[name]).collect();
+                    self.apply_gate(&call.name, &call_parameters, &call_qubits, call.line)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // The gates of qelib1 are mapped onto grid tokens directly, which keeps the imported grid readable.
+    // The signature has already been checked
+    fn apply_builtin(&mut self, name: &str, parameters: &[f64], qubits: &[usize], line: usize) -> Result<(), QasmError> {
+        let layout = &mut self.layout;
+        match name {
+            // Wires are already the identity
+            "id" | "u0" => (),
+            "x" => layout.single("X".to_string(), qubits[0]),
+            "y" => layout.single("Y".to_string(), qubits[0]),
+            "z" => layout.single("Z".to_string(), qubits[0]),
+            "h" => layout.single("H".to_string(), qubits[0]),
+            "s" => layout.single("S".to_string(), qubits[0]),
+            "t" => layout.single("T".to_string(), qubits[0]),
+            "sdg" => layout.single("S†".to_string(), qubits[0]),
+            "tdg" => layout.single("T†".to_string(), qubits[0]),
+            "sx" => layout.single("SX".to_string(), qubits[0]),
+            "sxdg" => layout.single("SX†".to_string(), qubits[0]),
+            "u1" | "p" => layout.single(format_parameterised_token("P", parameters), qubits[0]),
+            "rx" => layout.single(format_parameterised_token("Rx", parameters), qubits[0]),
+            "ry" => layout.single(format_parameterised_token("Ry", parameters), qubits[0]),
+            "rz" => layout.single(format_parameterised_token("Rz", parameters), qubits[0]),
+            "u2" => layout.single(format_parameterised_token("U", &[PI / 2.0, parameters[0], parameters[1]]), qubits[0]),
+            "U" | "u3" | "u" => layout.single(format_parameterised_token("U", parameters), qubits[0]),
+            "CX" | "cx" => layout.controlled("X".to_string(), qubits[0], qubits[1]),
+            "cy" => layout.controlled("Y".to_string(), qubits[0], qubits[1]),
+            "ch" => layout.controlled("H".to_string(), qubits[0], qubits[1]),
+            "crx" => layout.controlled(format_parameterised_token("Rx", parameters), qubits[0], qubits[1]),
+            "cry" => layout.controlled(format_parameterised_token("Ry", parameters), qubits[0], qubits[1]),
+            "crz" => layout.controlled(format_parameterised_token("Rz", parameters), qubits[0], qubits[1]),
+            "cu3" => layout.controlled(format_parameterised_token("U", parameters), qubits[0], qubits[1]),
+            // Controlled phases are symmetric, so the upper qubit can always be the control
+            "cz" => layout.controlled("Z".to_string(), qubits[0].min(qubits[1]), qubits[0].max(qubits[1])),
+            "cu1" | "cp" => layout.controlled(format_parameterised_token("P", parameters), qubits[0].min(qubits[1]), qubits[0].max(qubits[1])),
+            "swap" => layout.swap(qubits[0], qubits[1]),
+            "ccx" => {
+                for (gate, gate_qubits) in ccx_decomposition(qubits[0], qubits[1], qubits[2]) {
+                    self.apply_builtin(gate, &[], &gate_qubits, line)?;
+                }
+            }
+            "cswap" => {
+                let (a, b, c) = (qubits[0], qubits[1], qubits[2]);
+                self.layout.controlled("X".to_string(), c, b);
+                self.apply_builtin("ccx", &[], &[a, b, c], line)?;
+                self.layout.controlled("X".to_string(), c, b);
+            }
+            _ => return Err(QasmError::UnknownGate { line, name: name.to_string() }),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::quantum_state::QuantumState;
+    use crate::simulation::simulation_backend::BackendKind;
+    use crate::simulation::simulator::simulate_circuit;
+    use crate::simulation::test_support::assert_states_close;
+
+    // The state after the last step, which every backend has to agree on
+    fn final_state(import: &QasmImport) -> QuantumState {
+        let states: Vec<QuantumState> = BackendKind::all()
+            .into_iter()
+            .map(|backend_kind| simulate_circuit(import.circuit.clone(), backend_kind.create().as_mut()).unwrap().pop().unwrap())
+            .collect();
+        for state in states.iter() {
+            assert_states_close(std::slice::from_ref(state), &states[..1]);
+        }
+        states[0].clone()
+    }
+
+    #[test]
+    fn test_import_bell_state() {
+        let source = r#"
+            OPENQASM 2.0;
+            include "qelib1.inc";
+            qreg q[2];
+            creg c[2];
+            h q[0];
+            cx q[0], q[1];
+            measure q -> c;
+        "#;
+
+        let import = import_qasm(source).unwrap();
+
+        assert_eq!(import.grid, UnparsedCircuit::from(vec![vec!["H", "C_down"], vec!["I", "X"]]));
+        assert_eq!(import.classical_bits, 2);
+        assert_eq!(
+            import.measurements,
+            vec![QasmMeasurement { qubit: 0, clbit: 0, step: 2 }, QasmMeasurement { qubit: 1, clbit: 1, step: 2 }]
+        );
+    }
+
+    #[test]
+    fn test_gates_are_packed_into_steps() {
+        let source = "qreg a[1]; qreg b[2]; h a[0]; x b; barrier a, b[0]; z b[0]; rz(pi/4) a[0];";
+
+        let import = import_qasm(source).unwrap();
+
+        assert_eq!(
+            import.grid,
+            UnparsedCircuit::from(vec![vec!["H", "Rz(pi/4)"], vec!["X", "Z"], vec!["X", "I"]])
+        );
+    }
+
+    #[test]
+    fn test_distant_and_upward_controls_are_routed() {
+        let source = "qreg q[3]; x q[2]; cx q[2], q[0]; cx q[0], q[1];";
+
+        let import = import_qasm(source).unwrap();
+
+        assert_states_close(&[final_state(&import)], &[QuantumState::new(&[1, 1, 1]).unwrap()]);
+        assert!(import.grid.circuit.concat().contains(&"Swap".to_string()));
+    }
+
+    #[test]
+    fn test_toffoli_and_fredkin() {
+        for (a, b) in [(0, 0), (0, 1), (1, 0), (1, 1)] {
+            let mut source = String::from("qreg q[3];");
+            if a == 1 {
+                source.push_str("x q[0];");
+            }
+            if b == 1 {
+                source.push_str("x q[1];");
+            }
+            source.push_str("ccx q[0], q[1], q[2];");
+
+            let import = import_qasm(&source).unwrap();
+
+            assert_states_close(&[final_state(&import)], &[QuantumState::new(&[a, b, a & b]).unwrap()]);
+        }
+
+        let import = import_qasm("qreg q[3]; x q[0]; x q[1]; cswap q[0], q[1], q[2];").unwrap();
+        assert_states_close(&[final_state(&import)], &[QuantumState::new(&[1, 0, 1]).unwrap()]);
+    }
+
+    #[test]
+    fn test_gate_definitions_with_parameters() {
+        let source = r#"
+            qreg q[2];
+            gate flip(theta) a, b { u3(theta, 0, pi) a; barrier a, b; cx a, b; }
+            flip(2 * pi / 2) q[0], q[1];
+        "#;
+
+        let import = import_qasm(source).unwrap();
+
+        assert_states_close(&[final_state(&import)], &[QuantumState::new(&[1, 1]).unwrap()]);
+    }
+
+    #[test]
+    fn test_expansion_is_limited() {
+        let mut source = "qreg q[1];\ngate g0 a { h a; }\n".to_string();
+        for k in 1..40 {
+            source += &format!("gate g{} a {{ g{} a; g{} a; }}\n", k, k - 1, k - 1);
+        }
+        source += "g39 q[0];";
+        assert_eq!(import_qasm(&source), Err(vec![QasmError::TooManyOperations { line: 42, limit: MAX_OPERATIONS }]));
+
+        let source = "qreg q[2];\ngate g a { h a; h a; }\ngate gg a { g a; g a; }\ngg q;";
+        assert_eq!(import_qasm(source).unwrap().grid.circuit[0].len(), 4);
+    }
+
+    #[test]
+    fn test_conditions_on_unmeasured_registers() {
+        let source = "qreg q[1]; creg c[1]; if (c == 0) x q[0]; if (c == 1) h q[0];";
+
+        let import = import_qasm(source).unwrap();
+
+        assert_eq!(import.grid, UnparsedCircuit::from(vec![vec!["X"]]));
+    }
+
+    #[test]
+    fn test_import_errors_point_to_the_line() {
+        assert_eq!(
+            import_qasm("qreg q[1];\nfoo q[0];"),
+            Err(vec![QasmError::UnknownGate { line: 2, name: "foo".to_string() }])
+        );
+        assert_eq!(
+            import_qasm("OPENQASM 3.0;"),
+            Err(vec![QasmError::UnsupportedVersion { line: 1, version: "3.0".to_string() }])
+        );
+        assert_eq!(
+            import_qasm("qreg q[1];\ncreg c[1];\nmeasure q[0] -> c[0];\nx q[0];"),
+            Err(vec![QasmError::GateAfterMeasurement { line: 4, qubit: 0 }])
+        );
+        assert_eq!(
+            import_qasm("qreg q[2];\ncx q[0], q[2];"),
+            Err(vec![QasmError::IndexOutOfRange { line: 2, register: "q".to_string(), index: 2, size: 2 }])
+        );
+        assert_eq!(
+            import_qasm("qreg q[2];\ncx q[0], q[0];"),
+            Err(vec![QasmError::RepeatedQubit { line: 2, qubit: 0 }])
+        );
+        assert_eq!(
+            import_qasm("qreg q[1];\nrx(1/0) q[0];"),
+            Err(vec![QasmError::InvalidExpression { line: 2 }])
+        );
+        assert_eq!(
+            import_qasm("qreg a[1];\nqreg q[0];\ncreg c[1];\nif (c==1) x q;"),
+            Err(vec![QasmError::EmptyRegister { line: 2, name: "q".to_string() }])
+        );
+        assert_eq!(import_qasm("qreg q[1];\ncreg c[0];"), Err(vec![QasmError::EmptyRegister { line: 2, name: "c".to_string() }]));
+        assert_eq!(import_qasm("qreg q[7];"), Err(vec![QasmError::Circuit(QuantumCircuitError::TooManyQubits)]));
+        assert_eq!(
+            import_qasm("qreg q[4];\nqreg r[4000000000];"),
+            Err(vec![QasmError::Circuit(QuantumCircuitError::TooManyQubits)])
+        );
+        assert_eq!(
+            import_qasm("qreg q[18446744073709551615];"),
+            Err(vec![QasmError::Circuit(QuantumCircuitError::TooManyQubits)])
+        );
+    }
+
+    #[test]
+    fn test_error_serializes_with_line() {
+        let error = QasmError::UnknownGate { line: 3, name: "foo".to_string() };
+        assert_eq!(
+            serde_json::to_value(&error).unwrap(),
+            serde_json::json!({"code": "unknown_gate", "message": "Gate 'foo' on line 3 is not defined", "line": 3})
+        );
+    }
+}
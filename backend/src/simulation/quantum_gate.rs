@@ -1,5 +1,5 @@
 use ndarray::linalg::kron;
-use ndarray::{arr2, Array2};
+use ndarray::{arr2, s, Array2};
 use num::Complex;
 
 // QuantumGate struct
@@ -99,38 +99,124 @@ impl QuantumGate {
         }
     }
 
-    pub fn c_down(gate_underneath: QuantumGate) -> QuantumGate {
+    pub fn sdg_gate() -> QuantumGate {
         QuantumGate {
             matrix: arr2(&[
+                [Complex::new(1.0, 0.0), Complex::new(0.0, 0.0)],
+                [Complex::new(0.0, 0.0), Complex::new(0.0, -1.0)],
+            ]),
+            size: 1,
+        }
+    }
+
+    pub fn tdg_gate() -> QuantumGate {
+        QuantumGate {
+            matrix: arr2(&[
+                [Complex::new(1.0, 0.0), Complex::new(0.0, 0.0)],
                 [
-                    Complex::new(1.0, 0.0),
-                    Complex::new(0.0, 0.0),
-                    Complex::new(0.0, 0.0),
-                    Complex::new(0.0, 0.0),
-                ],
-                [
-                    Complex::new(0.0, 0.0),
-                    Complex::new(1.0, 0.0),
-                    Complex::new(0.0, 0.0),
-                    Complex::new(0.0, 0.0),
-                ],
-                [
-                    Complex::new(0.0, 0.0),
-                    Complex::new(0.0, 0.0),
-                    gate_underneath.matrix[[0,0]],
-                    gate_underneath.matrix[[0,1]],
-                ],
-                [
-                    Complex::new(0.0, 0.0),
                     Complex::new(0.0, 0.0),
-                    gate_underneath.matrix[[1,0]],
-                    gate_underneath.matrix[[1,1]],
+                    Complex::new(
+                        std::f64::consts::FRAC_1_SQRT_2,
+                        -std::f64::consts::FRAC_1_SQRT_2,
+                    ),
                 ],
             ]),
-            size: 2,
+            size: 1,
         }
     }
 
+    // The square root of X
+    pub fn sx_gate() -> QuantumGate {
+        QuantumGate {
+            matrix: arr2(&[
+                [Complex::new(0.5, 0.5), Complex::new(0.5, -0.5)],
+                [Complex::new(0.5, -0.5), Complex::new(0.5, 0.5)],
+            ]),
+            size: 1,
+        }
+    }
+
+    pub fn sxdg_gate() -> QuantumGate {
+        QuantumGate {
+            matrix: arr2(&[
+                [Complex::new(0.5, -0.5), Complex::new(0.5, 0.5)],
+                [Complex::new(0.5, 0.5), Complex::new(0.5, -0.5)],
+            ]),
+            size: 1,
+        }
+    }
+
+    pub fn rx_gate(theta: f64) -> QuantumGate {
+        let (sin, cos) = (theta / 2.0).sin_cos();
+        QuantumGate {
+            matrix: arr2(&[
+                [Complex::new(cos, 0.0), Complex::new(0.0, -sin)],
+                [Complex::new(0.0, -sin), Complex::new(cos, 0.0)],
+            ]),
+            size: 1,
+        }
+    }
+
+    pub fn ry_gate(theta: f64) -> QuantumGate {
+        let (sin, cos) = (theta / 2.0).sin_cos();
+        QuantumGate {
+            matrix: arr2(&[
+                [Complex::new(cos, 0.0), Complex::new(-sin, 0.0)],
+                [Complex::new(sin, 0.0), Complex::new(cos, 0.0)],
+            ]),
+            size: 1,
+        }
+    }
+
+    pub fn rz_gate(theta: f64) -> QuantumGate {
+        QuantumGate {
+            matrix: arr2(&[
+                [Complex::from_polar(1.0, -theta / 2.0), Complex::new(0.0, 0.0)],
+                [Complex::new(0.0, 0.0), Complex::from_polar(1.0, theta / 2.0)],
+            ]),
+            size: 1,
+        }
+    }
+
+    // Phase gate, diag(1, e^(i lambda)), which is S for pi/2 and T for pi/4
+    pub fn phase_gate(lambda: f64) -> QuantumGate {
+        QuantumGate {
+            matrix: arr2(&[
+                [Complex::new(1.0, 0.0), Complex::new(0.0, 0.0)],
+                [Complex::new(0.0, 0.0), Complex::from_polar(1.0, lambda)],
+            ]),
+            size: 1,
+        }
+    }
+
+    // General single qubit gate, the same matrix as u3 in OpenQASM's qelib1
+    pub fn u_gate(theta: f64, phi: f64, lambda: f64) -> QuantumGate {
+        let (sin, cos) = (theta / 2.0).sin_cos();
+        QuantumGate {
+            matrix: arr2(&[
+                [Complex::new(cos, 0.0), -Complex::from_polar(sin, lambda)],
+                [Complex::from_polar(sin, phi), Complex::from_polar(cos, phi + lambda)],
+            ]),
+            size: 1,
+        }
+    }
+
+    // The gate underneath controlled by the qubit above it, which works for gates on any number of qubits
+    pub fn c_down(gate_underneath: QuantumGate) -> QuantumGate {
+        let dimension = gate_underneath.matrix.nrows();
+        let mut matrix = Array2::<Complex<f64>>::eye(2 * dimension);
+        matrix.slice_mut(s![dimension.., dimension..]).assign(&gate_underneath.matrix);
+        QuantumGate {
+            matrix,
+            size: gate_underneath.size + 1,
+        }
+    }
+
+    // The gate underneath controlled by all of the given number of qubits above it, e.g. Toffoli for X and 2 controls
+    pub fn multi_controlled(gate_underneath: QuantumGate, no_of_controls: usize) -> QuantumGate {
+        (0..no_of_controls).fold(gate_underneath, |gate, _| QuantumGate::c_down(gate))
+    }
+
     pub fn c_up(gate_above: QuantumGate) -> QuantumGate {
         QuantumGate {
             matrix: arr2(&[
@@ -347,6 +433,22 @@ impl QuantumGate {
         }
     }
 
+    // The inverse of the gate, its conjugate transpose
+    pub fn adjoint(&self) -> QuantumGate {
+        QuantumGate {
+            matrix: self.matrix.t().mapv(|entry| entry.conj()),
+            size: self.size,
+        }
+    }
+
+    // The gate that applies this gate and then the given one on the same qubits
+    pub fn followed_by(&self, later: &QuantumGate) -> QuantumGate {
+        QuantumGate {
+            matrix: later.matrix.dot(&self.matrix),
+            size: self.size,
+        }
+    }
+
     // Combine two gates using the Kronecker product
     pub fn kronecker(self, other: QuantumGate) -> QuantumGate {
         QuantumGate {
@@ -359,6 +461,7 @@ impl QuantumGate {
 #[cfg(test)]
 mod tests {
     use crate::simulation::quantum_state::QuantumState;
+    use crate::simulation::test_support::assert_gates_close;
 
     use super::*;
 
@@ -366,11 +469,11 @@ mod tests {
     fn test_x_gate() {
         // X|0> -> |1>
         // X |1> -> |0>
-        let state = QuantumState::new(&[0]).apply_gate(QuantumGate::x_gate());
+        let state = QuantumState::new(&[0]).unwrap().apply_gate(QuantumGate::x_gate()).unwrap();
         let final_state = arr2(&[[Complex::new(0.0, 0.0)], [Complex::new(1.0, 0.0)]]);
         assert_eq!(state.col, final_state);
 
-        let state = QuantumState::new(&[1]).apply_gate(QuantumGate::x_gate());
+        let state = QuantumState::new(&[1]).unwrap().apply_gate(QuantumGate::x_gate()).unwrap();
         let final_state = arr2(&[[Complex::new(1.0, 0.0)], [Complex::new(0.0, 0.0)]]);
         assert_eq!(state.col, final_state);
     }
@@ -379,11 +482,11 @@ mod tests {
     fn test_i_gate() {
         // I|0> -> |0>
         // I|1> -> |1>
-        let state = QuantumState::new(&[0]).apply_gate(QuantumGate::i_gate());
+        let state = QuantumState::new(&[0]).unwrap().apply_gate(QuantumGate::i_gate()).unwrap();
         let final_state = arr2(&[[Complex::new(1.0, 0.0)], [Complex::new(0.0, 0.0)]]);
         assert_eq!(state.col, final_state);
 
-        let state = QuantumState::new(&[1]).apply_gate(QuantumGate::i_gate());
+        let state = QuantumState::new(&[1]).unwrap().apply_gate(QuantumGate::i_gate()).unwrap();
         let final_state = arr2(&[[Complex::new(0.0, 0.0)], [Complex::new(1.0, 0.0)]]);
         assert_eq!(state.col, final_state);
     }
@@ -392,11 +495,11 @@ mod tests {
     fn test_y_gate() {
         // Y|0> -> i|1>
         // Y|1> -> -i|0>
-        let state = QuantumState::new(&[0]).apply_gate(QuantumGate::y_gate());
+        let state = QuantumState::new(&[0]).unwrap().apply_gate(QuantumGate::y_gate()).unwrap();
         let final_state = arr2(&[[Complex::new(0.0, 0.0)], [Complex::new(0.0, 1.0)]]);
         assert_eq!(state.col, final_state);
 
-        let state = QuantumState::new(&[1]).apply_gate(QuantumGate::y_gate());
+        let state = QuantumState::new(&[1]).unwrap().apply_gate(QuantumGate::y_gate()).unwrap();
         let final_state = arr2(&[[Complex::new(0.0, -1.0)], [Complex::new(0.0, 0.0)]]);
         assert_eq!(state.col, final_state);
     }
@@ -405,11 +508,11 @@ mod tests {
     fn test_z_gate() {
         // Z|0> -> |0>
         // Z|1> -> -|1>
-        let state = QuantumState::new(&[0]).apply_gate(QuantumGate::z_gate());
+        let state = QuantumState::new(&[0]).unwrap().apply_gate(QuantumGate::z_gate()).unwrap();
         let final_state = arr2(&[[Complex::new(1.0, 0.0)], [Complex::new(0.0, 0.0)]]);
         assert_eq!(state.col, final_state);
 
-        let state = QuantumState::new(&[1]).apply_gate(QuantumGate::z_gate());
+        let state = QuantumState::new(&[1]).unwrap().apply_gate(QuantumGate::z_gate()).unwrap();
         let final_state = arr2(&[[Complex::new(0.0, 0.0)], [Complex::new(-1.0, 0.0)]]);
         assert_eq!(state.col, final_state);
     }
@@ -418,14 +521,14 @@ mod tests {
     fn test_h_gate() {
         // H|0> -> (|0> + |1>) / √2
         // H|1> -> (|0> - |1>) / √2
-        let state = QuantumState::new(&[0]).apply_gate(QuantumGate::h_gate());
+        let state = QuantumState::new(&[0]).unwrap().apply_gate(QuantumGate::h_gate()).unwrap();
         let final_state = arr2(&[
             [Complex::new(1.0 / 2.0_f64.sqrt(), 0.0)],
             [Complex::new(1.0 / 2.0_f64.sqrt(), 0.0)],
         ]);
         assert_eq!(state.col, final_state);
 
-        let state = QuantumState::new(&[1]).apply_gate(QuantumGate::h_gate());
+        let state = QuantumState::new(&[1]).unwrap().apply_gate(QuantumGate::h_gate()).unwrap();
         let final_state = arr2(&[
             [Complex::new(1.0 / 2.0_f64.sqrt(), 0.0)],
             [Complex::new(-1.0 / 2.0_f64.sqrt(), 0.0)],
@@ -439,7 +542,7 @@ mod tests {
         // CNOT|01> -> |01>
         // CNOT|10> -> |11>
         // CNOT|11> -> |10>
-        let state = QuantumState::new(&[0, 0]).apply_gate(QuantumGate::cnot_gate());
+        let state = QuantumState::new(&[0, 0]).unwrap().apply_gate(QuantumGate::cnot_gate()).unwrap();
 
         let expected_result = arr2(&[
             [Complex::new(1.0, 0.0)],
@@ -449,7 +552,7 @@ mod tests {
         ]);
         assert_eq!(state.col, expected_result);
 
-        let state = QuantumState::new(&[0, 1]).apply_gate(QuantumGate::cnot_gate());
+        let state = QuantumState::new(&[0, 1]).unwrap().apply_gate(QuantumGate::cnot_gate()).unwrap();
 
         let expected_result = arr2(&[
             [Complex::new(0.0, 0.0)],
@@ -459,7 +562,7 @@ mod tests {
         ]);
         assert_eq!(state.col, expected_result);
 
-        let state = QuantumState::new(&[1, 0]).apply_gate(QuantumGate::cnot_gate());
+        let state = QuantumState::new(&[1, 0]).unwrap().apply_gate(QuantumGate::cnot_gate()).unwrap();
 
         let expected_result = arr2(&[
             [Complex::new(0.0, 0.0)],
@@ -470,7 +573,7 @@ mod tests {
 
         assert_eq!(state.col, expected_result);
 
-        let state = QuantumState::new(&[1, 1]).apply_gate(QuantumGate::cnot_gate());
+        let state = QuantumState::new(&[1, 1]).unwrap().apply_gate(QuantumGate::cnot_gate()).unwrap();
 
         let expected_result = arr2(&[
             [Complex::new(0.0, 0.0)],
@@ -485,7 +588,7 @@ mod tests {
     #[test]
     fn test_swap_gate() {
         // Swap state of two qubits: |01> should become |10>
-        let state = QuantumState::new(&[0, 1]).apply_gate(QuantumGate::swap_gate());
+        let state = QuantumState::new(&[0, 1]).unwrap().apply_gate(QuantumGate::swap_gate()).unwrap();
 
         let expected_result = arr2(&[
             [Complex::new(0.0, 0.0)],
@@ -500,7 +603,7 @@ mod tests {
     #[test]
     fn test_toffoli_gate() {
         // Apply Toffoli gate (CCNOT): |110> should become |111>
-        let state = QuantumState::new(&[1, 1, 0]).apply_gate(QuantumGate::ccnot_gate());
+        let state = QuantumState::new(&[1, 1, 0]).unwrap().apply_gate(QuantumGate::ccnot_gate()).unwrap();
 
         let expected_result = arr2(&[
             [Complex::new(0.0, 0.0)],
@@ -514,5 +617,28 @@ mod tests {
         ]);
 
         assert_eq!(state.col, expected_result);
+        assert_eq!(QuantumGate::multi_controlled(QuantumGate::x_gate(), 2), QuantumGate::ccnot_gate());
+    }
+
+    #[test]
+    fn test_rotation_gates() {
+        use std::f64::consts::PI;
+
+        assert_gates_close(&QuantumGate::phase_gate(PI / 2.0), &QuantumGate::s_gate());
+        assert_gates_close(&QuantumGate::phase_gate(PI / 4.0), &QuantumGate::t_gate());
+        assert_gates_close(&QuantumGate::phase_gate(-PI / 2.0), &QuantumGate::sdg_gate());
+        assert_gates_close(&QuantumGate::t_gate().adjoint(), &QuantumGate::tdg_gate());
+        assert_gates_close(&QuantumGate::t_gate().followed_by(&QuantumGate::t_gate()), &QuantumGate::s_gate());
+        assert_gates_close(&QuantumGate::sx_gate().followed_by(&QuantumGate::sx_gate()), &QuantumGate::x_gate());
+        assert_gates_close(&QuantumGate::sx_gate().adjoint(), &QuantumGate::sxdg_gate());
+        assert_gates_close(&QuantumGate::s_gate().followed_by(&QuantumGate::h_gate()), &QuantumGate { matrix: QuantumGate::h_gate().matrix.dot(&QuantumGate::s_gate().matrix), size: 1 });
+        assert_gates_close(&QuantumGate::u_gate(PI, 0.0, PI), &QuantumGate::x_gate());
+        assert_gates_close(&QuantumGate::u_gate(PI / 2.0, 0.0, PI), &QuantumGate::h_gate());
+        assert_gates_close(&QuantumGate::ry_gate(PI), &QuantumGate::u_gate(PI, 0.0, 0.0));
+
+        // Rx(pi) = -iX and Rz(pi) = -iZ
+        let minus_i = QuantumGate { matrix: QuantumGate::i_gate().matrix * Complex::new(0.0, -1.0), size: 1 };
+        assert_gates_close(&QuantumGate::rx_gate(PI), &QuantumGate { matrix: minus_i.matrix.dot(&QuantumGate::x_gate().matrix), size: 1 });
+        assert_gates_close(&QuantumGate::rz_gate(PI), &QuantumGate { matrix: minus_i.matrix.dot(&QuantumGate::z_gate().matrix), size: 1 });
     }
 }
@@ -1,11 +1,17 @@
+use crate::simulation::circuit_validator::QuantumCircuitError;
 use crate::simulation::quantum_gate::QuantumGate;
 
 use ndarray::Array2;
 use ndarray::linalg::kron;
-use num::{Complex, ToPrimitive};
+use num::Complex;
+use rand::{Rng, RngCore};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+// Amplitudes with a smaller magnitude than this are dropped from sparse states
+pub const SPARSE_EPSILON: f64 = 1e-12;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
 pub struct QuantumStep {
     pub states: Vec<QuantumStateWrapper>,
 }
@@ -22,17 +28,20 @@ pub struct QuantumState {
 }
 
 impl QuantumState {
-    pub fn new(bits: &[usize]) -> QuantumState {
+    pub fn new(bits: &[usize]) -> Result<QuantumState, QuantumCircuitError> {
         let no_of_qubits = bits.len();
 
-        if !(1..=6).contains(&no_of_qubits) {
-            panic!("Number of qubits must be between 1 and 6, inclusive");
+        if no_of_qubits < 1 {
+            return Err(QuantumCircuitError::TooFewQubits);
+        }
+        if no_of_qubits > 6 {
+            return Err(QuantumCircuitError::TooManyQubits);
         }
 
         let mut index = 0_usize;
         for (i, &bit) in bits.iter().enumerate() {
             if bit != 0 && bit != 1 {
-                panic!("Bits must be 0 or 1");
+                return Err(QuantumCircuitError::InvalidBasisState);
             }
             index += bit << (no_of_qubits - i - 1);
         }
@@ -41,31 +50,125 @@ impl QuantumState {
             Array2::<Complex<f64>>::zeros((2_usize.pow(no_of_qubits as u32), 1));
         col[[index, 0]] = Complex::new(1.0, 0.0);
 
-        QuantumState { col }
+        Ok(QuantumState { col })
     }
 
     // Calculate the number of qubits in the QuantumState
     pub fn size(&self) -> usize {
-        self.col.len().ilog2().to_usize().unwrap()
+        self.col.len().max(1).ilog2() as usize
     }
 
-    // Apply a QuantumGate to a QuantumState, panic if gate and state are not of the same size, if the gate size is 0, return the state unchanged
-    pub fn apply_gate(self, gate: QuantumGate) -> QuantumState {
+    // Apply a QuantumGate to a QuantumState, fail if gate and state are not of the same size, if the gate size is 0, return the state unchanged
+    pub fn apply_gate(self, gate: QuantumGate) -> Result<QuantumState, QuantumCircuitError> {
         if gate.size == 0 {
-            return self;
+            return Ok(self);
         }
 
-        if self.size() != gate.size {
-            panic!(
-                "Trying to apply a gate for {} qubits to a state with {} qubits",
-                gate.size,
-                self.size()
-            )
+        if self.size() != gate.size || gate.matrix.dim() != (self.col.len(), self.col.len()) {
+            return Err(QuantumCircuitError::GateSizeMismatch {
+                gate_size: gate.size,
+                state_size: self.size(),
+            });
         }
 
         let col = gate.matrix.dot(&self.clone().col);
 
-        QuantumState { col }
+        Ok(QuantumState { col })
+    }
+
+    // Apply a QuantumGate to a subset of the qubits in a QuantumState.
+    // Positions are indices into the state, where position 0 is the most significant qubit,
+    // and are given in the same order as the qubits the gate matrix expects
+    pub fn apply_gate_on_positions(&self, gate: &QuantumGate, positions: &[usize]) -> Result<QuantumState, QuantumCircuitError> {
+        let no_of_qubits = self.size();
+        check_gate_positions(gate, positions, no_of_qubits)?;
+
+        let masks: Vec<usize> = positions
+            .iter()
+            .map(|position| 1 << (no_of_qubits - position - 1))
+            .collect();
+        let combined_mask = masks.iter().fold(0, |acc, mask| acc | mask);
+        let gate_dimension = 1 << gate.size;
+
+        let mut col = Array2::<Complex<f64>>::zeros(self.col.raw_dim());
+        for base in (0..self.col.len()).filter(|index| index & combined_mask == 0) {
+            let indices: Vec<usize> = (0..gate_dimension)
+                .map(|sub_index| spread_bits(sub_index, &masks) | base)
+                .collect();
+            for (row, &target) in indices.iter().enumerate() {
+                col[[target, 0]] = indices
+                    .iter()
+                    .enumerate()
+                    .map(|(column, &source)| gate.matrix[[row, column]] * self.col[[source, 0]])
+                    .sum();
+            }
+        }
+
+        Ok(QuantumState { col })
+    }
+
+    // Probability of measuring the qubit at the given position as 1
+    pub fn probability_of_one(&self, position: usize) -> Result<f64, QuantumCircuitError> {
+        let mask = position_mask(position, self.size())?;
+        Ok(self.col
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| index & mask != 0)
+            .map(|(_, amplitude)| amplitude.norm_sqr())
+            .sum())
+    }
+
+    // Collapse the qubit at the given position to the given outcome and renormalize the state
+    pub fn collapse(&self, position: usize, outcome: u8) -> Result<QuantumState, QuantumCircuitError> {
+        let mask = position_mask(position, self.size())?;
+        let mut col = self.col.clone();
+        for (index, amplitude) in col.iter_mut().enumerate() {
+            if (index & mask != 0) != (outcome == 1) {
+                *amplitude = Complex::new(0.0, 0.0);
+            }
+        }
+
+        let norm = col.iter().map(|amplitude| amplitude.norm_sqr()).sum::<f64>().sqrt();
+        if norm > 0.0 {
+            col.mapv_inplace(|amplitude| amplitude / norm);
+        }
+
+        Ok(QuantumState { col })
+    }
+
+    // Probability of measuring each basis state
+    pub fn probabilities(&self) -> Vec<f64> {
+        self.col.iter().map(|amplitude| amplitude.norm_sqr()).collect()
+    }
+
+    // The amplitudes with a larger magnitude than the threshold, keyed by the index of their basis state in binary
+    pub fn amplitudes_above(&self, threshold: f64) -> BTreeMap<String, Complex<f64>> {
+        let no_of_qubits = self.size();
+        self.col
+            .iter()
+            .enumerate()
+            .filter(|(_, amplitude)| amplitude.norm() > threshold)
+            .map(|(index, amplitude)| (format!("{:0width$b}", index, width = no_of_qubits), *amplitude))
+            .collect()
+    }
+
+    // Measure every qubit the given number of times, counted by the index of the basis state measured
+    pub fn sample(&self, shots: usize, rng: &mut dyn RngCore) -> BTreeMap<usize, usize> {
+        let probabilities = self.probabilities();
+        let mut counts = BTreeMap::new();
+        for _ in 0..shots {
+            let mut remaining = rng.gen::<f64>();
+            // Rounding can leave a little probability at the end, which goes to the last possible outcome
+            let index = probabilities
+                .iter()
+                .position(|probability| {
+                    remaining -= probability;
+                    remaining < 0.0
+                })
+                .unwrap_or_else(|| probabilities.iter().rposition(|probability| *probability > 0.0).unwrap_or(0));
+            *counts.entry(index).or_insert(0) += 1;
+        }
+        counts
     }
 
     pub fn kronecker(&self, other: QuantumState) -> QuantumState {
@@ -74,15 +177,164 @@ impl QuantumState {
     }
 }
 
+// A state vector that only stores the non-zero amplitudes, keyed by basis state index.
+// Qubit 0 is the most significant bit, as in QuantumState.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SparseQuantumState {
+    pub no_of_qubits: usize,
+    pub amplitudes: HashMap<usize, Complex<f64>>,
+}
+
+impl SparseQuantumState {
+    pub fn new(no_of_qubits: usize) -> SparseQuantumState {
+        SparseQuantumState {
+            no_of_qubits,
+            amplitudes: HashMap::from([(0, Complex::new(1.0, 0.0))]),
+        }
+    }
+
+    // Fraction of the basis states that have a non-zero amplitude
+    pub fn density(&self) -> f64 {
+        self.amplitudes.len() as f64 / (1_usize << self.no_of_qubits) as f64
+    }
+
+    // Apply a QuantumGate to the qubits at the given positions, position 0 being the most significant qubit
+    pub fn apply_gate_on_positions(&self, gate: &QuantumGate, positions: &[usize]) -> Result<SparseQuantumState, QuantumCircuitError> {
+        check_gate_positions(gate, positions, self.no_of_qubits)?;
+        let masks: Vec<usize> = positions
+            .iter()
+            .map(|position| 1 << (self.no_of_qubits - position - 1))
+            .collect();
+        let combined_mask = masks.iter().fold(0, |acc, mask| acc | mask);
+
+        let mut amplitudes: HashMap<usize, Complex<f64>> = HashMap::new();
+        for (&index, &amplitude) in self.amplitudes.iter() {
+            let base = index & !combined_mask;
+            let column = gather_bits(index, &masks);
+            for row in 0..(1 << gate.size) {
+                let factor = gate.matrix[[row, column]];
+                if factor.norm() == 0.0 {
+                    continue;
+                }
+                *amplitudes
+                    .entry(base | spread_bits(row, &masks))
+                    .or_insert(Complex::new(0.0, 0.0)) += factor * amplitude;
+            }
+        }
+        amplitudes.retain(|_, amplitude| amplitude.norm() > SPARSE_EPSILON);
+
+        Ok(SparseQuantumState { no_of_qubits: self.no_of_qubits, amplitudes })
+    }
+
+    // Probability of measuring the qubit at the given position as 1
+    pub fn probability_of_one(&self, position: usize) -> Result<f64, QuantumCircuitError> {
+        let mask = position_mask(position, self.no_of_qubits)?;
+        Ok(self.amplitudes
+            .iter()
+            .filter(|(index, _)| *index & mask != 0)
+            .map(|(_, amplitude)| amplitude.norm_sqr())
+            .sum())
+    }
+
+    // Collapse the qubit at the given position to the given outcome and renormalize the state
+    pub fn collapse(&self, position: usize, outcome: u8) -> Result<SparseQuantumState, QuantumCircuitError> {
+        let mask = position_mask(position, self.no_of_qubits)?;
+        let mut amplitudes = self.amplitudes.clone();
+        amplitudes.retain(|index, _| (index & mask != 0) == (outcome == 1));
+
+        let norm = amplitudes.values().map(|amplitude| amplitude.norm_sqr()).sum::<f64>().sqrt();
+        if norm > 0.0 {
+            amplitudes.values_mut().for_each(|amplitude| *amplitude /= norm);
+        }
+
+        Ok(SparseQuantumState { no_of_qubits: self.no_of_qubits, amplitudes })
+    }
+
+    pub fn to_dense(&self) -> QuantumState {
+        let mut col = Array2::<Complex<f64>>::zeros((1 << self.no_of_qubits, 1));
+        for (&index, &amplitude) in self.amplitudes.iter() {
+            col[[index, 0]] = amplitude;
+        }
+        QuantumState { col }
+    }
+}
+
+impl From<&QuantumState> for SparseQuantumState {
+    fn from(state: &QuantumState) -> Self {
+        SparseQuantumState {
+            no_of_qubits: state.size(),
+            amplitudes: state
+                .col
+                .iter()
+                .enumerate()
+                .filter(|(_, amplitude)| amplitude.norm() > SPARSE_EPSILON)
+                .map(|(index, amplitude)| (index, *amplitude))
+                .collect(),
+        }
+    }
+}
+
+// The bit mask of the qubit at the given position in a register of the given size
+fn position_mask(position: usize, no_of_qubits: usize) -> Result<usize, QuantumCircuitError> {
+    if position >= no_of_qubits {
+        return Err(QuantumCircuitError::QubitOutOfRange { qubit: position, no_of_qubits });
+    }
+    Ok(1 << (no_of_qubits - position - 1))
+}
+
+// Check that a gate fits the positions it is applied to and that they are distinct and inside the register
+fn check_gate_positions(gate: &QuantumGate, positions: &[usize], no_of_qubits: usize) -> Result<(), QuantumCircuitError> {
+    let dimension = 1_usize.checked_shl(positions.len() as u32).unwrap_or(0);
+    if gate.size != positions.len() || gate.matrix.dim() != (dimension, dimension) {
+        return Err(QuantumCircuitError::GateSizeMismatch {
+            gate_size: gate.size,
+            state_size: positions.len(),
+        });
+    }
+    for (index, &position) in positions.iter().enumerate() {
+        position_mask(position, no_of_qubits)?;
+        if positions[..index].contains(&position) {
+            return Err(QuantumCircuitError::GateSizeMismatch {
+                gate_size: gate.size,
+                state_size: positions.len(),
+            });
+        }
+    }
+    Ok(())
+}
+
+// Collect the bits of a register index selected by the masks into a gate-local index,
+// the last mask giving the least significant bit
+pub(crate) fn gather_bits(index: usize, masks: &[usize]) -> usize {
+    masks
+        .iter()
+        .rev()
+        .enumerate()
+        .filter(|(_, mask)| index & **mask != 0)
+        .fold(0, |acc, (bit, _)| acc | (1 << bit))
+}
+
+// Spread the bits of a gate-local index over the masks of the qubits it acts on,
+// the last mask receiving the least significant bit
+pub(crate) fn spread_bits(sub_index: usize, masks: &[usize]) -> usize {
+    masks
+        .iter()
+        .rev()
+        .enumerate()
+        .filter(|(bit, _)| sub_index & (1 << bit) != 0)
+        .fold(0, |acc, (_, mask)| acc | mask)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use ndarray::arr2;
+    use rand::SeedableRng;
 
     // Test that a simple state is correctly initialized
     #[test]
     fn test_simple_state() {
-        let state = QuantumState::new(&[0]);
+        let state = QuantumState::new(&[0]).unwrap();
         let expected_state = arr2(&[[Complex::new(1.0, 0.0)], [Complex::new(0.0, 0.0)]]);
 
         assert_eq!(state.col, expected_state);
@@ -91,7 +343,7 @@ mod tests {
     // Test that a larger state is correctly initialized
     #[test]
     fn test_large_state() {
-        let state = QuantumState::new(&[0, 0, 0]);
+        let state = QuantumState::new(&[0, 0, 0]).unwrap();
         let expected_state = arr2(&[
             [Complex::new(1.0, 0.0)],
             [Complex::new(0.0, 0.0)],
@@ -109,7 +361,7 @@ mod tests {
     // Test advanced state initialization
     #[test]
     fn test_advanced_state() {
-        let state = QuantumState::new(&[1, 1, 0]);
+        let state = QuantumState::new(&[1, 1, 0]).unwrap();
         let expected_state = arr2(&[
             [Complex::new(0.0, 0.0)],
             [Complex::new(0.0, 0.0)],
@@ -124,10 +376,58 @@ mod tests {
         assert_eq!(state.col, expected_state);
     }
 
+    #[test]
+    fn test_sparse_state_round_trip() {
+        let state = QuantumState::new(&[1, 0, 1]).unwrap();
+        let sparse = SparseQuantumState::from(&state);
+
+        assert_eq!(sparse.amplitudes.len(), 1);
+        assert_eq!(sparse.amplitudes[&5], Complex::new(1.0, 0.0));
+        assert_eq!(sparse.to_dense(), state);
+    }
+
+    #[test]
+    fn test_sparse_gate_matches_dense_gate() {
+        let state = QuantumState::new(&[1, 0, 1]).unwrap();
+        let gate = QuantumGate::h_gate();
+
+        let dense = state.apply_gate_on_positions(&gate, &[1]).unwrap();
+        let sparse = SparseQuantumState::from(&state).apply_gate_on_positions(&gate, &[1]).unwrap();
+
+        assert_eq!(sparse.amplitudes.len(), 2);
+        assert_eq!(sparse.to_dense(), dense);
+    }
+
+    #[test]
+    fn test_probabilities_and_amplitudes_above() {
+        let amplitude = Complex::new(0.6, 0.0);
+        let state = QuantumState { col: arr2(&[[amplitude], [Complex::new(0.0, 0.0)], [Complex::new(0.0, 1e-13)], [Complex::new(0.0, -0.8)]]) };
+
+        let probabilities = state.probabilities();
+        assert!((probabilities[0] - 0.36).abs() < 1e-12 && (probabilities[3] - 0.64).abs() < 1e-12);
+        assert_eq!(
+            state.amplitudes_above(SPARSE_EPSILON),
+            BTreeMap::from([("00".to_string(), amplitude), ("11".to_string(), Complex::new(0.0, -0.8))])
+        );
+        assert_eq!(state.amplitudes_above(0.7).len(), 1);
+    }
+
+    #[test]
+    fn test_sampled_counts_follow_the_probabilities() {
+        let amplitude = Complex::new(std::f64::consts::FRAC_1_SQRT_2, 0.0);
+        let state = QuantumState { col: arr2(&[[amplitude], [Complex::new(0.0, 0.0)], [Complex::new(0.0, 0.0)], [amplitude]]) };
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+
+        let counts = state.sample(2000, &mut rng);
+
+        assert_eq!(counts.keys().copied().collect::<Vec<usize>>(), vec![0, 3]);
+        assert!((counts[&0] as i64 - 1000).abs() < 150);
+    }
+
     // Test that the size of a state is correct
     #[test]
     fn test_size() {
-        let state = QuantumState::new(&[0, 0, 0, 0, 0]);
+        let state = QuantumState::new(&[0, 0, 0, 0, 0]).unwrap();
         assert_eq!(state.size(), 5);
     }
 }
@@ -0,0 +1,403 @@
+// Converts between grids and the JSON format of the Quirk simulator, {"cols": [[...], ...]}.
+// Quirk lists the gates column by column, uses 1 for an empty cell and "•" for a control.
+// A control in Quirk controls every other gate in its column, wherever it is, while C_down only
+// controls the gate directly underneath it. Columns are therefore split or routed when converting.
+
+use crate::simulation::angle::{format_angle, parse_angle};
+use crate::simulation::circuit_parser::{operations_in_step, parse_parameterised_token, GridOperation, UnparsedCircuit};
+use crate::simulation::circuit_validator::{validate_grid_input, QuantumCircuitError, MAX_QUBITS};
+use crate::simulation::grid_layout::GridLayout;
+use serde::ser::SerializeStruct;
+use serde::{Serialize, Serializer};
+use serde_json::{json, Value};
+use std::f64::consts::PI;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum QuirkError {
+    // The JSON is not an object with a list of columns
+    InvalidFormat { message: String },
+    // A gate that has no equivalent on the other side
+    UnsupportedGate { gate: String, qubit: usize, column: usize },
+    // The argument of a formula gate is not a constant angle
+    InvalidArgument { argument: String, qubit: usize, column: usize },
+    // Several controls in a column that are not all directly above its only gate
+    TooManyControls { column: usize },
+    // A column with a single swap, more than two of them or a control on a swap
+    InvalidSwap { column: usize },
+    // The grid was rejected by the circuit validator
+    Circuit(QuantumCircuitError),
+}
+
+impl QuirkError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            QuirkError::InvalidFormat { .. } => "invalid_format",
+            QuirkError::UnsupportedGate { .. } => "unsupported_gate",
+            QuirkError::InvalidArgument { .. } => "invalid_argument",
+            QuirkError::TooManyControls { .. } => "too_many_controls",
+            QuirkError::InvalidSwap { .. } => "invalid_swap",
+            QuirkError::Circuit(error) => error.code(),
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            QuirkError::InvalidFormat { message } => format!("The Quirk circuit is not valid: {}", message),
+            QuirkError::UnsupportedGate { gate, qubit, column } => format!(
+                "{} on qubit {} in column {} is not supported",
+                gate, qubit, column
+            ),
+            QuirkError::InvalidArgument { argument, qubit, column } => format!(
+                "The angle '{}' on qubit {} in column {} is not a constant",
+                argument, qubit, column
+            ),
+            QuirkError::TooManyControls { column } => {
+                format!("Column {} has more than one control, but they have to be directly above its only gate", column)
+            }
+            QuirkError::InvalidSwap { column } => {
+                format!("Column {} needs exactly two swaps and no controls on them", column)
+            }
+            QuirkError::Circuit(error) => error.message(),
+        }
+    }
+
+    pub fn qubit(&self) -> Option<usize> {
+        match self {
+            QuirkError::UnsupportedGate { qubit, .. } | QuirkError::InvalidArgument { qubit, .. } => Some(*qubit),
+            QuirkError::Circuit(error) => error.qubit(),
+            _ => None,
+        }
+    }
+
+    pub fn column(&self) -> Option<usize> {
+        match self {
+            QuirkError::UnsupportedGate { column, .. }
+            | QuirkError::InvalidArgument { column, .. }
+            | QuirkError::TooManyControls { column }
+            | QuirkError::InvalidSwap { column } => Some(*column),
+            QuirkError::Circuit(error) => error.step(),
+            QuirkError::InvalidFormat { .. } => None,
+        }
+    }
+}
+
+impl Serialize for QuirkError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if let QuirkError::Circuit(error) = self {
+            return error.serialize(serializer);
+        }
+        let mut error = serializer.serialize_struct("QuirkError", 4)?;
+        error.serialize_field("code", self.code())?;
+        error.serialize_field("message", &self.message())?;
+        error.serialize_field("qubit", &self.qubit())?;
+        error.serialize_field("column", &self.column())?;
+        error.end()
+    }
+}
+
+// What a single cell of a Quirk column stands for
+enum QuirkCell {
+    Empty,
+    Control,
+    Swap,
+    Gate(String),
+}
+
+pub fn import_quirk(quirk: &Value) -> Result<UnparsedCircuit, Vec<QuirkError>> {
+    let invalid_format = |message: &str| vec![QuirkError::InvalidFormat { message: message.to_string() }];
+    let columns = quirk
+        .get("cols")
+        .and_then(Value::as_array)
+        .ok_or_else(|| invalid_format("it has no list of columns under \"cols\""))?;
+    if quirk.get("init").is_some() {
+        return Err(invalid_format("qubits have to start in |0>, so \"init\" is not supported"));
+    }
+
+    let mut cells: Vec<Vec<QuirkCell>> = Vec::new();
+    let mut errors: Vec<QuirkError> = Vec::new();
+    for (column, entries) in columns.iter().enumerate() {
+        let entries = entries.as_array().ok_or_else(|| invalid_format("every column has to be a list"))?;
+        // The longest column sets the number of rows, so it is checked before any of them are made
+        if entries.len() > MAX_QUBITS {
+            return Err(vec![QuirkError::Circuit(QuantumCircuitError::TooManyQubits)]);
+        }
+        let mut column_cells = Vec::new();
+        for (qubit, entry) in entries.iter().enumerate() {
+            match parse_cell(entry, qubit, column) {
+                Ok(cell) => column_cells.push(cell),
+                Err(error) => errors.push(error),
+            }
+        }
+        cells.push(column_cells);
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let mut layout = GridLayout::default();
+    layout.add_qubits(cells.iter().map(|column| column.len()).max().unwrap_or(0));
+    let all_qubits: Vec<usize> = (0..layout.no_of_qubits()).collect();
+
+    for (column, column_cells) in cells.into_iter().enumerate() {
+        // Keep the columns of Quirk in order, even where gates could be moved further left
+        layout.barrier(&all_qubits);
+
+        let controls: Vec<usize> = qubits_where(&column_cells, |cell| matches!(cell, QuirkCell::Control));
+        let swaps: Vec<usize> = qubits_where(&column_cells, |cell| matches!(cell, QuirkCell::Swap));
+        let gates: Vec<usize> = qubits_where(&column_cells, |cell| matches!(cell, QuirkCell::Gate(_)));
+        if controls.len() > 1 {
+            // Stacked controls only reach the gate directly underneath them, so they can not be routed
+            match gates[..] {
+                [target] if swaps.is_empty() && target >= controls.len() && controls == (target - controls.len()..target).collect::<Vec<usize>>() => {
+                    let QuirkCell::Gate(token) = &column_cells[target] else { unreachable!("the target is a gate") };
+                    let mut tokens = vec!["C_down".to_string(); controls.len()];
+                    tokens.push(token.clone());
+                    layout.place(controls[0], tokens);
+                }
+                _ => errors.push(QuirkError::TooManyControls { column }),
+            }
+            continue;
+        }
+        if !(swaps.is_empty() || (swaps.len() == 2 && controls.is_empty())) {
+            errors.push(QuirkError::InvalidSwap { column });
+            continue;
+        }
+
+        if let [first, second] = swaps[..] {
+            layout.swap(first, second);
+        }
+        for (qubit, cell) in column_cells.into_iter().enumerate() {
+            if let QuirkCell::Gate(token) = cell {
+                match controls.first() {
+                    Some(control) => layout.controlled(token, *control, qubit),
+                    None => layout.single(token, qubit),
+                }
+            }
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let grid = layout.into_grid();
+    validate_grid_input(&grid).map_err(|errors| errors.into_iter().map(QuirkError::Circuit).collect::<Vec<QuirkError>>())?;
+    Ok(grid)
+}
+
+fn qubits_where(cells: &[QuirkCell], predicate: impl Fn(&QuirkCell) -> bool) -> Vec<usize> {
+    cells.iter().enumerate().filter(|(_, cell)| predicate(cell)).map(|(qubit, _)| qubit).collect()
+}
+
+fn parse_cell(entry: &Value, qubit: usize, column: usize) -> Result<QuirkCell, QuirkError> {
+    let unsupported = || QuirkError::UnsupportedGate { gate: entry.to_string(), qubit, column };
+    if entry.as_i64() == Some(1) {
+        return Ok(QuirkCell::Empty);
+    }
+    if let Some(name) = entry.as_str() {
+        return match name {
+            "•" => Ok(QuirkCell::Control),
+            "Swap" => Ok(QuirkCell::Swap),
+            "H" | "X" | "Y" | "Z" => Ok(QuirkCell::Gate(name.to_string())),
+            "Z^½" => Ok(QuirkCell::Gate("S".to_string())),
+            "Z^¼" => Ok(QuirkCell::Gate("T".to_string())),
+            "Z^-½" => Ok(QuirkCell::Gate("S†".to_string())),
+            "Z^-¼" => Ok(QuirkCell::Gate("T†".to_string())),
+            "X^½" => Ok(QuirkCell::Gate("SX".to_string())),
+            "X^-½" => Ok(QuirkCell::Gate("SX†".to_string())),
+            _ => Err(unsupported()),
+        };
+    }
+
+    // Formula gates, such as {"id": "Rzft", "arg": "pi/4"}
+    let id = entry.get("id").and_then(Value::as_str).ok_or_else(unsupported)?;
+    let argument = entry.get("arg").and_then(Value::as_str).unwrap_or("");
+    let name = match id {
+        "Rxft" => "Rx",
+        "Ryft" => "Ry",
+        "Rzft" => "Rz",
+        "Z^ft" => "P",
+        _ => return Err(unsupported()),
+    };
+    let value = parse_angle(argument).ok_or_else(|| QuirkError::InvalidArgument { argument: argument.to_string(), qubit, column })?;
+    // Z^f is a phase of f half turns
+    let angle = if name == "P" { value * PI } else { value };
+    Ok(QuirkCell::Gate(format!("{}({})", name, format_angle(angle))))
+}
+
+pub fn export_quirk(grid: &UnparsedCircuit) -> Result<Value, Vec<QuirkError>> {
+    validate_grid_input(grid).map_err(|errors| errors.into_iter().map(QuirkError::Circuit).collect::<Vec<QuirkError>>())?;
+
+    let no_of_qubits = grid.circuit.len();
+    let mut columns: Vec<Vec<Value>> = Vec::new();
+    let mut errors: Vec<QuirkError> = Vec::new();
+    for step in 0..grid.circuit[0].len() {
+        // Each controlled gate gets a column of its own, so its control does not reach the other gates of the step
+        let mut uncontrolled = vec![json!(1); no_of_qubits];
+        let mut controlled: Vec<Vec<Value>> = Vec::new();
+        for operation in operations_in_step(grid, step).map_err(|error| vec![QuirkError::Circuit(error)])? {
+            match operation {
+                GridOperation::Single { token, qubit, .. } => match export_cell(&token, qubit, step) {
+                    Ok(cell) => uncontrolled[qubit] = cell,
+                    Err(error) => errors.push(error),
+                },
+                GridOperation::Controlled { token, control, target, .. } => {
+                    let mut column = vec![json!(1); no_of_qubits];
+                    column[control..target].fill(json!("•"));
+                    match export_cell(&token, target, step) {
+                        Ok(cell) => column[target] = cell,
+                        Err(error) => errors.push(error),
+                    }
+                    controlled.push(column);
+                }
+                GridOperation::Swap { upper } => {
+                    uncontrolled[upper] = json!("Swap");
+                    uncontrolled[upper + 1] = json!("Swap");
+                }
+                GridOperation::Custom { token, upper, .. } => {
+                    errors.push(QuirkError::UnsupportedGate { gate: format!("'{}'", token), qubit: upper, column: step });
+                }
+            }
+        }
+        if controlled.is_empty() || uncontrolled.iter().any(|cell| *cell != json!(1)) {
+            columns.push(uncontrolled);
+        }
+        columns.append(&mut controlled);
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    Ok(json!({ "cols": columns }))
+}
+
+fn export_cell(token: &str, qubit: usize, column: usize) -> Result<Value, QuirkError> {
+    let unsupported = || QuirkError::UnsupportedGate { gate: format!("'{}'", token), qubit, column };
+    match token {
+        "I" => Ok(json!(1)),
+        "H" | "X" | "Y" | "Z" => Ok(json!(token)),
+        "S" => Ok(json!("Z^½")),
+        "T" => Ok(json!("Z^¼")),
+        "S†" => Ok(json!("Z^-½")),
+        "T†" => Ok(json!("Z^-¼")),
+        "SX" => Ok(json!("X^½")),
+        "SX†" => Ok(json!("X^-½")),
+        _ => {
+            let (name, angles) = parse_parameterised_token(token).ok_or_else(unsupported)?;
+            match (name, angles.as_slice()) {
+                ("Rx", [theta]) => Ok(json!({ "id": "Rxft", "arg": format_angle(*theta) })),
+                ("Ry", [theta]) => Ok(json!({ "id": "Ryft", "arg": format_angle(*theta) })),
+                ("Rz", [theta]) => Ok(json!({ "id": "Rzft", "arg": format_angle(*theta) })),
+                ("P", [lambda]) => Ok(json!({ "id": "Z^ft", "arg": format!("{}", lambda / PI) })),
+                _ => Err(unsupported()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::simulator::simulate_circuit_handler;
+
+    #[test]
+    fn test_import_bell_state() {
+        let quirk = json!({"cols": [["H"], ["•", "X"]]});
+
+        assert_eq!(
+            import_quirk(&quirk),
+            Ok(UnparsedCircuit::from(vec![vec!["H", "C_down"], vec!["I", "X"]]))
+        );
+    }
+
+    #[test]
+    fn test_import_controls_below_and_formula_gates() {
+        let quirk = json!({"cols": [
+            [{"id": "Rzft", "arg": "pi/4"}, "Z^½", "X"],
+            ["X", 1, "•"],
+            [{"id": "Z^ft", "arg": "0.25"}, "Swap", "Swap"],
+        ]});
+
+        let grid = import_quirk(&quirk).unwrap();
+
+        assert_eq!(grid.circuit[0][0], "Rz(pi/4)");
+        assert_eq!(grid.circuit[1][0], "S");
+        assert_eq!(grid.circuit[2][0], "X");
+        assert_eq!(grid.circuit[0].last().unwrap(), "P(pi/4)");
+        // The control is |1>, so the routed controlled X acts like an X
+        let state = simulate_circuit_handler(grid).unwrap().pop().unwrap();
+        let expected = simulate_circuit_handler(UnparsedCircuit::from(vec![
+            vec!["Rz(pi/4)", "X", "P(pi/4)"],
+            vec!["S", "I", "Swap"],
+            vec!["X", "I", "Swap"],
+        ]))
+        .unwrap()
+        .pop()
+        .unwrap();
+        for (a, b) in state.col.iter().zip(expected.col.iter()) {
+            assert!((a - b).norm() < 1e-10);
+        }
+    }
+
+    #[test]
+    fn test_import_errors() {
+        assert_eq!(
+            import_quirk(&json!({"cols": [["QFT3"], ["•", "•", "X"], ["Swap"]]})),
+            Err(vec![QuirkError::UnsupportedGate { gate: "\"QFT3\"".to_string(), qubit: 0, column: 0 }])
+        );
+        assert_eq!(
+            import_quirk(&json!({"cols": [["•", "•", "X"], ["Swap"], [{"id": "Rxft", "arg": "sin(t)"}]]})),
+            Err(vec![QuirkError::InvalidArgument { argument: "sin(t)".to_string(), qubit: 0, column: 2 }])
+        );
+        assert_eq!(
+            import_quirk(&json!({"cols": [["•", "X", "•"], ["Swap"]]})),
+            Err(vec![QuirkError::TooManyControls { column: 0 }, QuirkError::InvalidSwap { column: 1 }])
+        );
+        assert_eq!(import_quirk(&json!({"cols": [["•", "•", "X", "H"]]})), Err(vec![QuirkError::TooManyControls { column: 0 }]));
+        assert_eq!(import_quirk(&json!({"cols": [["X", "•", "•"]]})), Err(vec![QuirkError::TooManyControls { column: 0 }]));
+        assert!(matches!(import_quirk(&json!({"gates": []})), Err(errors) if errors[0].code() == "invalid_format"));
+        assert_eq!(
+            import_quirk(&json!({"cols": [["H"], vec![1; 50_000]]})),
+            Err(vec![QuirkError::Circuit(QuantumCircuitError::TooManyQubits)])
+        );
+    }
+
+    #[test]
+    fn test_export_splits_controlled_gates_into_columns() {
+        let grid = UnparsedCircuit::from(vec![
+            vec!["H", "C_down"],
+            vec!["T", "X"],
+            vec!["C_down", "Rx(pi/2)"],
+            vec!["Z", "I"],
+        ]);
+
+        assert_eq!(
+            export_quirk(&grid),
+            Ok(json!({"cols": [
+                ["H", "Z^¼", 1, 1],
+                [1, 1, "•", "Z"],
+                [1, 1, {"id": "Rxft", "arg": "pi/2"}, 1],
+                ["•", "X", 1, 1],
+            ]}))
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let grid = UnparsedCircuit::from(vec![
+            vec!["H", "C_down", "Swap", "P(-pi/4)"],
+            vec!["S", "Ry(0.5)", "Swap", "I"],
+            vec!["I", "I", "X", "Rz(pi/8)"],
+        ]);
+
+        assert_eq!(import_quirk(&export_quirk(&grid).unwrap()), Ok(grid));
+
+        let toffoli = UnparsedCircuit::from(vec![vec!["H", "C_down"], vec!["I", "C_down"], vec!["I", "X"]]);
+        let exported = export_quirk(&toffoli).unwrap();
+        assert_eq!(exported, json!({"cols": [["H", 1, 1], ["•", "•", "X"]]}));
+        assert_eq!(import_quirk(&exported), Ok(toffoli));
+        assert_eq!(
+            export_quirk(&UnparsedCircuit::from(vec![vec!["U(pi,0,pi)"]])),
+            Err(vec![QuirkError::UnsupportedGate { gate: "'U(pi,0,pi)'".to_string(), qubit: 0, column: 0 }])
+        );
+    }
+}
@@ -0,0 +1,148 @@
+use crate::simulation::circuit_validator::QuantumCircuitError;
+use crate::simulation::grouped_backend::GroupedBackend;
+use crate::simulation::quantum_gate::{QuantumGate, QuantumGateWrapper};
+use crate::simulation::quantum_state::QuantumState;
+use crate::simulation::sparse_backend::{AdaptiveBackend, SparseBackend};
+use crate::simulation::state_vector_backend::StateVectorBackend;
+use rand::{Rng, RngCore};
+use serde::{Deserialize, Serialize};
+
+// Operations that are not described by a unitary matrix
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NonUnitaryOperation {
+    Measure(usize),
+    Reset(usize),
+}
+
+// A SimulationBackend owns the state of a whole register and knows how to evolve it.
+// The simulator only talks to this trait, so different state representations can be
+// swapped in per request and compared against each other.
+pub trait SimulationBackend {
+    // Reset the register to |0...0> with the given number of qubits
+    fn initialize(&mut self, no_of_qubits: usize) -> Result<(), QuantumCircuitError>;
+
+    // Apply a gate to the qubits listed in the wrapper, in the order the gate matrix expects
+    fn apply_gate(&mut self, gate: &QuantumGateWrapper) -> Result<(), QuantumCircuitError>;
+
+    // Measure a single qubit in the computational basis, collapsing the state, and return the outcome
+    fn measure(&mut self, qubit: usize, rng: &mut dyn RngCore) -> Result<u8, QuantumCircuitError>;
+
+    // The full register as one state vector, qubit 0 being the most significant bit
+    fn snapshot(&self) -> QuantumState;
+
+    // Apply a non-unitary operation and return the measurement outcome it produced
+    fn apply_non_unitary(&mut self, operation: &NonUnitaryOperation, rng: &mut dyn RngCore) -> Result<u8, QuantumCircuitError> {
+        match *operation {
+            NonUnitaryOperation::Measure(qubit) => self.measure(qubit, rng),
+            NonUnitaryOperation::Reset(qubit) => {
+                let outcome = self.measure(qubit, rng)?;
+                if outcome == 1 {
+                    self.apply_gate(&QuantumGateWrapper { gate: QuantumGate::x_gate(), qubits: vec![qubit] })?;
+                }
+                Ok(outcome)
+            }
+        }
+    }
+}
+
+// The backends a request can choose between
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackendKind {
+    #[default]
+    Grouped,
+    StateVector,
+    Sparse,
+    // Sparse while only a few amplitudes are non-zero, dense otherwise
+    Auto,
+}
+
+impl BackendKind {
+    pub fn all() -> Vec<BackendKind> {
+        vec![BackendKind::Grouped, BackendKind::StateVector, BackendKind::Sparse, BackendKind::Auto]
+    }
+
+    pub fn create(self) -> Box<dyn SimulationBackend> {
+        match self {
+            BackendKind::Grouped => Box::new(GroupedBackend::default()),
+            BackendKind::StateVector => Box::new(StateVectorBackend::default()),
+            BackendKind::Sparse => Box::new(SparseBackend::default()),
+            BackendKind::Auto => Box::new(AdaptiveBackend::default()),
+        }
+    }
+}
+
+// The largest register a backend will allocate, well above what the validator lets through
+pub const MAX_BACKEND_QUBITS: usize = 24;
+
+// Draw a measurement outcome given the probability of measuring 1
+pub(crate) fn sample_outcome(probability_of_one: f64, rng: &mut dyn RngCore) -> u8 {
+    if rng.gen::<f64>() < probability_of_one {
+        1
+    } else {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_reset_returns_qubit_to_zero() {
+        for backend_kind in BackendKind::all() {
+            let mut backend = backend_kind.create();
+            let mut rng = StdRng::seed_from_u64(1);
+            backend.initialize(2).unwrap();
+            backend.apply_gate(&QuantumGateWrapper { gate: QuantumGate::x_gate(), qubits: vec![1] }).unwrap();
+
+            let outcome = backend.apply_non_unitary(&NonUnitaryOperation::Reset(1), &mut rng).unwrap();
+
+            assert_eq!(outcome, 1);
+            assert_eq!(backend.snapshot(), QuantumState::new(&[0, 0]).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_measure_collapses_entangled_qubits() {
+        for backend_kind in BackendKind::all() {
+            for seed in 0..8 {
+                let mut backend = backend_kind.create();
+                let mut rng = StdRng::seed_from_u64(seed);
+                backend.initialize(2).unwrap();
+                backend.apply_gate(&QuantumGateWrapper { gate: QuantumGate::h_gate(), qubits: vec![0] }).unwrap();
+                backend.apply_gate(&QuantumGateWrapper { gate: QuantumGate::cnot_gate(), qubits: vec![0, 1] }).unwrap();
+
+                let first = backend.measure(0, &mut rng).unwrap();
+                let second = backend.measure(1, &mut rng).unwrap();
+
+                assert_eq!(first, second);
+                let expected = QuantumState::new(&[first as usize, second as usize]).unwrap();
+                for (a, b) in backend.snapshot().col.iter().zip(expected.col.iter()) {
+                    assert!((a - b).norm() < 1e-10);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_out_of_range_qubits_are_errors() {
+        for backend_kind in BackendKind::all() {
+            let mut backend = backend_kind.create();
+            let mut rng = StdRng::seed_from_u64(0);
+            backend.initialize(2).unwrap();
+
+            assert_eq!(
+                backend.apply_gate(&QuantumGateWrapper { gate: QuantumGate::x_gate(), qubits: vec![2] }),
+                Err(QuantumCircuitError::QubitOutOfRange { qubit: 2, no_of_qubits: 2 })
+            );
+            assert_eq!(
+                backend.measure(5, &mut rng),
+                Err(QuantumCircuitError::QubitOutOfRange { qubit: 5, no_of_qubits: 2 })
+            );
+            assert!(backend.initialize(MAX_BACKEND_QUBITS + 1).is_err());
+        }
+    }
+}
@@ -1,100 +1,85 @@
-
-use crate::simulation::circuit_parser::build_circuit_from_data;
+use crate::simulation::circuit_parser::parse_gates_individually;
+use crate::simulation::gate_fusion::fuse_single_qubit_gates;
 use crate::simulation::circuit_validator::{validate_grid_input, QuantumCircuitError};
-use crate::simulation::quantum_gate::{QuantumGate, QuantumGateWrapper};
-use crate::simulation::quantum_state::{QuantumState, QuantumStateWrapper, QuantumStep};
+use crate::simulation::quantum_state::QuantumState;
 use crate::simulation::circuit_parser::{UnparsedCircuit, ParsedCircuit};
-use crate::simulation::utils::to_little_endian;
-use ndarray::{arr2};
-use ndarray::Array2;
-use num::Complex;
-
-pub fn simulate_circuit_handler(incoming_data: UnparsedCircuit) -> Result<Vec<QuantumState>, QuantumCircuitError> {
-    let validation_result = validate_grid_input(&incoming_data);
-    if validation_result.is_err() {
-        return Err(validation_result.unwrap_err());
-    }
+use crate::simulation::simulation_backend::{BackendKind, SimulationBackend};
+use crate::simulation::subcircuits::expand_subcircuits;
+use crate::simulation::utils::BitOrder;
+
+pub fn simulate_circuit_handler(incoming_data: UnparsedCircuit) -> Result<Vec<QuantumState>, Vec<QuantumCircuitError>> {
+    simulate_circuit_handler_with_backend(incoming_data, BackendKind::default(), BitOrder::default())
+}
+
+pub fn simulate_circuit_handler_with_backend(incoming_data: UnparsedCircuit, backend_kind: BackendKind, bit_order: BitOrder) -> Result<Vec<QuantumState>, Vec<QuantumCircuitError>> {
+    validate_grid_input(&incoming_data)?;
 
-    let parsed_circuit: ParsedCircuit = build_circuit_from_data(incoming_data);
+    // Every step is reported, so nothing can be fused and the gates are only applied one by one
+    let parsed_circuit: ParsedCircuit = parse_gates_individually(incoming_data).map_err(|err| vec![err])?;
 
-    let simulated_states: Vec<QuantumStep> = simulate_circuit(parsed_circuit);
+    let mut backend = backend_kind.create();
+    let simulated_states: Vec<QuantumState> = simulate_circuit(parsed_circuit, backend.as_mut()).map_err(|err| vec![err])?;
 
-    let combined_states: Vec<QuantumState> = combine_states_for_frontend(simulated_states);
+    let combined_states: Vec<QuantumState> = combine_states_for_frontend(simulated_states, bit_order);
 
     Ok(combined_states)
 }
 
-fn initialize_states(circuit: ParsedCircuit) -> Vec<QuantumStep> {
-    let mut first_step: QuantumStep = QuantumStep {
-        states: vec![],
-    };
-    for i in 0..circuit.circuit[0].gates.len() {
-        first_step.states.push(QuantumStateWrapper {
-            state: QuantumState::new(&[0]),
-            qubits: vec![i],
-        });
+// The state after the given step of the grid alone, for callers that need no other state,
+// so the single-qubit gates of each wire are fused up to the gates that need the wire
+pub fn simulate_state_after_step_with_backend(incoming_data: UnparsedCircuit, backend_kind: BackendKind, bit_order: BitOrder, step: usize) -> Result<QuantumState, Vec<QuantumCircuitError>> {
+    validate_grid_input(&incoming_data)?;
+    let no_of_steps = incoming_data.circuit[0].len();
+    if step >= no_of_steps {
+        return Err(vec![QuantumCircuitError::StepOutOfRange { step, no_of_steps }]);
     }
-    vec![first_step]
-}
 
+    let mut parsed_circuit: ParsedCircuit = parse_gates_individually(incoming_data).map_err(|err| vec![err])?;
+    parsed_circuit.circuit.truncate(step + 1);
+    let parsed_circuit = fuse_single_qubit_gates(parsed_circuit, |fused_step| fused_step == step);
 
+    let mut backend = backend_kind.create();
+    let state = simulate_final_state(parsed_circuit, backend.as_mut()).map_err(|err| vec![err])?;
+    Ok(bit_order.reorder(&state))
+}
 
-fn simulate_circuit(circuit: ParsedCircuit) -> Vec<QuantumStep> {
-    
-    let mut state_list: Vec<QuantumStep> = initialize_states(circuit.clone()); 
+// The states after every step inside of the sub-circuits as well, with the step of the grid each of them belongs to
+pub fn simulate_expanded_with_backend(incoming_data: UnparsedCircuit, backend_kind: BackendKind, bit_order: BitOrder) -> Result<(Vec<QuantumState>, Vec<usize>), Vec<QuantumCircuitError>> {
+    validate_grid_input(&incoming_data)?;
 
-    for gates_in_step in circuit.circuit.into_iter() {
-        let mut states_in_step: Vec<QuantumStateWrapper> = vec![];
+    let (parsed_circuit, grid_steps) = expand_subcircuits(&incoming_data).map_err(|err| vec![err])?;
 
-        for gate in gates_in_step.gates {
-            let states_in_prev_step = QuantumStep {states: state_list.last().unwrap().states.clone()};
-            let state_after_gate = calculate_state_after_gate(states_in_prev_step, gate);
-            
-            states_in_step.push(state_after_gate);
-        }
+    let mut backend = backend_kind.create();
+    let simulated_states: Vec<QuantumState> = simulate_circuit(parsed_circuit, backend.as_mut()).map_err(|err| vec![err])?;
 
-        state_list.push(QuantumStep {
-            states: states_in_step,
-        });
-    }
-    state_list
+    Ok((combine_states_for_frontend(simulated_states, bit_order), grid_steps))
 }
 
-fn calculate_state_after_gate(states_in_prev_step: QuantumStep, gate_wrapper: QuantumGateWrapper) -> QuantumStateWrapper {
-    let qubits_in_gate = gate_wrapper.qubits.clone();
-
-    let states_in_prev_step_gate_acts_on: Vec<QuantumStateWrapper> = states_in_prev_step.states
-        .into_iter()
-        .filter(|state| state.qubits.iter().any(|qubit| qubits_in_gate.contains(qubit)))
-        .collect();
+// Run the circuit on the given backend and take a snapshot of the register before the first step and after every step
+pub fn simulate_circuit(circuit: ParsedCircuit, backend: &mut dyn SimulationBackend) -> Result<Vec<QuantumState>, QuantumCircuitError> {
+    backend.initialize(circuit.no_of_qubits())?;
+    let mut state_list: Vec<QuantumState> = vec![backend.snapshot()];
 
-    let mut state_into_gate = combine_states(states_in_prev_step_gate_acts_on.clone());
-    let state_after_gate = state_into_gate.apply_gate(gate_wrapper.gate.clone());
-
-    let state_after_gate_wrapped = QuantumStateWrapper {
-        state: state_after_gate,
-        qubits: qubits_in_gate,
-    };
-    state_after_gate_wrapped
+    for gates_in_step in circuit.circuit.iter() {
+        for gate in gates_in_step.gates.iter() {
+            backend.apply_gate(gate)?;
+        }
+        state_list.push(backend.snapshot());
+    }
+    Ok(state_list)
 }
 
-fn combine_states(states: Vec<QuantumStateWrapper>) -> QuantumState {
-    let mut current_state: QuantumState = states[0].state.clone();
-    for entagled_group in states.iter().skip(1) {
-        current_state = current_state.kronecker(entagled_group.state.clone());
+// Run the circuit on the given backend and take a snapshot of the register at the end only
+pub fn simulate_final_state(circuit: ParsedCircuit, backend: &mut dyn SimulationBackend) -> Result<QuantumState, QuantumCircuitError> {
+    backend.initialize(circuit.no_of_qubits())?;
+    for gate in circuit.circuit.iter().flat_map(|gates_in_step| gates_in_step.gates.iter()) {
+        backend.apply_gate(gate)?;
     }
-    println!("Combining states:\n {:?}\ninto\n{:?}\n\n", states.clone(), current_state.clone());
-    current_state
+    Ok(backend.snapshot())
 }
 
-fn combine_states_for_frontend(simulated_states: Vec<QuantumStep>) -> Vec<QuantumState> {
-    let mut combined_states: Vec<QuantumState> = vec![];
-    for step in simulated_states.iter(){
-        let combined_states_in_step = combine_states(step.states.clone());
-        combined_states.push(to_little_endian(&combined_states_in_step));
-        
-    }
-    combined_states
+fn combine_states_for_frontend(simulated_states: Vec<QuantumState>, bit_order: BitOrder) -> Vec<QuantumState> {
+    simulated_states.iter().map(|state| bit_order.reorder(state)).collect()
 }
 
 
@@ -102,6 +87,7 @@ fn combine_states_for_frontend(simulated_states: Vec<QuantumStep>) -> Vec<Quantu
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::simulation::test_support::{assert_states_close, states_close};
     use ndarray::arr2;
     use num::Complex;
 
@@ -111,8 +97,8 @@ mod tests {
         let result = simulate_circuit_handler(UnparsedCircuit::from(incoming_data));
 
         let expected_result = vec![
-            QuantumState::new(&[0]),
-            QuantumState::new(&[1]),
+            QuantumState::new(&[0]).unwrap(),
+            QuantumState::new(&[1]).unwrap(),
         ];
 
         assert_eq!(result.unwrap(), expected_result);
@@ -124,7 +110,7 @@ mod tests {
         let result = simulate_circuit_handler(UnparsedCircuit::from(incoming_data));
 
         let expected_result = vec![
-            QuantumState::new(&[0]),
+            QuantumState::new(&[0]).unwrap(),
             QuantumState {
                 col: arr2(&[
                     [Complex::new(1.0 / 2.0_f64.sqrt(), 0.0)],
@@ -142,8 +128,8 @@ mod tests {
         let result = simulate_circuit_handler(UnparsedCircuit::from(incoming_data));
 
         let expected_result = vec![
-            QuantumState::new(&[0,0]),
-            QuantumState::new(&[1,0]),
+            QuantumState::new(&[0,0]).unwrap(),
+            QuantumState::new(&[1,0]).unwrap(),
         ];
 
         assert_eq!(result.unwrap(), expected_result);
@@ -155,8 +141,8 @@ mod tests {
         let result = simulate_circuit_handler(UnparsedCircuit::from(incoming_data));
 
         let expected_result = vec![
-            QuantumState::new(&[0,0]),
-            QuantumState::new(&[0,1]),
+            QuantumState::new(&[0,0]).unwrap(),
+            QuantumState::new(&[0,1]).unwrap(),
         ];
 
         assert_eq!(result.unwrap(), expected_result);
@@ -168,9 +154,9 @@ mod tests {
         let result = simulate_circuit_handler(UnparsedCircuit::from(incoming_data));
 
         let expected_result = vec![
-            QuantumState::new(&[0,0]),
-            QuantumState::new(&[0, 1]),
-            QuantumState::new(&[1, 1]),
+            QuantumState::new(&[0,0]).unwrap(),
+            QuantumState::new(&[0, 1]).unwrap(),
+            QuantumState::new(&[1, 1]).unwrap(),
         ];
 
         assert_eq!(result.unwrap(), expected_result);
@@ -182,9 +168,9 @@ mod tests {
         let result = simulate_circuit_handler(UnparsedCircuit::from(incoming_data));
 
         let expected_result = vec![
-            QuantumState::new(&[0,0]),
-            QuantumState::new(&[0,1]),
-            QuantumState::new(&[1,0]),
+            QuantumState::new(&[0,0]).unwrap(),
+            QuantumState::new(&[0,1]).unwrap(),
+            QuantumState::new(&[1,0]).unwrap(),
         ];
 
         assert_eq!(result.unwrap(), expected_result);
@@ -196,8 +182,8 @@ mod tests {
         let result = simulate_circuit_handler(incoming_data);
 
         let expected_result = vec![
-            QuantumState::new(&[0,0]),
-            QuantumState::new(&[0]).kronecker(QuantumState {
+            QuantumState::new(&[0,0]).unwrap(),
+            QuantumState::new(&[0]).unwrap().kronecker(QuantumState {
                 col: arr2(&[
                     [Complex::new(1.0 / 2.0_f64.sqrt(), 0.0)],
                     [Complex::new(1.0 / 2.0_f64.sqrt(), 0.0)],
@@ -229,14 +215,14 @@ mod tests {
         let result = simulate_circuit_handler(incoming_data);
 
         let expected_result = vec![
-            QuantumState::new(&[0,0,0]),
-            QuantumState::new(&[0,0]).kronecker(QuantumState {
+            QuantumState::new(&[0,0,0]).unwrap(),
+            QuantumState::new(&[0,0]).unwrap().kronecker(QuantumState {
                 col: arr2(&[
                     [Complex::new(1.0 / 2.0_f64.sqrt(), 0.0)],
                     [Complex::new(1.0 / 2.0_f64.sqrt(), 0.0)],
                 ]),
             }),
-            QuantumState::new(&[0]).kronecker(QuantumState {
+            QuantumState::new(&[0]).unwrap().kronecker(QuantumState {
                 col: arr2(&[
                     [Complex::new(1.0 / 2.0_f64.sqrt(), 0.0)],
                     [Complex::new(0.0, 0.0)],
@@ -260,4 +246,145 @@ mod tests {
 
         assert_eq!(result.unwrap(), expected_result);
     }
+
+    #[test]
+    fn test_backends_agree() {
+        let circuits = vec![
+            vec![vec!["H", "C_down", "I"], vec!["I", "X", "C_down"], vec!["I", "I", "X"]],
+            vec![vec!["X", "Swap", "H"], vec!["H", "Swap", "T"], vec!["S", "Y", "Z"]],
+            vec![vec!["H", "I", "C_down"], vec!["T", "C_down", "H"], vec!["I", "X", "S"], vec!["X", "H", "C_down"], vec!["H", "I", "Y"]],
+        ];
+
+        for circuit in circuits {
+            let grid = UnparsedCircuit::from(circuit);
+            let reference = simulate_circuit_handler_with_backend(grid.clone(), BackendKind::Grouped, BitOrder::default()).unwrap();
+            for backend_kind in BackendKind::all() {
+                let result = simulate_circuit_handler_with_backend(grid.clone(), backend_kind, BitOrder::default()).unwrap();
+                assert!(
+                    states_close(&result, &reference),
+                    "{:?} disagrees with the grouped backend on\n{}{:?}\n{:?}",
+                    backend_kind, grid, result, reference
+                );
+            }
+        }
+    }
+
+    // Throw random grids, including unknown tokens, ragged rows and dangling multi-qubit parts,
+    // at the parser and the simulator and make sure every one of them returns instead of panicking
+    #[test]
+    fn test_fuzz_grid_input_never_panics() {
+        use crate::simulation::circuit_parser::build_circuit_from_data;
+        use crate::simulation::quantum_gate::QuantumGate;
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+
+        let tokens = ["I", "H", "X", "Y", "Z", "T", "S", "Swap", "C_down", "Two", "Three", "", "A", "c_down", "SWAP", "CNOT-1"];
+        let custom_gates = std::collections::BTreeMap::from([
+            ("Two".to_string(), QuantumGate::swap_gate()),
+            ("Three".to_string(), QuantumGate::ccnot_gate()),
+        ]);
+        let circuit = |grid: &Vec<Vec<String>>| UnparsedCircuit { circuit: grid.clone(), custom_gates: custom_gates.clone(), ..UnparsedCircuit::default() };
+        let mut rng = StdRng::seed_from_u64(81);
+
+        for _ in 0..2000 {
+            let no_of_rows = rng.gen_range(0..8);
+            let no_of_steps = rng.gen_range(0..5);
+            let grid: Vec<Vec<String>> = (0..no_of_rows)
+                .map(|_| {
+                    let row_length = if rng.gen_bool(0.05) { rng.gen_range(0..5) } else { no_of_steps };
+                    (0..row_length)
+                        .map(|_| {
+                            let token_index = if rng.gen_bool(0.9) { rng.gen_range(0..11) } else { rng.gen_range(0..tokens.len()) };
+                            tokens[token_index].to_string()
+                        })
+                        .collect()
+                })
+                .collect();
+
+            let parsed = build_circuit_from_data(circuit(&grid));
+            // Anything the validator lets through must also be accepted by the parser
+            if validate_grid_input(&circuit(&grid)).is_ok() {
+                assert!(parsed.is_ok(), "This grid was validated but failed to parse: {:?}\n{}", parsed, circuit(&grid));
+            }
+            for backend_kind in BackendKind::all() {
+                let _ = simulate_circuit_handler_with_backend(circuit(&grid), backend_kind, BitOrder::default());
+            }
+        }
+    }
+
+    #[test]
+    fn test_control_on_last_row_is_an_error() {
+        let incoming_data = vec![vec!["I"], vec!["C_down"]];
+        let result = simulate_circuit_handler(UnparsedCircuit::from(incoming_data));
+
+        assert_eq!(result, Err(vec![QuantumCircuitError::ControlOnLastQubit { token: "C_down".to_string(), qubit: 1, step: 0 }]));
+    }
+
+    #[test]
+    fn test_bit_order() {
+        let grid = UnparsedCircuit::from(vec![vec!["X"], vec!["I"]]);
+
+        let little_endian = simulate_circuit_handler_with_backend(grid.clone(), BackendKind::default(), BitOrder::LittleEndian).unwrap();
+        let big_endian = simulate_circuit_handler_with_backend(grid, BackendKind::default(), BitOrder::BigEndian).unwrap();
+
+        // |q1 q0⟩ = |01⟩ and |q0 q1⟩ = |10⟩
+        assert_states_close(&little_endian[1..], &[QuantumState::new(&[0, 1]).unwrap()]);
+        assert_states_close(&big_endian[1..], &[QuantumState::new(&[1, 0]).unwrap()]);
+    }
+
+    #[test]
+    fn test_custom_gates_on_every_backend() {
+        use crate::simulation::quantum_gate::QuantumGate;
+
+        // A gate that prepares a Bell state from |00⟩, on qubits 1 and 2 while qubit 0 is flipped
+        let bell = QuantumGate::c_down(QuantumGate::x_gate()).matrix.dot(&QuantumGate::h_gate().kronecker(QuantumGate::i_gate()).matrix);
+        let grid = UnparsedCircuit {
+            circuit: vec![vec!["X".to_string()], vec!["Bell".to_string()], vec!["Bell".to_string()]],
+            custom_gates: std::collections::BTreeMap::from([("Bell".to_string(), QuantumGate { matrix: bell, size: 2 })]),
+            ..UnparsedCircuit::default()
+        };
+        let expected = simulate_circuit_handler(UnparsedCircuit::from(vec![vec!["X", "I"], vec!["H", "C_down"], vec!["I", "X"]])).unwrap();
+
+        for backend_kind in BackendKind::all() {
+            let result = simulate_circuit_handler_with_backend(grid.clone(), backend_kind, BitOrder::default()).unwrap();
+            assert_states_close(&result[1..], &expected[2..]);
+        }
+    }
+
+    // Applying the gates one by one, and fusing them up to the one step that is asked for,
+    // must give the same states as the large matrices of the entangled groups
+    #[test]
+    fn test_gate_fusion_keeps_the_states() {
+        use crate::simulation::circuit_parser::build_circuit_from_data;
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+
+        let tokens = ["I", "H", "X", "Y", "Z", "T", "S", "S†", "Rz(0.3)", "Ry(pi/3)", "Swap", "C_down"];
+        let mut rng = StdRng::seed_from_u64(49);
+
+        let mut checked = 0;
+        while checked < 200 {
+            let no_of_rows = rng.gen_range(1..6);
+            let no_of_steps = rng.gen_range(1..7);
+            let grid = UnparsedCircuit::from(
+                (0..no_of_rows)
+                    .map(|_| (0..no_of_steps).map(|_| tokens[rng.gen_range(0..tokens.len())].to_string()).collect())
+                    .collect::<Vec<Vec<String>>>(),
+            );
+            if validate_grid_input(&grid).is_err() {
+                continue;
+            }
+            checked += 1;
+
+            let reference = simulate_circuit(build_circuit_from_data(grid.clone()).unwrap(), BackendKind::Grouped.create().as_mut()).unwrap();
+            for backend_kind in BackendKind::all() {
+                let states = simulate_circuit_handler_with_backend(grid.clone(), backend_kind, BitOrder::BigEndian).unwrap();
+                assert!(states_close(&states, &reference), "{:?} changed the states of\n{}", backend_kind, grid);
+
+                let step = rng.gen_range(0..no_of_steps);
+                let state = simulate_state_after_step_with_backend(grid.clone(), backend_kind, BitOrder::BigEndian, step).unwrap();
+                assert!(states_close(&[state], &reference[step + 1..step + 2]), "{:?} changed step {} of\n{}", backend_kind, step, grid);
+            }
+        }
+    }
 }
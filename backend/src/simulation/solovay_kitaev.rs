@@ -0,0 +1,302 @@
+// Approximation of single-qubit gates by words in H, T and T†, with the Solovay–Kitaev algorithm as Dawson and Nielsen
+// describe it. A table of short words is searched for the closest one, and every level of the recursion corrects the
+// error of the level below with the group commutator of two rotations, which are approximated one level down themselves.
+// Gates are compared up to their global phase, which a gate that is not controlled does not have.
+
+use crate::simulation::quantum_gate::QuantumGate;
+use num::Complex;
+use std::collections::HashSet;
+use std::f64::consts::PI;
+use std::sync::OnceLock;
+
+// The table holds the shortest words for this many different gates
+const TABLE_SIZE: usize = 20_000;
+// Every level makes the words about five times longer, three levels are close to 1e-3 with a couple of thousand gates
+const MAX_DEPTH: usize = 3;
+// Gates closer than this are the same gate, the square root in the distance makes rounding errors of 1e-16 about 1e-8
+const EXACT: f64 = 1e-7;
+
+type Matrix = [[Complex<f64>; 2]; 2];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Letter {
+    H,
+    T,
+    Tdg,
+}
+
+impl Letter {
+    pub fn token(self) -> &'static str {
+        match self {
+            Letter::H => "H",
+            Letter::T => "T",
+            Letter::Tdg => "T†",
+        }
+    }
+
+    fn inverse(self) -> Letter {
+        match self {
+            Letter::H => Letter::H,
+            Letter::T => Letter::Tdg,
+            Letter::Tdg => Letter::T,
+        }
+    }
+
+    fn matrix(self) -> Matrix {
+        let gate = match self {
+            Letter::H => QuantumGate::h_gate(),
+            Letter::T => QuantumGate::t_gate(),
+            Letter::Tdg => QuantumGate::tdg_gate(),
+        };
+        to_matrix(&gate)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Approximation {
+    // The gates to apply, in the order they are applied
+    pub word: Vec<Letter>,
+    // How far the word is from the gate, 0 when it is the gate itself
+    pub distance: f64,
+}
+
+// The shortest approximation within the tolerance that the recursion finds, or the closest one if none is within it
+pub fn approximate(gate: &QuantumGate, tolerance: f64) -> Approximation {
+    let target = to_matrix(gate);
+    let mut closest: Option<Approximation> = None;
+    for depth in 0..=MAX_DEPTH {
+        let (word, matrix) = solovay_kitaev(&target, depth);
+        let distance = match distance(&target, &matrix) {
+            distance if distance < EXACT => 0.0,
+            distance => distance,
+        };
+        let approximation = Approximation { word: simplify(&word), distance };
+        if distance <= tolerance {
+            return approximation;
+        }
+        if closest.as_ref().is_none_or(|closest| distance < closest.distance) {
+            closest = Some(approximation);
+        }
+    }
+    closest.expect("there is at least one level")
+}
+
+// How far apart two gates are regardless of their global phase, from 0 for the same gate up to 1
+pub fn distance(left: &Matrix, right: &Matrix) -> f64 {
+    (1.0 - overlap(left, right)).max(0.0).sqrt()
+}
+
+pub fn to_matrix(gate: &QuantumGate) -> Matrix {
+    [[gate.matrix[[0, 0]], gate.matrix[[0, 1]]], [gate.matrix[[1, 0]], gate.matrix[[1, 1]]]]
+}
+
+// |tr(U†V)| / 2, which is 1 exactly when the gates only differ by a global phase
+fn overlap(left: &Matrix, right: &Matrix) -> f64 {
+    let trace: Complex<f64> = (0..2).flat_map(|row| (0..2).map(move |column| (row, column))).map(|(row, column)| left[row][column].conj() * right[row][column]).sum();
+    trace.norm() / 2.0
+}
+
+fn solovay_kitaev(gate: &Matrix, depth: usize) -> (Vec<Letter>, Matrix) {
+    if depth == 0 {
+        let (word, matrix) = table()
+            .iter()
+            .max_by(|(_, left), (_, right)| overlap(gate, left).total_cmp(&overlap(gate, right)))
+            .expect("the table holds at least the identity");
+        return (word.clone(), *matrix);
+    }
+
+    let (word, approximation) = solovay_kitaev(gate, depth - 1);
+    let (v, w) = balanced_commutator(&multiply(gate, &adjoint(&approximation)));
+    let (v_word, v_approximation) = solovay_kitaev(&v, depth - 1);
+    let (w_word, w_approximation) = solovay_kitaev(&w, depth - 1);
+
+    // The gate is about V·W·V†·W† times the approximation, which is applied first
+    let word = [word, inverse(&w_word), inverse(&v_word), w_word, v_word].concat();
+    let commutator = multiply(&multiply(&v_approximation, &w_approximation), &multiply(&adjoint(&v_approximation), &adjoint(&w_approximation)));
+    (word, multiply(&commutator, &approximation))
+}
+
+// Every word in H, T and T† up to the length where the table is full, the shortest one for each gate
+fn table() -> &'static [(Vec<Letter>, Matrix)] {
+    static TABLE: OnceLock<Vec<(Vec<Letter>, Matrix)>> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let identity = to_matrix(&QuantumGate::i_gate());
+        let mut seen = HashSet::from([key(&identity)]);
+        let mut table = vec![(vec![], identity)];
+        let mut index = 0;
+        while index < table.len() && table.len() < TABLE_SIZE {
+            let (word, matrix) = table[index].clone();
+            for letter in [Letter::H, Letter::T, Letter::Tdg] {
+                let next = multiply(&letter.matrix(), &matrix);
+                if seen.insert(key(&next)) {
+                    table.push(([word.clone(), vec![letter]].concat(), next));
+                }
+            }
+            index += 1;
+        }
+        table
+    })
+}
+
+// The same key for gates that only differ by a global phase
+fn key(matrix: &Matrix) -> Vec<i64> {
+    let determinant = matrix[0][0] * matrix[1][1] - matrix[0][1] * matrix[1][0];
+    let special = scale(matrix, determinant.sqrt().inv());
+    // Of the two matrices with determinant 1, the one with the smaller key is taken
+    [special, scale(&special, Complex::new(-1.0, 0.0))]
+        .iter()
+        .map(|matrix| matrix.iter().flatten().flat_map(|entry| [entry.re, entry.im]).map(|part| (part * 1e6).round() as i64).collect::<Vec<i64>>())
+        .min()
+        .expect("there are two candidates")
+}
+
+// Two rotations by the same angle whose group commutator V·W·V†·W† is the given gate, which has to be close to the identity
+fn balanced_commutator(gate: &Matrix) -> (Matrix, Matrix) {
+    let (angle, axis) = axis_angle(gate);
+    let half_sine = (angle / 2.0).sin();
+    // sin(θ/2) = 2·sin²(φ/2)·√(1 - sin⁴(φ/2)) for rotations by φ about the x and y axes
+    let quarter_sine_squared = ((1.0 - (1.0 - half_sine * half_sine).max(0.0).sqrt()) / 2.0).sqrt();
+    let phi = 2.0 * quarter_sine_squared.sqrt().asin();
+    let (v, w) = (rotation(phi, [1.0, 0.0, 0.0]), rotation(phi, [0.0, 1.0, 0.0]));
+
+    // Their commutator turns by the right angle, but about another axis, so both are turned to the axis of the gate
+    let (_, commutator_axis) = axis_angle(&multiply(&multiply(&v, &w), &multiply(&adjoint(&v), &adjoint(&w))));
+    let turn = rotation_between(commutator_axis, axis);
+    let conjugate = |matrix: &Matrix| multiply(&multiply(&turn, matrix), &adjoint(&turn));
+    (conjugate(&v), conjugate(&w))
+}
+
+// The angle and the axis of the rotation of the Bloch sphere that the gate makes
+fn axis_angle(gate: &Matrix) -> (f64, [f64; 3]) {
+    let determinant = gate[0][0] * gate[1][1] - gate[0][1] * gate[1][0];
+    let mut special = scale(gate, determinant.sqrt().inv());
+    if (special[0][0] + special[1][1]).re < 0.0 {
+        special = scale(&special, Complex::new(-1.0, 0.0));
+    }
+    let half_cosine = ((special[0][0] + special[1][1]).re / 2.0).clamp(-1.0, 1.0);
+    let axis = [
+        -(special[0][1].im + special[1][0].im) / 2.0,
+        (special[1][0].re - special[0][1].re) / 2.0,
+        (special[1][1].im - special[0][0].im) / 2.0,
+    ];
+    let length = axis.iter().map(|component| component * component).sum::<f64>().sqrt();
+    if length < EXACT {
+        return (0.0, [0.0, 0.0, 1.0]);
+    }
+    (2.0 * half_cosine.acos(), axis.map(|component| component / length))
+}
+
+// cos(θ/2)·I - i·sin(θ/2)·(n·σ)
+fn rotation(angle: f64, axis: [f64; 3]) -> Matrix {
+    let (cosine, sine) = ((angle / 2.0).cos(), (angle / 2.0).sin());
+    let [x, y, z] = axis;
+    [
+        [Complex::new(cosine, -sine * z), Complex::new(-sine * y, -sine * x)],
+        [Complex::new(sine * y, -sine * x), Complex::new(cosine, sine * z)],
+    ]
+}
+
+// The rotation that turns one unit vector into the other
+fn rotation_between(from: [f64; 3], to: [f64; 3]) -> Matrix {
+    let cross = [from[1] * to[2] - from[2] * to[1], from[2] * to[0] - from[0] * to[2], from[0] * to[1] - from[1] * to[0]];
+    let dot: f64 = from.iter().zip(to.iter()).map(|(a, b)| a * b).sum();
+    let sine = cross.iter().map(|component| component * component).sum::<f64>().sqrt();
+    if sine > EXACT {
+        return rotation(sine.atan2(dot), cross.map(|component| component / sine));
+    }
+    if dot > 0.0 {
+        return rotation(0.0, [0.0, 0.0, 1.0]);
+    }
+    // Opposite vectors, which any axis at right angles to them turns into each other
+    let other = if from[0].abs() < 0.9 { [1.0, 0.0, 0.0] } else { [0.0, 1.0, 0.0] };
+    let perpendicular = [from[1] * other[2] - from[2] * other[1], from[2] * other[0] - from[0] * other[2], from[0] * other[1] - from[1] * other[0]];
+    let length = perpendicular.iter().map(|component| component * component).sum::<f64>().sqrt();
+    rotation(PI, perpendicular.map(|component| component / length))
+}
+
+// The word of the inverse gate: the inverse letters in the reverse order
+fn inverse(word: &[Letter]) -> Vec<Letter> {
+    word.iter().rev().map(|letter| letter.inverse()).collect()
+}
+
+// The same gate with pairs of H taken out and runs of T and T† counted modulo T⁸ = I
+fn simplify(word: &[Letter]) -> Vec<Letter> {
+    // Runs of H counted modulo 2, and runs of T modulo 8 with T† as -1
+    let mut runs: Vec<(Letter, i32)> = vec![];
+    for letter in word {
+        let (kind, count) = match letter {
+            Letter::H => (Letter::H, 1),
+            Letter::T => (Letter::T, 1),
+            Letter::Tdg => (Letter::T, -1),
+        };
+        let modulus = if kind == Letter::H { 2 } else { 8 };
+        match runs.last_mut() {
+            Some((last, total)) if *last == kind => {
+                *total = (*total + count).rem_euclid(modulus);
+                if *total == 0 {
+                    runs.pop();
+                }
+            }
+            _ => runs.push((kind, count.rem_euclid(modulus))),
+        }
+    }
+    runs.into_iter()
+        .flat_map(|(kind, count)| match (kind, count) {
+            (Letter::T, count) if count > 4 => vec![Letter::Tdg; (8 - count) as usize],
+            (kind, count) => vec![kind; count as usize],
+        })
+        .collect()
+}
+
+fn multiply(left: &Matrix, right: &Matrix) -> Matrix {
+    let entry = |row: usize, column: usize| left[row][0] * right[0][column] + left[row][1] * right[1][column];
+    [[entry(0, 0), entry(0, 1)], [entry(1, 0), entry(1, 1)]]
+}
+
+fn adjoint(matrix: &Matrix) -> Matrix {
+    [[matrix[0][0].conj(), matrix[1][0].conj()], [matrix[0][1].conj(), matrix[1][1].conj()]]
+}
+
+fn scale(matrix: &Matrix, factor: Complex<f64>) -> Matrix {
+    matrix.map(|row| row.map(|entry| entry * factor))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word_matrix(word: &[Letter]) -> Matrix {
+        word.iter().fold(to_matrix(&QuantumGate::i_gate()), |matrix, letter| multiply(&letter.matrix(), &matrix))
+    }
+
+    #[test]
+    fn test_exact_words() {
+        let x = approximate(&QuantumGate::x_gate(), 0.0);
+        assert_eq!(x.distance, 0.0);
+        assert_eq!(x.word, vec![Letter::H, Letter::T, Letter::T, Letter::T, Letter::T, Letter::H]);
+
+        let s_dagger = approximate(&QuantumGate::sdg_gate(), 0.0);
+        assert_eq!(s_dagger.word, vec![Letter::Tdg, Letter::Tdg]);
+        assert!(approximate(&QuantumGate::i_gate(), 0.0).word.is_empty());
+    }
+
+    #[test]
+    fn test_rotations_within_tolerance() {
+        for (gate, tolerance) in [(QuantumGate::rz_gate(0.3), 1e-2), (QuantumGate::ry_gate(1.0), 1e-2), (QuantumGate::u_gate(0.7, -0.2, 2.1), 3e-2)] {
+            let approximation = approximate(&gate, tolerance);
+
+            assert!(approximation.distance <= tolerance, "{:?}", approximation.distance);
+            assert!((distance(&to_matrix(&gate), &word_matrix(&approximation.word)) - approximation.distance).abs() < EXACT);
+        }
+    }
+
+    #[test]
+    fn test_balanced_commutator() {
+        let gate = rotation(0.05, [0.6, 0.0, 0.8]);
+
+        let (v, w) = balanced_commutator(&gate);
+
+        let commutator = multiply(&multiply(&v, &w), &multiply(&adjoint(&v), &adjoint(&w)));
+        assert!(distance(&commutator, &gate) < EXACT);
+    }
+}
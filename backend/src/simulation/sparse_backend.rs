@@ -0,0 +1,166 @@
+use crate::simulation::circuit_validator::QuantumCircuitError;
+use crate::simulation::quantum_gate::QuantumGateWrapper;
+use crate::simulation::quantum_state::{QuantumState, SparseQuantumState};
+use crate::simulation::simulation_backend::{sample_outcome, SimulationBackend, MAX_BACKEND_QUBITS};
+use crate::simulation::state_vector_backend::StateVectorBackend;
+use rand::RngCore;
+
+// Switch from the sparse to the dense representation once more than this fraction of the amplitudes are non-zero
+const DENSE_THRESHOLD: f64 = 0.25;
+// Switch back to the sparse representation once at most this fraction are non-zero
+const SPARSE_THRESHOLD: f64 = 0.125;
+
+// Only stores the non-zero amplitudes of the register, which is much cheaper for circuits
+// that stay in a few basis states, such as reversible arithmetic on basis inputs
+#[derive(Debug, Clone)]
+pub struct SparseBackend {
+    state: SparseQuantumState,
+}
+
+impl Default for SparseBackend {
+    fn default() -> Self {
+        SparseBackend { state: SparseQuantumState::new(0) }
+    }
+}
+
+impl SparseBackend {
+    pub fn from_state(state: SparseQuantumState) -> SparseBackend {
+        SparseBackend { state }
+    }
+
+    pub fn density(&self) -> f64 {
+        self.state.density()
+    }
+}
+
+impl SimulationBackend for SparseBackend {
+    fn initialize(&mut self, no_of_qubits: usize) -> Result<(), QuantumCircuitError> {
+        if no_of_qubits > MAX_BACKEND_QUBITS {
+            return Err(QuantumCircuitError::TooManyQubits);
+        }
+        self.state = SparseQuantumState::new(no_of_qubits);
+        Ok(())
+    }
+
+    fn apply_gate(&mut self, gate_wrapper: &QuantumGateWrapper) -> Result<(), QuantumCircuitError> {
+        self.state = self.state.apply_gate_on_positions(&gate_wrapper.gate, &gate_wrapper.qubits)?;
+        Ok(())
+    }
+
+    fn measure(&mut self, qubit: usize, rng: &mut dyn RngCore) -> Result<u8, QuantumCircuitError> {
+        let outcome = sample_outcome(self.state.probability_of_one(qubit)?, rng);
+        self.state = self.state.collapse(qubit, outcome)?;
+        Ok(outcome)
+    }
+
+    fn snapshot(&self) -> QuantumState {
+        self.state.to_dense()
+    }
+}
+
+// Starts out sparse and moves between the sparse and dense representations
+// depending on how many amplitudes are non-zero after each gate
+#[derive(Debug, Clone)]
+pub enum AdaptiveBackend {
+    Sparse(SparseBackend),
+    Dense(StateVectorBackend),
+}
+
+impl Default for AdaptiveBackend {
+    fn default() -> Self {
+        AdaptiveBackend::Sparse(SparseBackend::default())
+    }
+}
+
+impl AdaptiveBackend {
+    pub fn is_sparse(&self) -> bool {
+        matches!(self, AdaptiveBackend::Sparse(_))
+    }
+
+    fn backend(&mut self) -> &mut dyn SimulationBackend {
+        match self {
+            AdaptiveBackend::Sparse(backend) => backend,
+            AdaptiveBackend::Dense(backend) => backend,
+        }
+    }
+
+    fn switch_representation_if_needed(&mut self) {
+        match self {
+            AdaptiveBackend::Sparse(backend) if backend.density() > DENSE_THRESHOLD => {
+                *self = AdaptiveBackend::Dense(StateVectorBackend::from_state(backend.snapshot()));
+            }
+            AdaptiveBackend::Dense(backend) => {
+                let sparse = SparseQuantumState::from(&backend.snapshot());
+                if sparse.density() <= SPARSE_THRESHOLD {
+                    *self = AdaptiveBackend::Sparse(SparseBackend::from_state(sparse));
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+impl SimulationBackend for AdaptiveBackend {
+    fn initialize(&mut self, no_of_qubits: usize) -> Result<(), QuantumCircuitError> {
+        *self = AdaptiveBackend::default();
+        self.backend().initialize(no_of_qubits)
+    }
+
+    fn apply_gate(&mut self, gate_wrapper: &QuantumGateWrapper) -> Result<(), QuantumCircuitError> {
+        self.backend().apply_gate(gate_wrapper)?;
+        self.switch_representation_if_needed();
+        Ok(())
+    }
+
+    fn measure(&mut self, qubit: usize, rng: &mut dyn RngCore) -> Result<u8, QuantumCircuitError> {
+        let outcome = self.backend().measure(qubit, rng)?;
+        self.switch_representation_if_needed();
+        Ok(outcome)
+    }
+
+    fn snapshot(&self) -> QuantumState {
+        match self {
+            AdaptiveBackend::Sparse(backend) => backend.snapshot(),
+            AdaptiveBackend::Dense(backend) => backend.snapshot(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::quantum_gate::QuantumGate;
+
+    #[test]
+    fn test_sparse_backend_stays_small_on_basis_states() {
+        let mut backend = SparseBackend::default();
+        backend.initialize(6).unwrap();
+        backend.apply_gate(&QuantumGateWrapper { gate: QuantumGate::x_gate(), qubits: vec![0] }).unwrap();
+        backend.apply_gate(&QuantumGateWrapper { gate: QuantumGate::ccnot_gate(), qubits: vec![0, 1, 2] }).unwrap();
+        backend.apply_gate(&QuantumGateWrapper { gate: QuantumGate::cnot_gate(), qubits: vec![0, 5] }).unwrap();
+
+        assert_eq!(backend.state.amplitudes.len(), 1);
+        assert_eq!(backend.snapshot(), QuantumState::new(&[1, 0, 0, 0, 0, 1]).unwrap());
+    }
+
+    #[test]
+    fn test_adaptive_backend_switches_representation() {
+        let mut backend = AdaptiveBackend::default();
+        backend.initialize(3).unwrap();
+        assert!(backend.is_sparse());
+
+        for qubit in 0..3 {
+            backend.apply_gate(&QuantumGateWrapper { gate: QuantumGate::h_gate(), qubits: vec![qubit] }).unwrap();
+        }
+        assert!(!backend.is_sparse());
+
+        for qubit in 0..3 {
+            backend.apply_gate(&QuantumGateWrapper { gate: QuantumGate::h_gate(), qubits: vec![qubit] }).unwrap();
+        }
+        assert!(backend.is_sparse());
+        let expected = QuantumState::new(&[0, 0, 0]).unwrap();
+        for (a, b) in backend.snapshot().col.iter().zip(expected.col.iter()) {
+            assert!((a - b).norm() < 1e-10);
+        }
+    }
+}
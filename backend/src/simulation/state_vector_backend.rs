@@ -0,0 +1,55 @@
+use crate::simulation::circuit_validator::QuantumCircuitError;
+use crate::simulation::quantum_gate::QuantumGateWrapper;
+use crate::simulation::quantum_state::QuantumState;
+use crate::simulation::simulation_backend::{sample_outcome, SimulationBackend, MAX_BACKEND_QUBITS};
+use ndarray::Array2;
+use num::Complex;
+use rand::RngCore;
+
+// Keeps a single dense state vector for the whole register and applies every gate to it directly.
+// Slower than the grouped backend for unentangled qubits, but has no bookkeeping to get wrong.
+#[derive(Debug, Clone)]
+pub struct StateVectorBackend {
+    state: QuantumState,
+}
+
+impl Default for StateVectorBackend {
+    fn default() -> Self {
+        StateVectorBackend {
+            state: QuantumState { col: Array2::<Complex<f64>>::zeros((1, 1)) },
+        }
+    }
+}
+
+impl StateVectorBackend {
+    pub fn from_state(state: QuantumState) -> StateVectorBackend {
+        StateVectorBackend { state }
+    }
+}
+
+impl SimulationBackend for StateVectorBackend {
+    fn initialize(&mut self, no_of_qubits: usize) -> Result<(), QuantumCircuitError> {
+        if no_of_qubits > MAX_BACKEND_QUBITS {
+            return Err(QuantumCircuitError::TooManyQubits);
+        }
+        let mut col = Array2::<Complex<f64>>::zeros((1 << no_of_qubits, 1));
+        col[[0, 0]] = Complex::new(1.0, 0.0);
+        self.state = QuantumState { col };
+        Ok(())
+    }
+
+    fn apply_gate(&mut self, gate_wrapper: &QuantumGateWrapper) -> Result<(), QuantumCircuitError> {
+        self.state = self.state.apply_gate_on_positions(&gate_wrapper.gate, &gate_wrapper.qubits)?;
+        Ok(())
+    }
+
+    fn measure(&mut self, qubit: usize, rng: &mut dyn RngCore) -> Result<u8, QuantumCircuitError> {
+        let outcome = sample_outcome(self.state.probability_of_one(qubit)?, rng);
+        self.state = self.state.collapse(qubit, outcome)?;
+        Ok(outcome)
+    }
+
+    fn snapshot(&self) -> QuantumState {
+        self.state.clone()
+    }
+}
@@ -0,0 +1,383 @@
+// Sub-circuits are small grids that a request defines once and places in its grid by name, like a custom gate on
+// as many rows as the sub-circuit has. Their grids can use custom gates and other sub-circuits, so blocks such as
+// a QFT or an adder can be built from each other. "QFT†" places the inverse of "QFT", and a control directly above
+// a sub-circuit controls all of it.
+// A sub-circuit takes one step of the simulation, or with expanded steps one step for each of its own steps.
+
+use crate::simulation::circuit_parser::{operations_in_step, GridOperation, ParsedCircuit, UnparsedCircuit};
+use crate::simulation::circuit_validator::{validate_grid_input, QuantumCircuitError};
+use crate::simulation::custom_gates::invalid_name_reason;
+use crate::simulation::quantum_gate::{GatesInTimeStep, QuantumGate, QuantumGateWrapper};
+use crate::simulation::quantum_state::QuantumState;
+use ndarray::Array2;
+use num::Complex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// The grids of the sub-circuits by name, as requests send them
+pub type SubCircuitDefinitions = BTreeMap<String, Vec<Vec<String>>>;
+
+// Written after the name of a sub-circuit to place its inverse
+pub const INVERSE_SUFFIX: &str = "†";
+
+// Every level of nesting can double the steps, so grids that place sub-circuits may only expand to this many
+pub const MAX_EXPANDED_STEPS: usize = 1024;
+
+// The highest power a circuit can be controlled with, which already takes 1024 of its steps to simulate step by step
+pub const MAX_POWER: usize = 1 << 10;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubCircuit {
+    pub circuit: Vec<Vec<String>>,
+    // How many steps it takes with the sub-circuits inside of it expanded, which are only made when they are simulated
+    pub no_of_steps: usize,
+    // The unitary of the whole sub-circuit
+    pub gate: QuantumGate,
+}
+
+// How many states a step with sub-circuits adds to the response
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockSteps {
+    // One state for every step of the grid
+    #[default]
+    Collapsed,
+    // One state for every step inside of the sub-circuits
+    Expanded,
+}
+
+// Turn the grids of a request into sub-circuits, each one after the sub-circuits it uses,
+// reporting every definition that can not be used
+pub fn define_subcircuits(
+    definitions: SubCircuitDefinitions,
+    custom_gates: &BTreeMap<String, QuantumGate>,
+) -> Result<BTreeMap<String, SubCircuit>, Vec<QuantumCircuitError>> {
+    let mut errors = Vec::new();
+    let mut pending = SubCircuitDefinitions::new();
+    for (name, circuit) in definitions {
+        let reason = invalid_name_reason(&name).or(custom_gates.contains_key(&name).then_some("has the name of a custom gate"));
+        match reason {
+            Some(reason) => errors.push(QuantumCircuitError::InvalidSubCircuit { name, reason: reason.to_string() }),
+            None => {
+                pending.insert(name, circuit);
+            }
+        }
+    }
+
+    let mut subcircuits = BTreeMap::new();
+    loop {
+        let ready: Vec<String> = pending
+            .iter()
+            .filter(|(_, circuit)| !circuit.iter().flatten().any(|token| pending.contains_key(token.trim_end_matches(INVERSE_SUFFIX))))
+            .map(|(name, _)| name.clone())
+            .collect();
+        if ready.is_empty() {
+            break;
+        }
+        for name in ready {
+            let circuit = pending.remove(&name).expect("only pending sub-circuits are ready");
+            match compile(&name, circuit, custom_gates, &subcircuits) {
+                Ok(subcircuit) => {
+                    subcircuits.insert(name, subcircuit);
+                }
+                Err(error) => errors.push(error),
+            }
+        }
+    }
+    // Whatever is left uses itself
+    for name in pending.into_keys() {
+        errors.push(QuantumCircuitError::InvalidSubCircuit { name, reason: "uses itself, directly or through other sub-circuits".to_string() });
+    }
+
+    if errors.is_empty() {
+        Ok(subcircuits)
+    } else {
+        Err(errors)
+    }
+}
+
+// A circuit raised to a power and controlled by a new qubit above it, e.g. the controlled-U^(2^k) blocks of phase estimation.
+// The circuit becomes the sub-circuit with the given name, its powers of two are that name with the power appended,
+// each placing the one before twice, and other powers place the powers of two they are the sum of
+pub fn controlled_circuit(circuit: Vec<Vec<String>>, name: &str, power: usize) -> Result<(Vec<Vec<String>>, SubCircuitDefinitions), QuantumCircuitError> {
+    let no_of_qubits = circuit.len();
+    if power > MAX_POWER {
+        return Err(QuantumCircuitError::InvalidSubCircuit {
+            name: name.to_string(),
+            reason: format!("can not be applied more than {} times", MAX_POWER),
+        });
+    }
+    if power == 0 {
+        return Ok((vec![vec!["I".to_string()]; no_of_qubits + 1], SubCircuitDefinitions::new()));
+    }
+
+    let block_name = |exponent: usize| if exponent == 1 { name.to_string() } else { format!("{}_{}", name, exponent) };
+    let placed = |blocks: &[String]| vec![blocks.to_vec(); no_of_qubits];
+    let mut definitions = SubCircuitDefinitions::from([(name.to_string(), circuit)]);
+    let mut exponent: usize = 1;
+    while exponent.checked_mul(2).is_some_and(|next| next <= power) {
+        definitions.insert(block_name(exponent * 2), placed(&[block_name(exponent), block_name(exponent)]));
+        exponent *= 2;
+    }
+    if !power.is_power_of_two() {
+        let powers_of_two: Vec<String> = (0..usize::BITS).map(|bit| 1 << bit).filter(|exponent| power & exponent != 0).map(block_name).collect();
+        definitions.insert(block_name(power), placed(&powers_of_two));
+    }
+
+    let mut grid = vec![vec!["C_down".to_string()]];
+    grid.extend(placed(&[block_name(power)]));
+    Ok((grid, definitions))
+}
+
+// The circuit with a step for every step inside of its sub-circuits, and the step of the grid each of them belongs to
+pub fn expand_subcircuits(grid: &UnparsedCircuit) -> Result<(ParsedCircuit, Vec<usize>), QuantumCircuitError> {
+    expanded_steps(grid)?;
+    let no_of_qubits = grid.circuit.len();
+    // The rows of the sub-circuits are swapped into this grid while they are expanded, so that the definitions are not copied
+    let mut scratch = grid.clone();
+    let mut circuit = Vec::new();
+    let mut grid_steps = Vec::new();
+    for step in 0..grid.circuit.first().map_or(0, Vec::len) {
+        for mut gates in step_gates(&mut scratch, step, true)? {
+            // Qubits that nothing happens to get a wire, so every qubit is covered in every step as in the parser
+            let covered: Vec<usize> = gates.iter().flat_map(|gate| gate.qubits.clone()).collect();
+            gates.extend(
+                (0..no_of_qubits)
+                    .filter(|qubit| !covered.contains(qubit))
+                    .map(|qubit| QuantumGateWrapper { gate: QuantumGate::i_gate(), qubits: vec![qubit] }),
+            );
+            gates.sort_by_key(|gate| gate.qubits[0]);
+            circuit.push(GatesInTimeStep { gates });
+            grid_steps.push(step);
+        }
+    }
+    Ok((ParsedCircuit { circuit }, grid_steps))
+}
+
+// How many steps the grid takes with its sub-circuits expanded, which may not be more than MAX_EXPANDED_STEPS
+// when it places any. The error names the longest sub-circuit of the grid
+pub fn expanded_steps(grid: &UnparsedCircuit) -> Result<usize, QuantumCircuitError> {
+    let (no_of_steps, longest) = count_steps(grid)?;
+    match longest {
+        Some((name, _)) if no_of_steps > MAX_EXPANDED_STEPS => Err(QuantumCircuitError::InvalidSubCircuit {
+            name,
+            reason: format!("expands to more than {} steps", MAX_EXPANDED_STEPS),
+        }),
+        _ => Ok(no_of_steps),
+    }
+}
+
+// The expanded steps of the grid, which can be anything up to usize::MAX, and its longest sub-circuit with its steps
+fn count_steps(grid: &UnparsedCircuit) -> Result<(usize, Option<(String, usize)>), QuantumCircuitError> {
+    let mut no_of_steps: usize = 0;
+    let mut longest: Option<(String, usize)> = None;
+    for step in 0..grid.circuit.first().map_or(0, Vec::len) {
+        let mut steps_in_step = 1;
+        for operation in operations_in_step(grid, step)? {
+            let token = match operation {
+                GridOperation::Single { token, .. } | GridOperation::Custom { token, .. } | GridOperation::Controlled { token, .. } => token,
+                GridOperation::Swap { .. } => continue,
+            };
+            let name = token.trim_end_matches(INVERSE_SUFFIX);
+            if let Some(subcircuit) = grid.subcircuits.get(name) {
+                steps_in_step = steps_in_step.max(subcircuit.no_of_steps);
+                if longest.as_ref().is_none_or(|(_, steps)| subcircuit.no_of_steps > *steps) {
+                    longest = Some((name.to_string(), subcircuit.no_of_steps));
+                }
+            }
+        }
+        no_of_steps = no_of_steps.saturating_add(steps_in_step);
+    }
+    Ok((no_of_steps, longest))
+}
+
+// The gates of a step of the grid, with its sub-circuits spread over their own steps when they are expanded.
+// Everything else happens in the first of those steps
+fn step_gates(grid: &mut UnparsedCircuit, step: usize, expand: bool) -> Result<Vec<Vec<QuantumGateWrapper>>, QuantumCircuitError> {
+    let mut expanded: Vec<Vec<QuantumGateWrapper>> = vec![Vec::new()];
+    for operation in operations_in_step(grid, step)? {
+        let mut steps_of = |token: &str, gate: QuantumGate| -> Result<Vec<QuantumGate>, QuantumCircuitError> {
+            match expand {
+                true => Ok(subcircuit_steps(grid, token)?.unwrap_or(vec![gate])),
+                false => Ok(vec![gate]),
+            }
+        };
+        let (qubits, gates): (Vec<usize>, Vec<QuantumGate>) = match operation {
+            GridOperation::Single { token, gate, qubit } => (vec![qubit], steps_of(&token, gate)?),
+            GridOperation::Custom { token, gate, upper } => ((upper..upper + gate.size).collect(), steps_of(&token, gate)?),
+            GridOperation::Controlled { token, gate, control, target } => {
+                let size = gate.size;
+                let gates = steps_of(&token, gate)?;
+                let no_of_controls = target - control;
+                ((control..target + size).collect(), gates.into_iter().map(|gate| QuantumGate::multi_controlled(gate, no_of_controls)).collect())
+            }
+            GridOperation::Swap { upper } => (vec![upper, upper + 1], vec![QuantumGate::swap_gate()]),
+        };
+        for (index, gate) in gates.into_iter().enumerate() {
+            if expanded.len() <= index {
+                expanded.push(Vec::new());
+            }
+            expanded[index].push(QuantumGateWrapper { gate, qubits: qubits.clone() });
+        }
+    }
+    Ok(expanded)
+}
+
+// The unitaries of the expanded steps of the sub-circuit a token places, reversed and inverted for "QFT†"
+fn subcircuit_steps(grid: &mut UnparsedCircuit, token: &str) -> Result<Option<Vec<QuantumGate>>, QuantumCircuitError> {
+    if let Some(name) = token.strip_suffix(INVERSE_SUFFIX) {
+        return Ok(subcircuit_steps(grid, name)?.map(|steps| steps.iter().rev().map(QuantumGate::adjoint).collect()));
+    }
+    let Some(subcircuit) = grid.subcircuits.get(token) else {
+        return Ok(None);
+    };
+    let (rows, no_of_qubits) = (subcircuit.circuit.clone(), subcircuit.gate.size);
+
+    let outer = std::mem::replace(&mut grid.circuit, rows);
+    let mut expand = || -> Result<Vec<QuantumGate>, QuantumCircuitError> {
+        let mut steps = Vec::new();
+        for step in 0..grid.circuit[0].len() {
+            for gates in step_gates(grid, step, true)? {
+                steps.push(step_unitary(&gates, no_of_qubits)?);
+            }
+        }
+        Ok(steps)
+    };
+    let steps = expand();
+    grid.circuit = outer;
+    steps.map(Some)
+}
+
+fn compile(
+    name: &str,
+    circuit: Vec<Vec<String>>,
+    custom_gates: &BTreeMap<String, QuantumGate>,
+    subcircuits: &BTreeMap<String, SubCircuit>,
+) -> Result<SubCircuit, QuantumCircuitError> {
+    let invalid = |reason: String| QuantumCircuitError::InvalidSubCircuit { name: name.to_string(), reason };
+    let mut grid = UnparsedCircuit { circuit, custom_gates: custom_gates.clone(), subcircuits: subcircuits.clone() };
+    validate_grid_input(&grid).map_err(|errors| invalid(format!("can not be used: {}", errors[0].message())))?;
+
+    // The sub-circuits inside of it are single gates here, so nesting them does not multiply the work
+    let no_of_qubits = grid.circuit.len();
+    let dimension = 1 << no_of_qubits;
+    let mut matrix = Array2::eye(dimension);
+    for step in 0..grid.circuit[0].len() {
+        for gates in step_gates(&mut grid, step, false)? {
+            matrix = step_unitary(&gates, no_of_qubits)?.matrix.dot(&matrix);
+        }
+    }
+    let (no_of_steps, _) = count_steps(&grid)?;
+    Ok(SubCircuit { circuit: grid.circuit, no_of_steps, gate: QuantumGate { matrix, size: no_of_qubits } })
+}
+
+// The unitary of gates on separate qubits, column by column as the images of the basis states
+fn step_unitary(gates: &[QuantumGateWrapper], no_of_qubits: usize) -> Result<QuantumGate, QuantumCircuitError> {
+    let dimension = 1 << no_of_qubits;
+    let mut matrix = Array2::<Complex<f64>>::zeros((dimension, dimension));
+    for index in 0..dimension {
+        let mut state = QuantumState { col: Array2::zeros((dimension, 1)) };
+        state.col[[index, 0]] = Complex::new(1.0, 0.0);
+        for gate in gates {
+            state = state.apply_gate_on_positions(&gate.gate, &gate.qubits)?;
+        }
+        matrix.column_mut(index).assign(&state.col.column(0));
+    }
+    Ok(QuantumGate { matrix, size: no_of_qubits })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::circuit_parser::build_circuit_from_data;
+    use crate::simulation::test_support::assert_gates_close;
+
+    fn definitions(subcircuits: &[(&str, Vec<Vec<&str>>)]) -> SubCircuitDefinitions {
+        subcircuits
+            .iter()
+            .map(|(name, circuit)| (name.to_string(), circuit.iter().map(|row| row.iter().map(|token| token.to_string()).collect()).collect()))
+            .collect()
+    }
+
+    #[test]
+    fn test_nested_subcircuits() {
+        let subcircuits = define_subcircuits(
+            definitions(&[("Bell", vec![vec!["Plus", "C_down"], vec!["I", "X"]]), ("Plus", vec![vec!["H"]])]),
+            &BTreeMap::new(),
+        )
+        .unwrap();
+
+        assert_eq!(subcircuits["Plus"].no_of_steps, 1);
+        assert_eq!(subcircuits["Bell"].no_of_steps, 2);
+        let bell = QuantumGate::c_down(QuantumGate::x_gate()).matrix.dot(&QuantumGate::h_gate().kronecker(QuantumGate::i_gate()).matrix);
+        assert_gates_close(&subcircuits["Bell"].gate, &QuantumGate { matrix: bell, size: 2 });
+    }
+
+    #[test]
+    fn test_inverse_and_controlled_blocks() {
+        let subcircuits = define_subcircuits(definitions(&[("Rot", vec![vec!["H", "T"]])]), &BTreeMap::new()).unwrap();
+        let rot = subcircuits["Rot"].gate.clone();
+        let grid = UnparsedCircuit {
+            circuit: vec![vec!["Rot".to_string(), "Rot†".to_string(), "C_down".to_string()], vec!["I".to_string(), "I".to_string(), "Rot".to_string()]],
+            subcircuits,
+            ..UnparsedCircuit::default()
+        };
+
+        let parsed = build_circuit_from_data(grid.clone()).unwrap();
+        assert_gates_close(&parsed.circuit[1].gates[0].gate, &rot.adjoint());
+        assert_gates_close(&parsed.circuit[2].gates[0].gate, &QuantumGate::c_down(rot));
+
+        let (expanded, grid_steps) = expand_subcircuits(&grid).unwrap();
+        assert_eq!(grid_steps, vec![0, 0, 1, 1, 2, 2]);
+        assert_gates_close(&expanded.circuit[2].gates[0].gate, &QuantumGate::t_gate().adjoint());
+        assert_gates_close(&expanded.circuit[5].gates[0].gate, &QuantumGate::c_down(QuantumGate::t_gate()));
+        assert_eq!(expanded.circuit[0].gates[1], QuantumGateWrapper { gate: QuantumGate::i_gate(), qubits: vec![1] });
+    }
+
+    #[test]
+    fn test_controlled_powers() {
+        let (circuit, definitions) = controlled_circuit(vec![vec!["T".to_string()]], "W", 5).unwrap();
+
+        assert_eq!(circuit, vec![vec!["C_down"], vec!["W_5"]]);
+        assert_eq!(definitions.keys().collect::<Vec<_>>(), vec!["W", "W_2", "W_4", "W_5"]);
+        assert_eq!(definitions["W_5"], vec![vec!["W", "W_4"]]);
+
+        let subcircuits = define_subcircuits(definitions, &BTreeMap::new()).unwrap();
+        let parsed = build_circuit_from_data(UnparsedCircuit { circuit, subcircuits, ..UnparsedCircuit::default() }).unwrap();
+        assert_gates_close(&parsed.circuit[0].gates[0].gate, &QuantumGate::c_down(QuantumGate::phase_gate(5.0 * std::f64::consts::PI / 4.0)));
+
+        assert_eq!(controlled_circuit(vec![vec!["H".to_string()]; 2], "V", 0), Ok((vec![vec!["I".to_string()]; 3], SubCircuitDefinitions::new())));
+        assert_eq!(controlled_circuit(vec![vec!["H".to_string()]], "V", MAX_POWER).unwrap().1.len(), 11);
+        assert!(controlled_circuit(vec![vec!["H".to_string()]], "V", usize::MAX).is_err());
+    }
+
+    #[test]
+    fn test_nesting_is_not_expanded_past_the_limit() {
+        // Every level places the one below twice, so the last one has 2^30 steps
+        let mut nested = definitions(&[("B0", vec![vec!["H", "C_down"], vec!["I", "X"]])]);
+        for level in 1..30 {
+            let below = format!("B{}", level - 1);
+            nested.insert(format!("B{}", level), vec![vec![below.clone(), below]; 2]);
+        }
+        let subcircuits = define_subcircuits(nested, &BTreeMap::new()).unwrap();
+        assert_eq!(subcircuits["B29"].no_of_steps, 1 << 30);
+
+        let grid = UnparsedCircuit { circuit: vec![vec!["B29".to_string()]; 2], subcircuits, ..UnparsedCircuit::default() };
+        assert_eq!(build_circuit_from_data(grid.clone()).unwrap().circuit.len(), 1);
+        let error = expand_subcircuits(&grid).unwrap_err();
+        assert_eq!(error, QuantumCircuitError::InvalidSubCircuit { name: "B29".to_string(), reason: "expands to more than 1024 steps".to_string() });
+    }
+
+    #[test]
+    fn test_invalid_subcircuits() {
+        let errors = define_subcircuits(
+            definitions(&[("Loop", vec![vec!["Back"]]), ("Back", vec![vec!["Loop†"]]), ("Broken", vec![vec!["A"]]), ("Swap", vec![vec!["X"]])]),
+            &BTreeMap::new(),
+        )
+        .unwrap_err();
+
+        let names: Vec<&str> = errors.iter().map(|error| error.token().unwrap()).collect();
+        assert_eq!(names, vec!["Swap", "Broken", "Back", "Loop"]);
+        assert_eq!(errors[1].message(), "The sub-circuit 'Broken' can not be used: 'A' on qubit 0 at step 0 is not a known gate");
+    }
+}
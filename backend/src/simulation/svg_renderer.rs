@@ -0,0 +1,295 @@
+// Renders grid circuits as SVG images that can be embedded in web pages without the React client.
+// Columns grow with the longest label in them, so parameterised gates such as "U(pi/2,0,pi)" fit in their box.
+// Optionally the probabilities of the basis states after one step are drawn as a bar chart under the circuit.
+
+use crate::simulation::circuit_parser::{operations_in_step, GridOperation, UnparsedCircuit};
+use crate::simulation::circuit_validator::{validate_grid_input, QuantumCircuitError};
+use crate::simulation::simulation_backend::BackendKind;
+use crate::simulation::simulator::simulate_state_after_step_with_backend;
+use crate::simulation::utils::BitOrder;
+
+const MARGIN: usize = 16;
+const LABEL_WIDTH: usize = 40;
+const ROW_HEIGHT: usize = 48;
+const MIN_COLUMN_WIDTH: usize = 56;
+const GATE_SIZE: usize = 32;
+// An estimate of the width of one character of the monospace label font
+const CHARACTER_WIDTH: usize = 8;
+const CHART_HEIGHT: usize = 120;
+const CHART_LABEL_HEIGHT: usize = 40;
+const CHART_NOTE_HEIGHT: usize = 16;
+
+pub fn render_svg(grid: &UnparsedCircuit, probability_step: Option<usize>, bit_order: BitOrder) -> Result<String, Vec<QuantumCircuitError>> {
+    validate_grid_input(grid)?;
+    let no_of_qubits = grid.circuit.len();
+    let no_of_steps = grid.circuit[0].len();
+
+    let mut steps: Vec<Vec<GridOperation>> = Vec::new();
+    for step in 0..no_of_steps {
+        steps.push(operations_in_step(grid, step).map_err(|error| vec![error])?);
+    }
+    let column_widths: Vec<usize> = steps
+        .iter()
+        .map(|operations| {
+            let widest_box = operations.iter().map(|operation| box_width(operation_label(operation))).max().unwrap_or(GATE_SIZE);
+            (widest_box + 16).max(MIN_COLUMN_WIDTH)
+        })
+        .collect();
+    let column_starts: Vec<usize> = column_widths
+        .iter()
+        .scan(MARGIN + LABEL_WIDTH, |x, width| {
+            let start = *x;
+            *x += width;
+            Some(start)
+        })
+        .collect();
+    let circuit_width = MARGIN + LABEL_WIDTH + column_widths.iter().sum::<usize>() + MARGIN;
+    let circuit_height = MARGIN + no_of_qubits * ROW_HEIGHT + MARGIN;
+    let wire_y = |qubit: usize| MARGIN + qubit * ROW_HEIGHT + ROW_HEIGHT / 2;
+
+    let mut elements: Vec<String> = Vec::new();
+
+    if let Some(step) = probability_step {
+        if step >= no_of_steps {
+            return Err(vec![QuantumCircuitError::StepOutOfRange { step, no_of_steps }]);
+        }
+        elements.push(format!(
+            r##"<rect x="{}" y="{}" width="{}" height="{}" fill="#eef4ff"/>"##,
+            column_starts[step],
+            MARGIN / 2,
+            column_widths[step],
+            no_of_qubits * ROW_HEIGHT + MARGIN
+        ));
+    }
+
+    for qubit in 0..no_of_qubits {
+        let y = wire_y(qubit);
+        elements.push(format!(r#"<text x="{}" y="{}" dominant-baseline="middle">q{}</text>"#, MARGIN, y, qubit));
+        elements.push(format!(
+            r#"<line x1="{}" y1="{}" x2="{}" y2="{}" stroke="black"/>"#,
+            MARGIN + LABEL_WIDTH,
+            y,
+            circuit_width - MARGIN,
+            y
+        ));
+    }
+
+    for (step, operations) in steps.iter().enumerate() {
+        let x = column_starts[step] + column_widths[step] / 2;
+        for operation in operations {
+            match operation {
+                GridOperation::Single { token, qubit, .. } => {
+                    if token != "I" {
+                        elements.push(gate_box(x, wire_y(*qubit), token));
+                    }
+                }
+                GridOperation::Controlled { token, gate, control, target } => {
+                    elements.push(format!(
+                        r#"<line x1="{}" y1="{}" x2="{}" y2="{}" stroke="black"/>"#,
+                        x,
+                        wire_y(*control),
+                        x,
+                        wire_y(*target)
+                    ));
+                    for row in *control..*target {
+                        elements.push(format!(r#"<circle cx="{}" cy="{}" r="5" fill="black"/>"#, x, wire_y(row)));
+                    }
+                    if token == "X" {
+                        elements.push(target_symbol(x, wire_y(*target)));
+                    } else {
+                        elements.push(tall_gate_box(x, wire_y(*target), wire_y(target + gate.size - 1), token));
+                    }
+                }
+                GridOperation::Swap { upper } => {
+                    elements.push(format!(
+                        r#"<line x1="{}" y1="{}" x2="{}" y2="{}" stroke="black"/>"#,
+                        x,
+                        wire_y(*upper),
+                        x,
+                        wire_y(upper + 1)
+                    ));
+                    elements.push(swap_cross(x, wire_y(*upper)));
+                    elements.push(swap_cross(x, wire_y(upper + 1)));
+                }
+                GridOperation::Custom { token, gate, upper } => {
+                    let lower = upper + gate.size - 1;
+                    elements.push(tall_gate_box(x, wire_y(*upper), wire_y(lower), token));
+                }
+            }
+        }
+    }
+
+    let mut height = circuit_height;
+    if let Some(step) = probability_step {
+        let probabilities = simulate_state_after_step_with_backend(grid.clone(), BackendKind::default(), bit_order, step)?.probabilities();
+        elements.append(&mut probability_chart(&probabilities, no_of_qubits, bit_order, circuit_height, circuit_width));
+        height += CHART_HEIGHT + CHART_LABEL_HEIGHT + CHART_NOTE_HEIGHT + MARGIN;
+    }
+
+    Ok(format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\" font-family=\"monospace\" font-size=\"14\">\n<rect width=\"{w}\" height=\"{h}\" fill=\"white\"/>\n{}\n</svg>\n",
+        elements.join("\n"),
+        w = circuit_width,
+        h = height
+    ))
+}
+
+// The text drawn for an operation, which decides how wide its column has to be
+fn operation_label(operation: &GridOperation) -> &str {
+    match operation {
+        GridOperation::Single { token, .. } | GridOperation::Controlled { token, .. } | GridOperation::Custom { token, .. } => token,
+        GridOperation::Swap { .. } => "",
+    }
+}
+
+fn box_width(label: &str) -> usize {
+    (label.chars().count() * CHARACTER_WIDTH + 12).max(GATE_SIZE)
+}
+
+fn gate_box(x: usize, y: usize, label: &str) -> String {
+    tall_gate_box(x, y, y, label)
+}
+
+// A box from the wire at the top to the wire at the bottom, labelled in the middle
+fn tall_gate_box(x: usize, top: usize, bottom: usize, label: &str) -> String {
+    let width = box_width(label);
+    format!(
+        "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"white\" stroke=\"black\"/>\n<text x=\"{}\" y=\"{}\" text-anchor=\"middle\" dominant-baseline=\"middle\">{}</text>",
+        x - width / 2,
+        top - GATE_SIZE / 2,
+        width,
+        bottom - top + GATE_SIZE,
+        x,
+        (top + bottom) / 2,
+        escape(label)
+    )
+}
+
+// The ⊕ of a controlled X
+fn target_symbol(x: usize, y: usize) -> String {
+    format!(
+        "<circle cx=\"{x}\" cy=\"{y}\" r=\"12\" fill=\"white\" stroke=\"black\"/>\n<line x1=\"{}\" y1=\"{y}\" x2=\"{}\" y2=\"{y}\" stroke=\"black\"/>\n<line x1=\"{x}\" y1=\"{}\" x2=\"{x}\" y2=\"{}\" stroke=\"black\"/>",
+        x - 12,
+        x + 12,
+        y - 12,
+        y + 12,
+        x = x,
+        y = y
+    )
+}
+
+fn swap_cross(x: usize, y: usize) -> String {
+    format!(
+        "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"black\"/>\n<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"black\"/>",
+        x - 7,
+        y - 7,
+        x + 7,
+        y + 7,
+        x - 7,
+        y + 7,
+        x + 7,
+        y - 7
+    )
+}
+
+// One bar per basis state, labelled with its index in binary and a note on which end qubit 0 is at
+fn probability_chart(probabilities: &[f64], no_of_qubits: usize, bit_order: BitOrder, top: usize, circuit_width: usize) -> Vec<String> {
+    let left = MARGIN + LABEL_WIDTH;
+    let chart_width = circuit_width - left - MARGIN;
+    let bar_slot = chart_width as f64 / probabilities.len() as f64;
+    let baseline = top + CHART_HEIGHT;
+    // Long labels are turned sideways once they no longer fit under their bar
+    let sideways = (no_of_qubits * CHARACTER_WIDTH) as f64 > bar_slot;
+
+    let note = match bit_order {
+        BitOrder::LittleEndian => "q0 is the rightmost bit",
+        BitOrder::BigEndian => "q0 is the leftmost bit",
+    };
+    let mut elements = vec![
+        format!(
+            r#"<line x1="{}" y1="{}" x2="{}" y2="{}" stroke="black"/>"#,
+            left,
+            baseline,
+            left + chart_width,
+            baseline
+        ),
+        format!(
+            r#"<text x="{}" y="{}" font-size="10" dominant-baseline="hanging">{}</text>"#,
+            left,
+            baseline + CHART_LABEL_HEIGHT,
+            note
+        ),
+    ];
+    for (index, probability) in probabilities.iter().enumerate() {
+        let bar_height = probability * CHART_HEIGHT as f64;
+        let centre = left as f64 + bar_slot * (index as f64 + 0.5);
+        elements.push(format!(
+            r##"<rect x="{:.1}" y="{:.1}" width="{:.1}" height="{:.1}" fill="#3b73d1"><title>{:.4}</title></rect>"##,
+            centre - bar_slot * 0.4,
+            baseline as f64 - bar_height,
+            bar_slot * 0.8,
+            bar_height,
+            probability
+        ));
+        let label = format!("{:0width$b}", index, width = no_of_qubits);
+        let label_y = baseline + 6;
+        if sideways {
+            elements.push(format!(
+                r#"<text x="{:.1}" y="{}" font-size="10" text-anchor="end" dominant-baseline="middle" transform="rotate(-90 {:.1} {})">{}</text>"#,
+                centre, label_y, centre, label_y, label
+            ));
+        } else {
+            elements.push(format!(
+                r#"<text x="{:.1}" y="{}" font-size="10" text-anchor="middle" dominant-baseline="hanging">{}</text>"#,
+                centre, label_y, label
+            ));
+        }
+    }
+    elements
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_every_kind_of_gate() {
+        let grid = UnparsedCircuit::from(vec![
+            vec!["H", "C_down", "Swap", "U(pi/2,0,pi)"],
+            vec!["I", "X", "Swap", "C_down"],
+            vec!["T", "I", "I", "Rz(pi/4)"],
+        ]);
+
+        let svg = render_svg(&grid, None, BitOrder::LittleEndian).unwrap();
+
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\""));
+        assert!(svg.ends_with("</svg>\n"));
+        assert!(svg.contains(">H</text>"));
+        assert!(svg.contains(">U(pi/2,0,pi)</text>"));
+        assert!(svg.contains(">Rz(pi/4)</text>"));
+        assert_eq!(svg.matches(r#"r="5" fill="black""#).count(), 2);
+        assert_eq!(svg.matches(r#"r="12""#).count(), 1);
+        assert!(!svg.contains(">I</text>"));
+        assert!(!svg.contains("<title>"));
+    }
+
+    #[test]
+    fn test_probability_chart_of_a_step() {
+        let grid = UnparsedCircuit::from(vec![vec!["H", "C_down"], vec!["I", "X"]]);
+
+        let svg = render_svg(&grid, Some(1), BitOrder::LittleEndian).unwrap();
+
+        assert_eq!(svg.matches("<title>").count(), 4);
+        assert_eq!(svg.matches("<title>0.5000</title>").count(), 2);
+        assert!(svg.contains(">11</text>"));
+        assert!(svg.contains(">q0 is the rightmost bit</text>"));
+        assert_eq!(
+            render_svg(&grid, Some(2), BitOrder::LittleEndian),
+            Err(vec![QuantumCircuitError::StepOutOfRange { step: 2, no_of_steps: 2 }])
+        );
+    }
+}
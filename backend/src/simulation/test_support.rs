@@ -0,0 +1,34 @@
+// Assertions that the tests of several modules share, which compare gates and states up to rounding errors
+
+use crate::simulation::circuit_parser::UnparsedCircuit;
+use crate::simulation::quantum_gate::QuantumGate;
+use crate::simulation::quantum_state::QuantumState;
+use crate::simulation::simulator::simulate_circuit_handler;
+use num::Complex;
+
+const EPSILON: f64 = 1e-10;
+
+pub fn assert_gates_close(left: &QuantumGate, right: &QuantumGate) {
+    assert_eq!(left.size, right.size);
+    assert!((&left.matrix - &right.matrix).iter().all(|difference| difference.norm() < EPSILON), "{:?} != {:?}", left, right);
+}
+
+pub fn states_close(left: &[QuantumState], right: &[QuantumState]) -> bool {
+    left.len() == right.len()
+        && left.iter().zip(right.iter()).all(|(left_state, right_state)| {
+            left_state.col.dim() == right_state.col.dim()
+                && left_state.col.iter().zip(right_state.col.iter()).all(|(a, b)| (a - b).norm() < EPSILON)
+        })
+}
+
+pub fn assert_states_close(left: &[QuantumState], right: &[QuantumState]) {
+    assert!(states_close(left, right), "{:?} != {:?}", left, right);
+}
+
+// The final states of two grids agree up to a global phase, within the tolerance
+pub fn assert_same_final_state(left: &UnparsedCircuit, right: &UnparsedCircuit, tolerance: f64) {
+    let left_state = simulate_circuit_handler(left.clone()).unwrap().pop().unwrap();
+    let right_state = simulate_circuit_handler(right.clone()).unwrap().pop().unwrap();
+    let overlap: Complex<f64> = left_state.col.iter().zip(right_state.col.iter()).map(|(a, b)| a.conj() * b).sum();
+    assert!(1.0 - overlap.norm() < tolerance, "{} against\n{}", left, right);
+}
@@ -0,0 +1,159 @@
+// Renders grid circuits as text diagrams, for debug output, test failures and the /render/text endpoint:
+//
+// q0: ─H──●─
+//         │
+// q1: ────X─
+
+use crate::simulation::circuit_parser::{operations_in_step, GridOperation, UnparsedCircuit};
+use crate::simulation::circuit_validator::{validate_grid_input, QuantumCircuitError};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TextStyle {
+    #[default]
+    Unicode,
+    // Only ASCII characters, for terminals and logs that mangle box drawing characters
+    Ascii,
+}
+
+struct Symbols {
+    wire: char,
+    control: &'static str,
+    swap: &'static str,
+    connector: char,
+}
+
+impl TextStyle {
+    fn symbols(self) -> Symbols {
+        match self {
+            TextStyle::Unicode => Symbols { wire: '─', control: "●", swap: "×", connector: '│' },
+            TextStyle::Ascii => Symbols { wire: '-', control: "*", swap: "x", connector: '|' },
+        }
+    }
+}
+
+pub fn render_text(grid: &UnparsedCircuit, style: TextStyle) -> Result<String, Vec<QuantumCircuitError>> {
+    validate_grid_input(grid)?;
+    let symbols = style.symbols();
+    let no_of_qubits = grid.circuit.len();
+
+    let label_width = format!("q{}: ", no_of_qubits - 1).len();
+    let mut qubit_lines: Vec<String> = (0..no_of_qubits).map(|qubit| format!("{:<width$}", format!("q{}:", qubit), width = label_width)).collect();
+    let mut connector_lines: Vec<String> = vec![" ".repeat(label_width); no_of_qubits - 1];
+
+    for step in 0..grid.circuit[0].len() {
+        // The label on every qubit, None for a wire, and whether the rows below each qubit are connected
+        let mut labels: Vec<Option<String>> = vec![None; no_of_qubits];
+        let mut connected: Vec<bool> = vec![false; no_of_qubits - 1];
+        for operation in operations_in_step(grid, step).map_err(|error| vec![error])? {
+            match operation {
+                GridOperation::Single { token, qubit, .. } => {
+                    if token != "I" {
+                        labels[qubit] = Some(token);
+                    }
+                }
+                GridOperation::Controlled { token, gate, control, target } => {
+                    labels[control..target].fill(Some(symbols.control.to_string()));
+                    labels[target..target + gate.size].fill(Some(token));
+                    connected[control..target + gate.size - 1].fill(true);
+                }
+                GridOperation::Swap { upper } => {
+                    labels[upper] = Some(symbols.swap.to_string());
+                    labels[upper + 1] = Some(symbols.swap.to_string());
+                    connected[upper] = true;
+                }
+                GridOperation::Custom { token, gate, upper } => {
+                    labels[upper..upper + gate.size].fill(Some(token));
+                    connected[upper..upper + gate.size - 1].fill(true);
+                }
+            }
+        }
+
+        let width = labels.iter().flatten().map(|label| label.chars().count()).max().unwrap_or(1);
+        // Connectors line up with the symbols, which are one character wide
+        let centre = (width - 1) / 2;
+        for (qubit, label) in labels.iter().enumerate() {
+            let label = label.clone().unwrap_or_else(|| symbols.wire.to_string());
+            let padding = width - label.chars().count();
+            let wire = |length: usize| symbols.wire.to_string().repeat(length);
+            qubit_lines[qubit].push_str(&format!("{}{}{}{}", wire(1 + padding / 2), label, wire(padding - padding / 2), wire(1)));
+        }
+        for (qubit, line) in connector_lines.iter_mut().enumerate() {
+            let mut cell = vec![' '; width + 2];
+            if connected[qubit] {
+                cell[1 + centre] = symbols.connector;
+            }
+            line.extend(cell);
+        }
+    }
+
+    let mut lines: Vec<String> = Vec::new();
+    for (qubit, line) in qubit_lines.into_iter().enumerate() {
+        lines.push(line);
+        if let Some(connector) = connector_lines.get(qubit) {
+            lines.push(connector.trim_end().to_string());
+        }
+    }
+    lines.push(String::new());
+    Ok(lines.join("\n"))
+}
+
+// Valid grids are shown as diagrams, anything else as its rows of tokens
+impl fmt::Display for UnparsedCircuit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match render_text(self, TextStyle::Unicode) {
+            Ok(diagram) => write!(f, "{}", diagram),
+            Err(_) => write!(f, "{:?}", self.circuit),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_bell_state() {
+        let grid = UnparsedCircuit::from(vec![vec!["H", "C_down"], vec!["I", "X"]]);
+
+        assert_eq!(render_text(&grid, TextStyle::Unicode).unwrap(), "q0: ─H──●─\n        │\nq1: ────X─\n");
+        assert_eq!(render_text(&grid, TextStyle::Ascii).unwrap(), "q0: -H--*-\n        |\nq1: ----X-\n");
+    }
+
+    #[test]
+    fn test_render_swaps_and_wide_labels() {
+        let grid = UnparsedCircuit::from(vec![vec!["Swap", "Rz(pi/4)"], vec!["Swap", "C_down"], vec!["T", "Y"]]);
+
+        assert_eq!(
+            render_text(&grid, TextStyle::Unicode).unwrap(),
+            [
+                "q0: ─×──Rz(pi/4)─",
+                "     │",
+                "q1: ─×─────●─────",
+                "           │",
+                "q2: ─T─────Y─────",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn test_display_falls_back_to_tokens() {
+        let grid = UnparsedCircuit::from(vec![vec!["X"]]);
+        let invalid_grid = UnparsedCircuit::from(vec![vec!["A"]]);
+
+        assert_eq!(grid.to_string(), "q0: ─X─\n");
+        assert_eq!(invalid_grid.to_string(), "[[\"A\"]]");
+    }
+
+    #[test]
+    fn test_render_custom_gate_on_several_qubits() {
+        let mut grid = UnparsedCircuit::from(vec![vec!["Oracle", "H"], vec!["Oracle", "I"]]);
+        grid.custom_gates.insert("Oracle".to_string(), crate::simulation::quantum_gate::QuantumGate::swap_gate());
+
+        assert_eq!(render_text(&grid, TextStyle::Unicode).unwrap(), "q0: ─Oracle──H─\n       │\nq1: ─Oracle────\n");
+    }
+}
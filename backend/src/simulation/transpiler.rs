@@ -0,0 +1,428 @@
+// Transpilation of a grid circuit into a small set of basis gates, to show that a few gates are enough for every circuit.
+// Every gate is first written as gates with any number of controls on a single target, which are then taken apart into
+// CNOTs and single-qubit gates: Toffoli gates into the 6 CNOTs and 7 T gates of qelib1, other gates with one control
+// into two CNOTs around three single-qubit gates, and gates with more controls by Barenco's halving of the gate.
+// The single-qubit gates between two CNOTs on a wire are multiplied together and written in the basis, exactly for
+// Rz and SX, and for H and T as exactly as the tolerance asks for with the Solovay–Kitaev algorithm.
+
+use crate::simulation::angle::format_angle;
+use crate::simulation::circuit_inverse::invert_circuit;
+use crate::simulation::circuit_parser::{operations_in_step, parse_parameterised_token, GridOperation, UnparsedCircuit};
+use crate::simulation::circuit_validator::{validate_grid_input, QuantumCircuitError};
+use crate::simulation::grid_layout::GridLayout;
+use crate::simulation::qasm_importer::ccx_decomposition;
+use crate::simulation::quantum_gate::QuantumGate;
+use crate::simulation::solovay_kitaev::approximate;
+use crate::simulation::subcircuits::{expanded_steps, INVERSE_SUFFIX};
+use num::Complex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::f64::consts::PI;
+
+pub const DEFAULT_TOLERANCE: f64 = 1e-2;
+const EPSILON: f64 = 1e-9;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Basis {
+    // H, T, T† and CNOT, which only approximate most rotations
+    #[default]
+    HTCnot,
+    // Rz, SX and CNOT, which write every gate exactly
+    RzSxCx,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TranspiledCircuit {
+    pub grid: UnparsedCircuit,
+    // How often every gate is used, with controlled gates named like "CX", "CCX" or "CRz"
+    pub counts_before: BTreeMap<String, usize>,
+    // With the CNOTs of the decomposition, before those between rows that are not neighbours are made of several
+    pub counts_after: BTreeMap<String, usize>,
+    // The CNOTs the grid needs on top of those, because it only has controls directly above their target
+    pub routing_cx: usize,
+    // The largest distance between single-qubit gates and the gates that approximate them, 0 when they are all exact
+    pub approximation_error: f64,
+}
+
+// A gate with any number of controls on one target, the form every gate of the grid is brought into first
+#[derive(Debug, Clone)]
+struct ControlledGate {
+    controls: Vec<usize>,
+    target: usize,
+    gate: QuantumGate,
+    step: usize,
+}
+
+#[derive(Debug, Clone)]
+enum BasisGate {
+    // A single-qubit gate that is yet to be written in the basis
+    Single { qubit: usize, gate: QuantumGate, step: usize },
+    Cnot { control: usize, target: usize, step: usize },
+}
+
+// The circuit written in the gates of the basis. Custom gates on more than one qubit have no decomposition, sub-circuits
+// are transpiled step by step. The grid only has controls directly above their target, so CNOTs between other qubits are
+// made of CNOTs between neighbours, with H gates around them where the control is below the target
+pub fn transpile_circuit(grid: &UnparsedCircuit, basis: Basis, tolerance: f64) -> Result<TranspiledCircuit, Vec<QuantumCircuitError>> {
+    validate_grid_input(grid)?;
+    // Sub-circuits are taken apart gate by gate, which is only done for as many steps as they would be simulated with
+    expanded_steps(grid).map_err(|error| vec![error])?;
+    let counts_before = grid_counts(grid).map_err(|error| vec![error])?;
+    let no_of_qubits = grid.circuit.len();
+
+    let logical_gates: Vec<BasisGate> = controlled_gates(grid).map_err(|error| vec![error])?.iter().flat_map(decompose).collect();
+    let logical_cnots = logical_gates.iter().filter(|gate| matches!(gate, BasisGate::Cnot { .. })).count();
+    let basis_gates: Vec<BasisGate> = logical_gates.into_iter().flat_map(neighbour_cnots).collect();
+
+    let mut layout = GridLayout::default();
+    layout.add_qubits(no_of_qubits);
+    let mut counts_after = BTreeMap::new();
+    let mut cnots = 0;
+    let mut approximation_error: f64 = 0.0;
+    let mut errors = Vec::new();
+    for basis_gate in fuse(basis_gates, no_of_qubits) {
+        match basis_gate {
+            BasisGate::Cnot { control, target, .. } => {
+                cnots += 1;
+                layout.controlled("X".to_string(), control, target);
+            }
+            BasisGate::Single { qubit, gate, step } => {
+                let (tokens, distance) = basis.tokens(&gate, tolerance);
+                if distance > tolerance {
+                    errors.push(QuantumCircuitError::ApproximationFailed { qubit, step, tolerance, distance });
+                }
+                approximation_error = approximation_error.max(distance);
+                for token in tokens {
+                    *counts_after.entry(gate_name(&token)).or_insert(0) += 1;
+                    layout.single(token, qubit);
+                }
+            }
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    if logical_cnots > 0 {
+        counts_after.insert("CX".to_string(), logical_cnots);
+    }
+    let routing_cx = cnots - logical_cnots;
+
+    Ok(TranspiledCircuit { grid: layout.into_grid(), counts_before, counts_after, routing_cx, approximation_error })
+}
+
+impl Basis {
+    // The tokens of a single-qubit gate up to its global phase, with how far they are from it
+    fn tokens(self, gate: &QuantumGate, tolerance: f64) -> (Vec<String>, f64) {
+        match self {
+            Basis::HTCnot => {
+                let approximation = approximate(gate, tolerance);
+                (approximation.word.iter().map(|letter| letter.token().to_string()).collect(), approximation.distance)
+            }
+            Basis::RzSxCx => (rz_sx_tokens(gate), 0.0),
+        }
+    }
+}
+
+// Rz(β)·Ry(γ)·Rz(δ) is Rz(β+π)·SX·Rz(γ+π)·SX·Rz(δ) up to its phase, and needs fewer gates when γ is 0 or π/2
+fn rz_sx_tokens(gate: &QuantumGate) -> Vec<String> {
+    let (_, beta, gamma, delta) = zyz_angles(gate);
+    // Rz turns by multiples of 2π only change the phase
+    let rz = |angle: f64| {
+        let angle = PI - (PI - angle).rem_euclid(2.0 * PI);
+        (angle.abs() > EPSILON).then(|| format!("Rz({})", format_angle(angle)))
+    };
+    let sx = || Some("SX".to_string());
+    let tokens = if gamma < EPSILON {
+        vec![rz(beta + delta)]
+    } else if (gamma - PI / 2.0).abs() < EPSILON {
+        vec![rz(delta - PI / 2.0), sx(), rz(beta + PI / 2.0)]
+    } else {
+        vec![rz(delta), sx(), rz(gamma + PI), sx(), rz(beta + PI)]
+    };
+    tokens.into_iter().flatten().collect()
+}
+
+// The angles of a single-qubit gate as e^(iα)·Rz(β)·Ry(γ)·Rz(δ), with γ between 0 and π
+fn zyz_angles(gate: &QuantumGate) -> (f64, f64, f64, f64) {
+    let matrix = &gate.matrix;
+    let determinant = matrix[[0, 0]] * matrix[[1, 1]] - matrix[[0, 1]] * matrix[[1, 0]];
+    let alpha = determinant.arg() / 2.0;
+    // Without the phase the matrix is [[e^(-i(β+δ)/2)·cos(γ/2), ...], [e^(i(β-δ)/2)·sin(γ/2), ...]]
+    let phase = Complex::from_polar(1.0, -alpha);
+    let (upper, lower) = (matrix[[0, 0]] * phase, matrix[[1, 0]] * phase);
+    let gamma = 2.0 * lower.norm().atan2(upper.norm());
+    let (sum, difference) = (-2.0 * upper.arg(), 2.0 * lower.arg());
+    (alpha, (sum + difference) / 2.0, gamma, (sum - difference) / 2.0)
+}
+
+// Every gate of the grid step by step as controlled gates
+fn controlled_gates(grid: &UnparsedCircuit) -> Result<Vec<ControlledGate>, QuantumCircuitError> {
+    let mut gates = Vec::new();
+    for step in 0..grid.circuit[0].len() {
+        for operation in operations_in_step(grid, step)? {
+            match operation {
+                GridOperation::Single { token, .. } if token == "I" => {}
+                GridOperation::Single { gate, qubit, .. } => gates.push(ControlledGate { controls: vec![], target: qubit, gate, step }),
+                GridOperation::Controlled { token, gate, control, target } => {
+                    for mut controlled in block_gates(grid, &token, gate, target, step)? {
+                        controlled.controls.splice(0..0, control..target);
+                        gates.push(controlled);
+                    }
+                }
+                GridOperation::Swap { upper } => gates.extend(swap(upper, upper + 1, step)),
+                GridOperation::Custom { token, gate, upper } => gates.extend(block_gates(grid, &token, gate, upper, step)?),
+            }
+        }
+    }
+    Ok(gates)
+}
+
+// The gates of a gate that starts at the upper row: a single-qubit gate is one, and a sub-circuit on more rows
+// is the gates of its grid
+fn block_gates(grid: &UnparsedCircuit, token: &str, gate: QuantumGate, upper: usize, step: usize) -> Result<Vec<ControlledGate>, QuantumCircuitError> {
+    if gate.size == 1 {
+        return Ok(vec![ControlledGate { controls: vec![], target: upper, gate, step }]);
+    }
+    let unsupported = || QuantumCircuitError::UnsupportedGate { token: token.to_string(), qubit: upper, step };
+    let subcircuit = subcircuit_grid(grid, token).ok_or_else(unsupported)?;
+    let gates = controlled_gates(&subcircuit).map_err(|_| unsupported())?;
+    Ok(gates
+        .into_iter()
+        .map(|gate| ControlledGate { controls: gate.controls.iter().map(|control| control + upper).collect(), target: gate.target + upper, gate: gate.gate, step })
+        .collect())
+}
+
+// The grid of a sub-circuit, or of the inverse of one, which can use every definition of the grid it is placed in
+fn subcircuit_grid(grid: &UnparsedCircuit, token: &str) -> Option<UnparsedCircuit> {
+    match token.strip_suffix(INVERSE_SUFFIX) {
+        Some(name) => invert_circuit(&subcircuit_grid(grid, name)?, None, None).ok(),
+        None => grid.subcircuits.get(token).map(|subcircuit| UnparsedCircuit { circuit: subcircuit.circuit.clone(), ..grid.clone() }),
+    }
+}
+
+// A swap is three CNOTs, the middle one upside down
+fn swap(first: usize, second: usize, step: usize) -> Vec<ControlledGate> {
+    [(first, second), (second, first), (first, second)]
+        .into_iter()
+        .map(|(control, target)| ControlledGate { controls: vec![control], target, gate: QuantumGate::x_gate(), step })
+        .collect()
+}
+
+// CNOTs and single-qubit gates that make the controlled gate exactly, including its phase
+fn decompose(controlled: &ControlledGate) -> Vec<BasisGate> {
+    let ControlledGate { controls, target, gate, step } = controlled;
+    let (target, step) = (*target, *step);
+    match controls.as_slice() {
+        [] => vec![BasisGate::Single { qubit: target, gate: gate.clone(), step }],
+        [control] if is_x(gate) => vec![BasisGate::Cnot { control: *control, target, step }],
+        [control] => {
+            // U = e^(iα)·A·X·B·X·C with A·B·C = I, so the phase is all that is left on the control when it is 0
+            let (alpha, beta, gamma, delta) = zyz_angles(gate);
+            let a = QuantumGate::ry_gate(gamma / 2.0).followed_by(&QuantumGate::rz_gate(beta));
+            let b = QuantumGate::rz_gate(-(delta + beta) / 2.0).followed_by(&QuantumGate::ry_gate(-gamma / 2.0));
+            let c = QuantumGate::rz_gate((delta - beta) / 2.0);
+            vec![
+                BasisGate::Single { qubit: target, gate: c, step },
+                BasisGate::Cnot { control: *control, target, step },
+                BasisGate::Single { qubit: target, gate: b, step },
+                BasisGate::Cnot { control: *control, target, step },
+                BasisGate::Single { qubit: target, gate: a, step },
+                BasisGate::Single { qubit: *control, gate: QuantumGate::phase_gate(alpha), step },
+            ]
+        }
+        [first, second] if is_x(gate) => toffoli(*first, *second, target, step),
+        [others @ .., last] => {
+            // With V² = U, the gate is C(V) from the last control, C(X) from the others onto it, C(V†) from it,
+            // C(X) again and C(V) from the others, which only makes U when every control is 1
+            let root = square_root(gate);
+            let gate = |controls: Vec<usize>, target: usize, gate: QuantumGate| ControlledGate { controls, target, gate, step };
+            [
+                gate(vec![*last], target, root.clone()),
+                gate(others.to_vec(), *last, QuantumGate::x_gate()),
+                gate(vec![*last], target, root.adjoint()),
+                gate(others.to_vec(), *last, QuantumGate::x_gate()),
+                gate(others.to_vec(), target, root),
+            ]
+            .iter()
+            .flat_map(decompose)
+            .collect()
+        }
+    }
+}
+
+// The Toffoli gate of qelib1, with 6 CNOTs and 7 T and T† gates
+fn toffoli(a: usize, b: usize, c: usize, step: usize) -> Vec<BasisGate> {
+    ccx_decomposition(a, b, c)
+        .into_iter()
+        .map(|(gate, qubits)| match (gate, &qubits[..]) {
+            ("cx", &[control, target]) => BasisGate::Cnot { control, target, step },
+            ("h", &[qubit]) => BasisGate::Single { qubit, gate: QuantumGate::h_gate(), step },
+            ("t", &[qubit]) => BasisGate::Single { qubit, gate: QuantumGate::t_gate(), step },
+            ("tdg", &[qubit]) => BasisGate::Single { qubit, gate: QuantumGate::tdg_gate(), step },
+            _ => unreachable!("ccx only decomposes into h, t, tdg and cx"),
+        })
+        .collect()
+}
+
+// A CNOT as CNOTs from a row onto the row underneath it, the only ones the grid has.
+// H gates on both qubits turn a CNOT upside down, and one between rows further apart takes 4·(distance - 1) CNOTs:
+// the parities of the rows from the control down are carried to the target and taken off the rows in between again
+fn neighbour_cnots(gate: BasisGate) -> Vec<BasisGate> {
+    let BasisGate::Cnot { control, target, step } = gate else {
+        return vec![gate];
+    };
+    if control > target {
+        let h = |qubit: usize| BasisGate::Single { qubit, gate: QuantumGate::h_gate(), step };
+        return [vec![h(control), h(target)], neighbour_cnots(BasisGate::Cnot { control: target, target: control, step }), vec![h(control), h(target)]].concat();
+    }
+    let cnot = |upper: usize| BasisGate::Cnot { control: upper, target: upper + 1, step };
+    if target == control + 1 {
+        return vec![cnot(control)];
+    }
+    let half = |last: usize| (control + 1..last).rev().chain([control]).chain(control + 1..last).map(cnot).collect::<Vec<BasisGate>>();
+    [half(target), half(target - 1)].concat()
+}
+
+// Multiply the single-qubit gates on each wire together until a CNOT uses the wire
+fn fuse(gates: Vec<BasisGate>, no_of_qubits: usize) -> Vec<BasisGate> {
+    let mut pending: Vec<Option<(QuantumGate, usize)>> = vec![None; no_of_qubits];
+    let flush = |pending: &mut Vec<Option<(QuantumGate, usize)>>, qubit: usize| pending[qubit].take().map(|(gate, step)| BasisGate::Single { qubit, gate, step });
+
+    let mut fused = Vec::new();
+    for gate in gates {
+        match gate {
+            BasisGate::Single { qubit, gate, step } => {
+                let gate = match pending[qubit].take() {
+                    Some((earlier, _)) => earlier.followed_by(&gate),
+                    None => gate,
+                };
+                pending[qubit] = Some((gate, step));
+            }
+            BasisGate::Cnot { control, target, step } => {
+                fused.extend(flush(&mut pending, control));
+                fused.extend(flush(&mut pending, target));
+                fused.push(BasisGate::Cnot { control, target, step });
+            }
+        }
+    }
+    fused.extend((0..no_of_qubits).filter_map(|qubit| flush(&mut pending, qubit)));
+    fused
+}
+
+fn is_x(gate: &QuantumGate) -> bool {
+    gate.matrix.iter().zip(QuantumGate::x_gate().matrix.iter()).all(|(a, b)| (a - b).norm() < EPSILON)
+}
+
+// A gate V with V² = U, from (U + √det·I)² = U·(tr U + 2√det) for 2x2 matrices
+fn square_root(gate: &QuantumGate) -> QuantumGate {
+    let matrix = &gate.matrix;
+    let determinant = matrix[[0, 0]] * matrix[[1, 1]] - matrix[[0, 1]] * matrix[[1, 0]];
+    let trace = matrix[[0, 0]] + matrix[[1, 1]];
+    // Either square root of the determinant works, unless it makes the divisor 0
+    let root = determinant.sqrt();
+    let root = if (trace + root * 2.0).norm() < EPSILON { -root } else { root };
+    let divisor = (trace + root * 2.0).sqrt();
+    let identity = QuantumGate::i_gate().matrix;
+    QuantumGate { matrix: (matrix + &(identity * root)) / divisor, size: 1 }
+}
+
+// How often every gate of the grid is used
+fn grid_counts(grid: &UnparsedCircuit) -> Result<BTreeMap<String, usize>, QuantumCircuitError> {
+    let mut counts = BTreeMap::new();
+    for step in 0..grid.circuit[0].len() {
+        for operation in operations_in_step(grid, step)? {
+            let name = match operation {
+                GridOperation::Single { token, .. } if token == "I" => continue,
+                GridOperation::Single { token, .. } | GridOperation::Custom { token, .. } => gate_name(&token),
+                GridOperation::Controlled { token, control, target, .. } => format!("{}{}", "C".repeat(target - control), gate_name(&token)),
+                GridOperation::Swap { .. } => "Swap".to_string(),
+            };
+            *counts.entry(name).or_insert(0) += 1;
+        }
+    }
+    Ok(counts)
+}
+
+// Tokens with angles are counted together, so "Rz(pi/4)" is an "Rz"
+fn gate_name(token: &str) -> String {
+    match parse_parameterised_token(token) {
+        Some((name, _)) => name.to_string(),
+        None => token.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::subcircuits::define_subcircuits;
+    use crate::simulation::test_support::assert_same_final_state;
+
+    fn counts(pairs: &[(&str, usize)]) -> BTreeMap<String, usize> {
+        pairs.iter().map(|(name, count)| (name.to_string(), *count)).collect()
+    }
+
+    #[test]
+    fn test_toffoli_into_clifford_t() {
+        let grid = UnparsedCircuit::from(vec![vec!["H", "C_down"], vec!["H", "C_down"], vec!["I", "X"]]);
+
+        let transpiled = transpile_circuit(&grid, Basis::HTCnot, DEFAULT_TOLERANCE).unwrap();
+
+        assert_eq!(transpiled.counts_before, counts(&[("CCX", 1), ("H", 2)]));
+        assert_eq!(transpiled.counts_after, counts(&[("CX", 6), ("H", 4), ("T", 4), ("T†", 3)]));
+        // The two CNOTs from the top row onto the bottom one take four CNOTs between neighbours each
+        assert_eq!(transpiled.routing_cx, 6);
+        assert!(transpiled.grid.circuit.iter().flatten().all(|token| ["I", "H", "T", "T†", "C_down", "X"].contains(&token.as_str())));
+        assert_eq!(transpiled.approximation_error, 0.0);
+        assert_same_final_state(&grid, &transpiled.grid, 1e-9);
+    }
+
+    #[test]
+    fn test_every_gate_into_rz_sx() {
+        let mut grid = UnparsedCircuit::from(vec![
+            vec!["H", "C_down", "Swap", "C_down", "U(0.3,1.2,-0.5)", "C_down", "Y"],
+            vec!["Ry(pi/3)", "C_down", "Swap", "Rz(0.7)", "SX", "Bell", "S†"],
+            vec!["T", "Rx(1.1)", "H", "I", "C_down", "Bell", "Bell†"],
+            vec!["I", "X", "Z", "I", "U(1,2,3)", "Z", "Bell†"],
+        ]);
+        grid.subcircuits = define_subcircuits(
+            BTreeMap::from([("Bell".to_string(), vec![vec!["H".to_string(), "C_down".to_string()], vec!["T".to_string(), "X".to_string()]])]),
+            &grid.custom_gates,
+        )
+        .unwrap();
+
+        let transpiled = transpile_circuit(&grid, Basis::RzSxCx, DEFAULT_TOLERANCE).unwrap();
+
+        assert!(transpiled.counts_after.keys().all(|name| ["CX", "Rz", "SX"].contains(&name.as_str())), "{:?}", transpiled.counts_after);
+        assert!(transpiled.grid.circuit.iter().flatten().all(|token| token == "I" || token == "SX" || token == "X" || token == "C_down" || token.starts_with("Rz(")));
+        assert_eq!(transpiled.approximation_error, 0.0);
+        assert_same_final_state(&grid, &transpiled.grid, 1e-9);
+    }
+
+    #[test]
+    fn test_rotations_are_approximated() {
+        let grid = UnparsedCircuit::from(vec![vec!["Rz(0.3)", "C_down"], vec!["H", "Ry(1)"]]);
+
+        let transpiled = transpile_circuit(&grid, Basis::HTCnot, 2e-2).unwrap();
+
+        assert!(transpiled.approximation_error > 0.0 && transpiled.approximation_error <= 2e-2);
+        assert!(transpiled.counts_after.keys().all(|name| ["CX", "H", "T", "T†"].contains(&name.as_str())));
+        assert_same_final_state(&grid, &transpiled.grid, 1e-2);
+
+        let too_close = transpile_circuit(&grid, Basis::HTCnot, 1e-6).unwrap_err();
+        assert_eq!(too_close[0].code(), "approximation_failed");
+        assert_eq!(too_close[0].qubit(), Some(0));
+    }
+
+    #[test]
+    fn test_custom_gates_on_several_qubits_are_unsupported() {
+        let mut grid = UnparsedCircuit::from(vec![vec!["H", "Oracle"], vec!["I", "Oracle"]]);
+        grid.custom_gates.insert("Oracle".to_string(), QuantumGate::cz_gate());
+
+        assert_eq!(
+            transpile_circuit(&grid, Basis::RzSxCx, DEFAULT_TOLERANCE),
+            Err(vec![QuantumCircuitError::UnsupportedGate { token: "Oracle".to_string(), qubit: 0, step: 1 }])
+        );
+    }
+}
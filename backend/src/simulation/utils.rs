@@ -1,7 +1,35 @@
 use crate::simulation::quantum_state::QuantumState;
-use crate::ComplexContainer;
 use ndarray::Array2;
 use num::Complex;
+use serde::{Deserialize, Serialize};
+
+// Which qubit is the most significant bit of the index of a basis state
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BitOrder {
+    // Qubit 0 is the least significant bit, |q_n-1 ... q_1 q_0⟩, as in Qiskit and Quirk
+    #[default]
+    LittleEndian,
+    // Qubit 0 is the most significant bit, |q_0 q_1 ... q_n-1⟩, as in most textbooks and the simulator itself
+    BigEndian,
+}
+
+impl BitOrder {
+    // Reorder a state from the simulator, where qubit 0 is the most significant bit
+    pub fn reorder(self, state: &QuantumState) -> QuantumState {
+        match self {
+            BitOrder::LittleEndian => to_little_endian(state),
+            BitOrder::BigEndian => state.clone(),
+        }
+    }
+}
+
+// A complex number as an object with named parts, {"re": 0.5, "im": 0.0}
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct ComplexContainer {
+    pub re: f64,
+    pub im: f64,
+}
 
 pub fn format_to_complex_container(state: &QuantumState) -> Vec<ComplexContainer> {
     let mut container_vec = Vec::new();